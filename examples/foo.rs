@@ -20,7 +20,7 @@ async fn main() {
     };
 
     let config = FuzzerConfig::builder()
-        .input_format(InputFormat::Text)
+        .input_format(InputFormat::Unicode)
         .fuzz_mode(FuzzMode::Mutation)
         .timeout(Duration::from_secs(1))
         .max_iterations(1000)
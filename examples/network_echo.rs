@@ -0,0 +1,60 @@
+use fuzzer::network::{Framing, NetworkTarget, ServerProcess};
+use fuzzer::utils::{FuzzMode, InputFormat};
+use fuzzer::{Fuzzer, FuzzerConfig};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::time::Duration;
+
+const ADDRESS: &str = "127.0.0.1:7878";
+
+/// A line-based echo server that goes down on a line starting with `!` followed by a digit.
+fn serve() {
+    let listener = TcpListener::bind(ADDRESS).expect("bind");
+    for stream in listener.incoming().flatten() {
+        let mut writer = stream.try_clone().expect("clone");
+        for line in BufReader::new(stream).split(b'\n').map_while(Result::ok) {
+            if line.first() == Some(&b'!') && line.get(1).is_some_and(u8::is_ascii_digit) {
+                eprintln!("unexpected command {:?}", String::from_utf8_lossy(&line));
+                std::process::abort();
+            }
+            let _ = writer.write_all(&line);
+            let _ = writer.write_all(b"\n");
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    if std::env::args().nth(1).as_deref() == Some("serve") {
+        serve();
+        return;
+    }
+
+    let exe = std::env::current_exe().expect("current executable");
+    let server = ServerProcess::new(exe.to_str().expect("utf-8 path"))
+        .arg("serve")
+        .startup_delay(Duration::from_millis(200));
+    let target = NetworkTarget::tcp(ADDRESS)
+        .framing(Framing::Delimiter(b"\n".to_vec()))
+        .response_timeout(Duration::from_millis(10))
+        .server(server)
+        .into_target("echo");
+
+    let config = FuzzerConfig::builder()
+        .input_format(InputFormat::Text)
+        .fuzz_mode(FuzzMode::Generation)
+        .timeout(Duration::from_secs(2))
+        .max_iterations(5000)
+        .seed(7)
+        .stop_on_first_crash(true)
+        .stats_interval(500)
+        .max_input_size(32)
+        .build();
+
+    let mut fuzzer = Fuzzer::new(config);
+    fuzzer.add_target(target);
+
+    if let Err(e) = fuzzer.run().await {
+        eprintln!("Fuzzer encountered an error: {}", e);
+    }
+}
@@ -1,18 +1,620 @@
+use serde::{Deserialize, Serialize};
 use std::alloc::{GlobalAlloc, Layout, System};
+use std::backtrace::Backtrace;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::{btree_map::Entry, BTreeMap};
+use std::fmt;
+use std::future::{poll_fn, Future};
+use std::pin::pin;
+use std::ptr;
+use std::sync::atomic::{AtomicIsize, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::thread::LocalKey;
 
+static CURRENT_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The first allocation refused under each [`LimitGuard`], by guard id.
+static REFUSED: Mutex<BTreeMap<u64, LimitExceeded>> = Mutex::new(BTreeMap::new());
+static NEXT_GUARD: AtomicU64 = AtomicU64::new(0);
+static LIMIT_HANDLER: OnceLock<LimitHandler> = OnceLock::new();
+
+/// Live allocations made under a [`LeakTracker`], by address.
+static TRACKED: Mutex<BTreeMap<usize, TrackedAllocation>> = Mutex::new(BTreeMap::new());
+/// Number of live [`LeakTracker`]s; freed allocations are only looked up while it is not zero.
+static TRACKERS: AtomicUsize = AtomicUsize::new(0);
+static NEXT_TRACKER: AtomicU64 = AtomicU64::new(0);
+/// Allocations tracked so far, for sampling backtraces.
+static TRACKED_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Set while the allocator's own bookkeeping or the limit handler runs, so that their
+    /// allocations are neither checked nor tracked.
+    static BYPASS: Cell<bool> = const { Cell::new(false) };
+    /// The tracker that allocations on this thread are attributed to.
+    static TRACKING: Cell<Option<Tracking>> = const { Cell::new(None) };
+    /// The limits of the guard that allocations on this thread are checked against.
+    static LIMITS: Cell<Option<Limits>> = const { Cell::new(None) };
+    /// The counters of the scope that allocations on this thread are counted in.
+    static SCOPE: Cell<ScopeRef> = const { Cell::new(ScopeRef(ptr::null())) };
+}
+
+/// Heap usage counted by an [`AllocationScope`]. Frees of memory allocated before the scope
+/// can take `current` below zero.
+#[derive(Debug, Default)]
+struct ScopeCounters {
+    current: AtomicIsize,
+    peak: AtomicIsize,
+    allocations: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ScopeRef(*const ScopeCounters);
+
+// SAFETY: a `ScopeRef` is only set in `SCOPE` while the scope owning the counters is borrowed
+// by `AllocationScope::run` or `AllocationScope::track`, or while a `ThreadContext` holding
+// them runs, and the counters are atomics.
+unsafe impl Send for ScopeRef {}
+unsafe impl Sync for ScopeRef {}
+
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    guard: u64,
+    max_allocation: usize,
+    max_heap: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tracking {
+    tracker: u64,
+    backtrace_interval: usize,
+}
+
+struct TrackedAllocation {
+    tracker: u64,
+    size: usize,
+    backtrace: Option<Backtrace>,
+}
+
+/// Forwards to the system allocator and counts heap usage. It is installed as the global
+/// allocator only with the `tracking-allocator` feature, so binaries that bring their own
+/// allocator can leave it out.
 pub struct CustomAllocator;
 
 unsafe impl GlobalAlloc for CustomAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        //todo
-        System.alloc(layout)
+        if !check_limits(layout.size(), 0) {
+            return ptr::null_mut();
+        }
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            record_allocation(layout.size());
+            count_in_scope(layout.size() as isize, 1);
+            track(ptr, layout.size(), None);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if !check_limits(layout.size(), 0) {
+            return ptr::null_mut();
+        }
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record_allocation(layout.size());
+            count_in_scope(layout.size() as isize, 1);
+            track(ptr, layout.size(), None);
+        }
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        //todo
-        System.dealloc(ptr, layout)
+        // Untracked before the block is freed, so that an allocation reusing the address on
+        // another thread keeps its entry.
+        if let Some(tracked) = untrack(ptr) {
+            bypass(|| drop(tracked));
+        }
+        System.dealloc(ptr, layout);
+        CURRENT_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        count_in_scope(-(layout.size() as isize), 0);
     }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size > layout.size() && !check_limits(new_size, layout.size()) {
+            return ptr::null_mut();
+        }
+        let tracked = untrack(ptr);
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if new_ptr.is_null() {
+            if let Some(tracked) = tracked {
+                track(ptr, tracked.size, Some(tracked));
+            }
+        } else {
+            CURRENT_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+            record_allocation(new_size);
+            count_in_scope(new_size as isize - layout.size() as isize, 1);
+            // Growing a buffer that predates the tracker is not a new allocation.
+            if let Some(tracked) = tracked {
+                track(new_ptr, new_size, Some(tracked));
+            }
+        }
+        new_ptr
+    }
+}
+
+fn bypass<R>(f: impl FnOnce() -> R) -> R {
+    let previous = BYPASS.replace(true);
+    let result = f();
+    BYPASS.set(previous);
+    result
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
+/// Runs `f` with `key` set to `value` on this thread, restoring it afterward even if `f`
+/// panics.
+fn within<T: Copy + 'static, R>(
+    key: &'static LocalKey<Cell<T>>,
+    value: T,
+    f: impl FnOnce() -> R,
+) -> R {
+    struct Restore<T: Copy + 'static> {
+        key: &'static LocalKey<Cell<T>>,
+        previous: T,
+    }
+
+    impl<T: Copy + 'static> Drop for Restore<T> {
+        fn drop(&mut self) {
+            self.key.set(self.previous);
+        }
+    }
+
+    let _restore = Restore {
+        key,
+        previous: key.replace(value),
+    };
+    f()
+}
+
+/// Polls `future` with `key` set to `value`, on whichever thread polls it.
+async fn poll_within<T: Copy + 'static, F: Future>(
+    key: &'static LocalKey<Cell<T>>,
+    value: T,
+    future: F,
+) -> F::Output {
+    let mut future = pin!(future);
+    poll_fn(|cx| within(key, value, || future.as_mut().poll(cx))).await
+}
+
+/// Records an allocation made under a tracker. `moved` is the entry of a reallocated block,
+/// which is kept under its original tracker whichever thread moves it.
+fn track(ptr: *mut u8, size: usize, moved: Option<TrackedAllocation>) {
+    if BYPASS.get() {
+        return;
+    }
+    let entry = match moved {
+        Some(tracked) => TrackedAllocation { size, ..tracked },
+        None => {
+            let Some(tracking) = TRACKING.get() else {
+                return;
+            };
+            let count = TRACKED_COUNT.fetch_add(1, Ordering::Relaxed);
+            let sample =
+                tracking.backtrace_interval > 0 && count % tracking.backtrace_interval == 0;
+            TrackedAllocation {
+                tracker: tracking.tracker,
+                size,
+                backtrace: sample.then(|| bypass(Backtrace::force_capture)),
+            }
+        }
+    };
+    bypass(|| lock(&TRACKED).insert(ptr as usize, entry));
+}
+
+fn untrack(ptr: *mut u8) -> Option<TrackedAllocation> {
+    if TRACKERS.load(Ordering::Relaxed) == 0 || BYPASS.get() {
+        return None;
+    }
+    bypass(|| lock(&TRACKED).remove(&(ptr as usize)))
+}
+
+fn record_allocation(size: usize) {
+    let current = CURRENT_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_BYTES.fetch_max(current, Ordering::Relaxed);
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Adds an allocation or free made on this thread to the scope it runs under.
+fn count_in_scope(bytes: isize, allocations: usize) {
+    let scope = SCOPE.get();
+    if scope.0.is_null() || BYPASS.get() {
+        return;
+    }
+    // SAFETY: see `ScopeRef`.
+    let counters = unsafe { &*scope.0 };
+    let current = counters.current.fetch_add(bytes, Ordering::Relaxed) + bytes;
+    counters.peak.fetch_max(current, Ordering::Relaxed);
+    counters
+        .allocations
+        .fetch_add(allocations, Ordering::Relaxed);
+}
+
+/// Returns false if an allocation of `size` bytes, replacing `released` bytes, would exceed
+/// the limits of the guard this thread runs under. The first allocation a guard refuses is
+/// recorded against it and passed to the limit handler.
+fn check_limits(size: usize, released: usize) -> bool {
+    let Some(limits) = LIMITS.get() else {
+        return true;
+    };
+    if BYPASS.get() {
+        return true;
+    }
+    let heap_bytes = current_bytes().saturating_sub(released);
+    let (limit, limit_bytes) = if size > limits.max_allocation {
+        (MemoryLimit::Allocation, limits.max_allocation)
+    } else if heap_bytes.saturating_add(size) > limits.max_heap {
+        (MemoryLimit::Heap, limits.max_heap)
+    } else {
+        return true;
+    };
+    let exceeded = LimitExceeded {
+        limit,
+        limit_bytes,
+        requested_bytes: size,
+        heap_bytes,
+    };
+    let first = bypass(|| match lock(&REFUSED).entry(limits.guard) {
+        Entry::Vacant(entry) => {
+            entry.insert(exceeded);
+            true
+        }
+        Entry::Occupied(_) => false,
+    });
+    if first {
+        if let Some(handler) = LIMIT_HANDLER.get() {
+            bypass(|| handler(limits.guard, &exceeded));
+        }
+    }
+    false
+}
+
+#[cfg(feature = "tracking-allocator")]
 #[global_allocator]
 static GLOBAL: CustomAllocator = CustomAllocator;
+
+/// True if the tracking allocator is installed and the counters below are live.
+pub const fn is_enabled() -> bool {
+    cfg!(feature = "tracking-allocator")
+}
+
+/// Bytes currently allocated on the heap.
+pub fn current_bytes() -> usize {
+    CURRENT_BYTES.load(Ordering::Relaxed)
+}
+
+/// Highest heap usage since the process started.
+pub fn peak_bytes() -> usize {
+    PEAK_BYTES.load(Ordering::Relaxed)
+}
+
+/// Allocations made since the process started, counting reallocations.
+pub fn allocation_count() -> usize {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+/// Heap usage of the code run by an [`AllocationScope`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllocationStats {
+    /// Bytes the code allocated and had not freed when the scope finished.
+    pub current_bytes: usize,
+    /// Highest number of bytes the code held at any point.
+    pub peak_bytes: usize,
+    pub allocations: usize,
+}
+
+/// Counts the allocations and frees made by code it runs, on whichever thread runs it.
+/// Allocations made by threads or tasks that code spawns are not counted.
+#[derive(Debug)]
+pub struct AllocationScope {
+    counters: Arc<ScopeCounters>,
+}
+
+impl AllocationScope {
+    /// Starts a scope, or returns `None` if the tracking allocator is not installed.
+    pub fn start() -> Option<Self> {
+        is_enabled().then(|| AllocationScope {
+            counters: Arc::default(),
+        })
+    }
+
+    pub fn run<R>(&self, f: impl FnOnce() -> R) -> R {
+        within(&SCOPE, self.scope_ref(), f)
+    }
+
+    /// Polls `future` in the scope.
+    pub async fn track<F: Future>(&self, future: F) -> F::Output {
+        poll_within(&SCOPE, self.scope_ref(), future).await
+    }
+
+    pub fn finish(self) -> AllocationStats {
+        AllocationStats {
+            current_bytes: self.counters.current.load(Ordering::Relaxed).max(0) as usize,
+            peak_bytes: self.counters.peak.load(Ordering::Relaxed).max(0) as usize,
+            allocations: self.counters.allocations.load(Ordering::Relaxed),
+        }
+    }
+
+    fn scope_ref(&self) -> ScopeRef {
+        ScopeRef(Arc::as_ptr(&self.counters))
+    }
+}
+
+/// The tracker, limits and scope that allocations on this thread are attributed to, carried
+/// over to the thread that runs a synchronous target.
+#[derive(Debug)]
+pub struct ThreadContext {
+    tracking: Option<Tracking>,
+    limits: Option<Limits>,
+    scope: Option<Arc<ScopeCounters>>,
+}
+
+impl ThreadContext {
+    pub fn capture() -> Self {
+        let scope = SCOPE.get();
+        ThreadContext {
+            tracking: TRACKING.get(),
+            limits: LIMITS.get(),
+            // SAFETY: a scope set in `SCOPE` points into the `Arc` of a live `AllocationScope`.
+            scope: (!scope.0.is_null()).then(|| unsafe {
+                Arc::increment_strong_count(scope.0);
+                Arc::from_raw(scope.0)
+            }),
+        }
+    }
+
+    /// Runs `f` on this thread as if on the thread the context was captured on. The counters
+    /// of the scope stay alive for as long as the context, even if `f` outlives the scope.
+    pub fn run<R>(&self, f: impl FnOnce() -> R) -> R {
+        let scope = ScopeRef(self.scope.as_ref().map_or(ptr::null(), Arc::as_ptr));
+        within(&TRACKING, self.tracking, || {
+            within(&LIMITS, self.limits, || within(&SCOPE, scope, f))
+        })
+    }
+}
+
+/// Limits on the heap used while targets run, like libFuzzer's `-malloc_limit_mb` and
+/// `-rss_limit_mb`. They need the `tracking-allocator` feature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryOptions {
+    /// Largest single allocation a target may make.
+    pub max_allocation_bytes: Option<usize>,
+    /// Largest live heap of the whole process while a target runs.
+    pub max_heap_bytes: Option<usize>,
+}
+
+impl MemoryOptions {
+    pub const fn is_limited(&self) -> bool {
+        self.max_allocation_bytes.is_some() || self.max_heap_bytes.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MemoryLimit {
+    Allocation,
+    Heap,
+}
+
+/// An allocation that was refused because it would have exceeded a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LimitExceeded {
+    pub limit: MemoryLimit,
+    pub limit_bytes: usize,
+    pub requested_bytes: usize,
+    /// Live heap when the allocation was requested.
+    pub heap_bytes: usize,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.limit {
+            MemoryLimit::Allocation => write!(
+                f,
+                "allocation of {} bytes exceeds the limit of {} bytes",
+                self.requested_bytes, self.limit_bytes
+            ),
+            MemoryLimit::Heap => write!(
+                f,
+                "allocation of {} bytes with {} bytes live exceeds the heap limit of {} bytes",
+                self.requested_bytes, self.heap_bytes, self.limit_bytes
+            ),
+        }
+    }
+}
+
+/// Called from inside the allocator with the id of a guard and the first allocation it refused,
+/// before the allocation fails. Most allocations cannot fail without aborting the process, so
+/// this is the last chance to save the input. It must not panic.
+pub type LimitHandler = fn(u64, &LimitExceeded);
+
+/// Installs the limit handler. Returns false if one was already installed.
+pub fn set_limit_handler(handler: LimitHandler) -> bool {
+    LIMIT_HANDLER.set(handler).is_ok()
+}
+
+/// Refuses allocations that exceed the limits when they are made by code the guard runs, on
+/// whichever thread runs it. The heap limit applies to the live heap of the whole process.
+#[derive(Debug)]
+pub struct LimitGuard {
+    limits: Limits,
+}
+
+impl LimitGuard {
+    /// Creates a guard for `options`, or returns `None` if they set no limit or the tracking
+    /// allocator is not installed.
+    pub fn enforce(options: &MemoryOptions) -> Option<Self> {
+        if !is_enabled() || !options.is_limited() {
+            return None;
+        }
+        Some(LimitGuard {
+            limits: Limits {
+                guard: NEXT_GUARD.fetch_add(1, Ordering::Relaxed),
+                max_allocation: options.max_allocation_bytes.unwrap_or(usize::MAX),
+                max_heap: options.max_heap_bytes.unwrap_or(usize::MAX),
+            },
+        })
+    }
+
+    /// The id passed to the limit handler for allocations this guard refuses.
+    pub const fn id(&self) -> u64 {
+        self.limits.guard
+    }
+
+    pub fn run<R>(&self, f: impl FnOnce() -> R) -> R {
+        within(&LIMITS, Some(self.limits), f)
+    }
+
+    /// Polls `future` under the guard.
+    pub async fn track<F: Future>(&self, future: F) -> F::Output {
+        poll_within(&LIMITS, Some(self.limits), future).await
+    }
+
+    /// Returns the first allocation this guard refused, if any.
+    pub fn finish(self) -> Option<LimitExceeded> {
+        lock(&REFUSED).remove(&self.limits.guard)
+    }
+}
+
+impl Drop for LimitGuard {
+    fn drop(&mut self) {
+        lock(&REFUSED).remove(&self.limits.guard);
+    }
+}
+
+/// An execution that exceeded a memory limit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutOfMemory {
+    pub target: String,
+    #[serde(flatten)]
+    pub exceeded: LimitExceeded,
+    pub input: Vec<u8>,
+}
+
+/// Leak detection for targets, which needs the `tracking-allocator` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeakOptions {
+    pub detect: bool,
+    /// A backtrace is captured for one in this many allocations while confirming a leak; 0
+    /// captures none.
+    pub backtrace_interval: usize,
+    /// Backtraces kept per leak.
+    pub max_backtraces: usize,
+}
+
+impl Default for LeakOptions {
+    fn default() -> Self {
+        LeakOptions {
+            detect: false,
+            backtrace_interval: 1,
+            max_backtraces: 8,
+        }
+    }
+}
+
+/// Allocations a tracker saw that were still live when it finished.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeakReport {
+    pub leaked_bytes: usize,
+    pub leaked_allocations: usize,
+    /// Where the largest of the sampled allocations were made.
+    pub backtraces: Vec<String>,
+}
+
+impl LeakReport {
+    pub const fn is_empty(&self) -> bool {
+        self.leaked_allocations == 0
+    }
+}
+
+/// Tracks the allocations made by code it runs, on whichever thread runs it, until they are
+/// freed. Allocations made by threads or tasks that code spawns are not tracked.
+#[derive(Debug)]
+pub struct LeakTracker {
+    tracking: Tracking,
+}
+
+impl LeakTracker {
+    /// Starts a tracker, or returns `None` if the tracking allocator is not installed.
+    pub fn start(backtrace_interval: usize) -> Option<Self> {
+        if !is_enabled() {
+            return None;
+        }
+        TRACKERS.fetch_add(1, Ordering::Relaxed);
+        Some(LeakTracker {
+            tracking: Tracking {
+                tracker: NEXT_TRACKER.fetch_add(1, Ordering::Relaxed),
+                backtrace_interval,
+            },
+        })
+    }
+
+    pub fn run<R>(&self, f: impl FnOnce() -> R) -> R {
+        within(&TRACKING, Some(self.tracking), f)
+    }
+
+    /// Polls `future` under the tracker.
+    pub async fn track<F: Future>(&self, future: F) -> F::Output {
+        poll_within(&TRACKING, Some(self.tracking), future).await
+    }
+
+    /// Stops tracking and reports the allocations still live.
+    pub fn finish(self, max_backtraces: usize) -> LeakReport {
+        let mut leaked = self.take_live();
+        leaked.sort_by_key(|tracked| Reverse(tracked.size));
+        LeakReport {
+            leaked_bytes: leaked.iter().map(|tracked| tracked.size).sum(),
+            leaked_allocations: leaked.len(),
+            backtraces: leaked
+                .iter()
+                .filter_map(|tracked| {
+                    let backtrace = tracked.backtrace.as_ref()?;
+                    Some(format!("{} bytes\n{}", tracked.size, backtrace))
+                })
+                .take(max_backtraces)
+                .collect(),
+        }
+    }
+
+    fn take_live(&self) -> Vec<TrackedAllocation> {
+        bypass(|| {
+            let mut tracked = lock(&TRACKED);
+            let live: Vec<usize> = tracked
+                .iter()
+                .filter(|(_, entry)| entry.tracker == self.tracking.tracker)
+                .map(|(&address, _)| address)
+                .collect();
+            live.iter()
+                .filter_map(|address| tracked.remove(address))
+                .collect()
+        })
+    }
+}
+
+impl Drop for LeakTracker {
+    fn drop(&mut self) {
+        drop(self.take_live());
+        TRACKERS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// An execution that left allocations live, confirmed by running it again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Leak {
+    pub target: String,
+    #[serde(flatten)]
+    pub report: LeakReport,
+    pub input: Vec<u8>,
+}
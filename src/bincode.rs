@@ -0,0 +1,564 @@
+use crate::errors::FuzzerError;
+use crate::utils::{random_text, special_float};
+use rand::{prelude::StdRng, Rng};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+
+const MAX_DECODE_DEPTH: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct BincodeOptions {
+    /// Depth of randomly generated layouts, used when no layout is configured.
+    pub max_depth: usize,
+    pub max_items: usize,
+    pub max_length: usize,
+    /// Probability of an invalid value that keeps the framing intact: a bool other than 0 or 1,
+    /// an option tag other than 0 or 1, an out-of-range variant index or a non-UTF-8 string.
+    pub invalid_value_rate: f64,
+}
+
+impl Default for BincodeOptions {
+    fn default() -> Self {
+        BincodeOptions {
+            max_depth: 4,
+            max_items: 4,
+            max_length: 32,
+            invalid_value_rate: 0.05,
+        }
+    }
+}
+
+/// The shape of a value in bincode's default encoding: little-endian fixed-width integers,
+/// `u64` lengths for strings, sequences and maps, `u32` variant indices and a one-byte tag for
+/// options. Structs and tuples are both `Tuple`; an enum lists the payload of each variant.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BincodeLayout {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    F32,
+    F64,
+    Char,
+    String,
+    Bytes,
+    Option(Box<BincodeLayout>),
+    Seq(Box<BincodeLayout>),
+    Map(Box<BincodeLayout>, Box<BincodeLayout>),
+    Tuple(Vec<BincodeLayout>),
+    Enum(Vec<BincodeLayout>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BincodeValue {
+    /// A fixed-size value (bool, integer, float or char) in its encoded form.
+    Scalar(Vec<u8>),
+    Bytes(Vec<u8>),
+    Option(u8, Option<Box<BincodeValue>>),
+    Seq(Vec<BincodeValue>),
+    Map(Vec<(BincodeValue, BincodeValue)>),
+    Tuple(Vec<BincodeValue>),
+    Variant(u32, Box<BincodeValue>),
+}
+
+impl BincodeLayout {
+    pub fn load_from_file(filename: &str) -> Result<Self, FuzzerError> {
+        let file = File::open(filename).map_err(|e| {
+            FuzzerError::InputGenerationError(format!(
+                "Failed to open bincode layout {}: {}",
+                filename, e
+            ))
+        })?;
+        serde_json::from_reader(file).map_err(|e| {
+            FuzzerError::InputGenerationError(format!("Invalid bincode layout {}: {}", filename, e))
+        })
+    }
+
+    /// Encoded size of fixed-size layouts. `Char` is one to four bytes of UTF-8.
+    const fn scalar_width(&self) -> Option<usize> {
+        Some(match self {
+            BincodeLayout::Bool | BincodeLayout::U8 | BincodeLayout::I8 => 1,
+            BincodeLayout::U16 | BincodeLayout::I16 => 2,
+            BincodeLayout::U32 | BincodeLayout::I32 | BincodeLayout::F32 => 4,
+            BincodeLayout::U64 | BincodeLayout::I64 | BincodeLayout::F64 => 8,
+            BincodeLayout::U128 | BincodeLayout::I128 => 16,
+            _ => return None,
+        })
+    }
+
+    pub fn generate(&self, options: &BincodeOptions, rng: &mut StdRng) -> BincodeValue {
+        let invalid = rng.gen_bool(options.invalid_value_rate);
+        match self {
+            BincodeLayout::Bool => BincodeValue::Scalar(vec![if invalid {
+                rng.gen_range(2..=255)
+            } else {
+                rng.gen_range(0..=1)
+            }]),
+            BincodeLayout::F32 => {
+                BincodeValue::Scalar((special_float(rng) as f32).to_le_bytes().to_vec())
+            }
+            BincodeLayout::F64 => BincodeValue::Scalar(special_float(rng).to_le_bytes().to_vec()),
+            BincodeLayout::Char => {
+                let c = char::from_u32(rng.gen_range(0x20..0x3000)).unwrap_or('?');
+                let mut buf = [0; 4];
+                BincodeValue::Scalar(c.encode_utf8(&mut buf).as_bytes().to_vec())
+            }
+            BincodeLayout::String | BincodeLayout::Bytes => {
+                let len = rng.gen_range(0..=options.max_length);
+                let mut bytes = if *self == BincodeLayout::String {
+                    random_text(rng, len)
+                } else {
+                    (0..len).map(|_| rng.gen()).collect()
+                };
+                if invalid {
+                    bytes.push(0xFF);
+                }
+                BincodeValue::Bytes(bytes)
+            }
+            BincodeLayout::Option(inner) => match rng.gen_range(0..2) {
+                _ if invalid => BincodeValue::Option(rng.gen_range(2..=255), None),
+                0 => BincodeValue::Option(0, None),
+                _ => BincodeValue::Option(1, Some(Box::new(inner.generate(options, rng)))),
+            },
+            BincodeLayout::Seq(inner) => {
+                let len = rng.gen_range(0..=options.max_items);
+                BincodeValue::Seq((0..len).map(|_| inner.generate(options, rng)).collect())
+            }
+            BincodeLayout::Map(key, value) => {
+                let len = rng.gen_range(0..=options.max_items);
+                BincodeValue::Map(
+                    (0..len)
+                        .map(|_| (key.generate(options, rng), value.generate(options, rng)))
+                        .collect(),
+                )
+            }
+            BincodeLayout::Tuple(fields) => {
+                BincodeValue::Tuple(fields.iter().map(|f| f.generate(options, rng)).collect())
+            }
+            BincodeLayout::Enum(variants) if variants.is_empty() || invalid => {
+                BincodeValue::Variant(variants.len() as u32, Box::new(BincodeValue::Tuple(vec![])))
+            }
+            BincodeLayout::Enum(variants) => {
+                let index = rng.gen_range(0..variants.len());
+                BincodeValue::Variant(
+                    index as u32,
+                    Box::new(variants[index].generate(options, rng)),
+                )
+            }
+            // Integers.
+            _ => {
+                let width = self.scalar_width().unwrap_or(8);
+                BincodeValue::Scalar(interesting_or_random(width, rng))
+            }
+        }
+    }
+
+    /// Parses data encoded with this layout. Trailing bytes are ignored.
+    pub fn decode(&self, data: &[u8]) -> Option<BincodeValue> {
+        let mut pos = 0;
+        self.decode_at(data, &mut pos, 0)
+    }
+
+    fn decode_at(&self, data: &[u8], pos: &mut usize, depth: usize) -> Option<BincodeValue> {
+        if depth > MAX_DECODE_DEPTH {
+            return None;
+        }
+        Some(match self {
+            BincodeLayout::Char => {
+                let lead = *data.get(*pos)?;
+                let len = match lead {
+                    0xF0..=0xF7 => 4,
+                    0xE0..=0xEF => 3,
+                    0xC0..=0xDF => 2,
+                    _ => 1,
+                };
+                BincodeValue::Scalar(take(data, pos, len)?.to_vec())
+            }
+            BincodeLayout::String | BincodeLayout::Bytes => {
+                let len = u64::from_le_bytes(take(data, pos, 8)?.try_into().ok()?);
+                BincodeValue::Bytes(take(data, pos, usize::try_from(len).ok()?)?.to_vec())
+            }
+            BincodeLayout::Option(inner) => match take(data, pos, 1)?[0] {
+                0 => BincodeValue::Option(0, None),
+                1 => {
+                    BincodeValue::Option(1, Some(Box::new(inner.decode_at(data, pos, depth + 1)?)))
+                }
+                _ => return None,
+            },
+            BincodeLayout::Seq(inner) => {
+                let len = u64::from_le_bytes(take(data, pos, 8)?.try_into().ok()?);
+                let len = usize::try_from(len).ok()?.min(data.len() - *pos + 1);
+                let items = (0..len)
+                    .map(|_| inner.decode_at(data, pos, depth + 1))
+                    .collect::<Option<_>>()?;
+                BincodeValue::Seq(items)
+            }
+            BincodeLayout::Map(key, value) => {
+                let len = u64::from_le_bytes(take(data, pos, 8)?.try_into().ok()?);
+                let len = usize::try_from(len).ok()?.min(data.len() - *pos + 1);
+                let entries = (0..len)
+                    .map(|_| {
+                        Some((
+                            key.decode_at(data, pos, depth + 1)?,
+                            value.decode_at(data, pos, depth + 1)?,
+                        ))
+                    })
+                    .collect::<Option<_>>()?;
+                BincodeValue::Map(entries)
+            }
+            BincodeLayout::Tuple(fields) => BincodeValue::Tuple(
+                fields
+                    .iter()
+                    .map(|f| f.decode_at(data, pos, depth + 1))
+                    .collect::<Option<_>>()?,
+            ),
+            BincodeLayout::Enum(variants) => {
+                let index = u32::from_le_bytes(take(data, pos, 4)?.try_into().ok()?);
+                let variant = variants.get(index as usize)?;
+                BincodeValue::Variant(index, Box::new(variant.decode_at(data, pos, depth + 1)?))
+            }
+            _ => BincodeValue::Scalar(take(data, pos, self.scalar_width()?)?.to_vec()),
+        })
+    }
+
+    /// Mutates one value in the tree, keeping the encoding framed by this layout.
+    pub fn mutate(&self, options: &BincodeOptions, rng: &mut StdRng, value: &mut BincodeValue) {
+        let mut nodes = Vec::new();
+        collect_nodes(self, value, &mut Vec::new(), &mut nodes);
+        let (path, layout) = nodes.swap_remove(rng.gen_range(0..nodes.len()));
+        let Some(node) = node_at(value, &path) else {
+            return;
+        };
+        match (layout, node) {
+            (_, node) if rng.gen_bool(0.2) => *node = layout.generate(options, rng),
+            (BincodeLayout::Seq(inner), BincodeValue::Seq(items)) => match rng.gen_range(0..3) {
+                0 if !items.is_empty() => {
+                    items.remove(rng.gen_range(0..items.len()));
+                }
+                1 if !items.is_empty() => {
+                    let index = rng.gen_range(0..items.len());
+                    items.insert(index, items[index].clone());
+                }
+                _ => {
+                    let at = rng.gen_range(0..=items.len());
+                    items.insert(at, inner.generate(options, rng));
+                }
+            },
+            (BincodeLayout::Map(key, value), BincodeValue::Map(entries)) => {
+                if !entries.is_empty() && rng.gen_bool(0.5) {
+                    entries.remove(rng.gen_range(0..entries.len()));
+                } else {
+                    entries.push((key.generate(options, rng), value.generate(options, rng)));
+                }
+            }
+            (BincodeLayout::Enum(variants), BincodeValue::Variant(index, payload))
+                if !variants.is_empty() =>
+            {
+                *index = rng.gen_range(0..variants.len()) as u32;
+                **payload = variants[*index as usize].generate(options, rng);
+            }
+            (_, BincodeValue::Bytes(bytes)) => match rng.gen_range(0..3) {
+                0 => bytes.clear(),
+                1 => {
+                    let extra = rng.gen_range(1..=options.max_length.max(1));
+                    bytes.extend(random_text(rng, extra));
+                }
+                _ if *layout == BincodeLayout::String
+                    && !rng.gen_bool(options.invalid_value_rate) =>
+                {
+                    // Only overwrite ASCII bytes so the string stays valid UTF-8.
+                    let ascii: Vec<usize> = (0..bytes.len()).filter(|&i| bytes[i] < 0x80).collect();
+                    let byte = [0x00, b'\n', b'"', b'\\'][rng.gen_range(0..4)];
+                    match ascii.get(rng.gen_range(0..ascii.len().max(1))) {
+                        Some(&index) => bytes[index] = byte,
+                        None => bytes.push(byte),
+                    }
+                }
+                _ if !bytes.is_empty() => {
+                    let index = rng.gen_range(0..bytes.len());
+                    bytes[index] = [0x00, 0xFF, 0xC0, b'\n'][rng.gen_range(0..4)];
+                }
+                _ => bytes.push(0),
+            },
+            (BincodeLayout::Bool | BincodeLayout::Char, node) => {
+                *node = layout.generate(options, rng);
+            }
+            (_, BincodeValue::Scalar(bytes)) if layout.scalar_width().is_some() => {
+                *bytes = interesting_or_random(bytes.len(), rng);
+            }
+            (_, node) => *node = layout.generate(options, rng),
+        }
+    }
+
+    /// A random layout for generating envelopes when none is configured.
+    pub fn random(options: &BincodeOptions, rng: &mut StdRng, depth: usize) -> Self {
+        const SCALARS: [BincodeLayout; 16] = [
+            BincodeLayout::Bool,
+            BincodeLayout::U8,
+            BincodeLayout::U16,
+            BincodeLayout::U32,
+            BincodeLayout::U64,
+            BincodeLayout::U128,
+            BincodeLayout::I8,
+            BincodeLayout::I16,
+            BincodeLayout::I32,
+            BincodeLayout::I64,
+            BincodeLayout::I128,
+            BincodeLayout::F32,
+            BincodeLayout::F64,
+            BincodeLayout::Char,
+            BincodeLayout::String,
+            BincodeLayout::Bytes,
+        ];
+        if depth >= options.max_depth || rng.gen_bool(0.4) {
+            return SCALARS[rng.gen_range(0..SCALARS.len())].clone();
+        }
+        let child = |rng: &mut StdRng| Box::new(Self::random(options, rng, depth + 1));
+        match rng.gen_range(0..5) {
+            0 => BincodeLayout::Option(child(rng)),
+            1 => BincodeLayout::Seq(child(rng)),
+            2 => BincodeLayout::Map(child(rng), child(rng)),
+            3 => {
+                let len = rng.gen_range(1..=options.max_items.max(1));
+                BincodeLayout::Tuple(
+                    (0..len)
+                        .map(|_| Self::random(options, rng, depth + 1))
+                        .collect(),
+                )
+            }
+            _ => {
+                let len = rng.gen_range(1..=options.max_items.max(1));
+                BincodeLayout::Enum(
+                    (0..len)
+                        .map(|_| Self::random(options, rng, depth + 1))
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+impl BincodeValue {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            BincodeValue::Scalar(bytes) => out.extend_from_slice(bytes),
+            BincodeValue::Bytes(bytes) => {
+                out.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+                out.extend_from_slice(bytes);
+            }
+            BincodeValue::Option(tag, inner) => {
+                out.push(*tag);
+                if let Some(inner) = inner {
+                    inner.encode_into(out);
+                }
+            }
+            BincodeValue::Seq(items) => {
+                out.extend_from_slice(&(items.len() as u64).to_le_bytes());
+                for item in items {
+                    item.encode_into(out);
+                }
+            }
+            BincodeValue::Map(entries) => {
+                out.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+                for (key, value) in entries {
+                    key.encode_into(out);
+                    value.encode_into(out);
+                }
+            }
+            BincodeValue::Tuple(fields) => {
+                for field in fields {
+                    field.encode_into(out);
+                }
+            }
+            BincodeValue::Variant(index, payload) => {
+                out.extend_from_slice(&index.to_le_bytes());
+                payload.encode_into(out);
+            }
+        }
+    }
+}
+
+pub fn generate(
+    layout: Option<&BincodeLayout>,
+    options: &BincodeOptions,
+    rng: &mut StdRng,
+) -> Vec<u8> {
+    match layout {
+        Some(layout) => layout.generate(options, rng).encode(),
+        None => BincodeLayout::random(options, rng, 0)
+            .generate(options, rng)
+            .encode(),
+    }
+}
+
+/// Decodes the data with the layout, mutates one value and re-encodes it. Returns `None` if
+/// the data does not decode.
+pub fn mutate(
+    layout: &BincodeLayout,
+    options: &BincodeOptions,
+    rng: &mut StdRng,
+    data: &[u8],
+) -> Option<Vec<u8>> {
+    let mut value = layout.decode(data)?;
+    layout.mutate(options, rng, &mut value);
+    Some(value.encode())
+}
+
+/// Lists every value in the tree with its layout. Map entries are children `2 * i` (key) and
+/// `2 * i + 1` (value); an option or variant payload is child 0.
+fn collect_nodes<'a>(
+    layout: &'a BincodeLayout,
+    value: &BincodeValue,
+    prefix: &mut Vec<usize>,
+    nodes: &mut Vec<(Vec<usize>, &'a BincodeLayout)>,
+) {
+    nodes.push((prefix.clone(), layout));
+    let mut visit =
+        |index: usize, layout: &'a BincodeLayout, child: &BincodeValue, nodes: &mut Vec<_>| {
+            prefix.push(index);
+            collect_nodes(layout, child, prefix, nodes);
+            prefix.pop();
+        };
+    match (layout, value) {
+        (BincodeLayout::Option(inner), BincodeValue::Option(_, Some(child))) => {
+            visit(0, inner, child, nodes);
+        }
+        (BincodeLayout::Seq(inner), BincodeValue::Seq(items)) => {
+            for (i, item) in items.iter().enumerate() {
+                visit(i, inner, item, nodes);
+            }
+        }
+        (BincodeLayout::Map(key_layout, value_layout), BincodeValue::Map(entries)) => {
+            for (i, (key, value)) in entries.iter().enumerate() {
+                visit(2 * i, key_layout, key, nodes);
+                visit(2 * i + 1, value_layout, value, nodes);
+            }
+        }
+        (BincodeLayout::Tuple(fields), BincodeValue::Tuple(values)) => {
+            for (i, (field, value)) in fields.iter().zip(values).enumerate() {
+                visit(i, field, value, nodes);
+            }
+        }
+        (BincodeLayout::Enum(variants), BincodeValue::Variant(index, payload)) => {
+            if let Some(variant) = variants.get(*index as usize) {
+                visit(0, variant, payload, nodes);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn node_at<'a>(value: &'a mut BincodeValue, path: &[usize]) -> Option<&'a mut BincodeValue> {
+    let Some((&first, rest)) = path.split_first() else {
+        return Some(value);
+    };
+    let child = match value {
+        BincodeValue::Option(_, Some(inner)) | BincodeValue::Variant(_, inner) => inner.as_mut(),
+        BincodeValue::Seq(items) | BincodeValue::Tuple(items) => items.get_mut(first)?,
+        BincodeValue::Map(entries) => {
+            let entry = entries.get_mut(first / 2)?;
+            if first % 2 == 0 {
+                &mut entry.0
+            } else {
+                &mut entry.1
+            }
+        }
+        _ => return None,
+    };
+    node_at(child, rest)
+}
+
+fn take<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Option<&'a [u8]> {
+    let end = pos.checked_add(len)?;
+    let bytes = data.get(*pos..end)?;
+    *pos = end;
+    Some(bytes)
+}
+
+/// Little-endian bytes of a boundary value for the width, or of a random value.
+fn interesting_or_random(width: usize, rng: &mut StdRng) -> Vec<u8> {
+    let value: u128 = match rng.gen_range(0..8) {
+        0 => 0,
+        1 => 1,
+        2 => u128::MAX,
+        3 => u128::MAX >> (128 - 8 * width + 1),
+        4 => 1 << (8 * width - 1),
+        _ => rng.gen_range(0..1000),
+    };
+    value.to_le_bytes()[..width].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn layout() -> BincodeLayout {
+        BincodeLayout::Tuple(vec![
+            BincodeLayout::U32,
+            BincodeLayout::String,
+            BincodeLayout::Option(Box::new(BincodeLayout::U8)),
+            BincodeLayout::Seq(Box::new(BincodeLayout::I16)),
+            BincodeLayout::Map(Box::new(BincodeLayout::Char), Box::new(BincodeLayout::Bool)),
+            BincodeLayout::Enum(vec![BincodeLayout::Tuple(Vec::new()), BincodeLayout::F64]),
+        ])
+    }
+
+    fn options() -> BincodeOptions {
+        BincodeOptions {
+            invalid_value_rate: 0.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn generated_values_round_trip() {
+        let layout = layout();
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..200 {
+            let value = layout.generate(&options(), &mut rng);
+            assert_eq!(layout.decode(&value.encode()), Some(value));
+        }
+    }
+
+    #[test]
+    fn decodes_known_encodings() {
+        let layout = BincodeLayout::Tuple(vec![BincodeLayout::U16, BincodeLayout::String]);
+        let data = [7, 0, 2, 0, 0, 0, 0, 0, 0, 0, b'h', b'i'];
+        assert_eq!(
+            layout.decode(&data),
+            Some(BincodeValue::Tuple(vec![
+                BincodeValue::Scalar(vec![7, 0]),
+                BincodeValue::Bytes(b"hi".to_vec()),
+            ]))
+        );
+        assert_eq!(layout.decode(&data[..11]), None);
+        let option = BincodeLayout::Option(Box::new(BincodeLayout::U8));
+        assert_eq!(option.decode(&[2, 0]), None);
+    }
+
+    #[test]
+    fn mutation_keeps_the_layout() {
+        let layout = layout();
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..200 {
+            let data = generate(Some(&layout), &options(), &mut rng);
+            let mutated = mutate(&layout, &options(), &mut rng, &data).unwrap();
+            assert!(layout.decode(&mutated).is_some());
+        }
+    }
+}
@@ -1,8 +1,25 @@
 use crate::{
+    allocator::{LeakOptions, MemoryOptions},
+    bincode::{BincodeLayout, BincodeOptions},
+    cbor::CborOptions,
+    encoding::{EncodingChain, EncodingLayer},
+    http::HttpOptions,
+    json_schema::{JsonSchema, JsonSchemaOptions},
+    msgpack::MessagePackOptions,
     mutator_options::MutatorOptions,
-    utils::{FuzzMode, InputFormat},
+    ngram::NGramOptions,
+    protobuf::{ProtobufOptions, ProtobufSchema},
+    protocol::ProtocolOptions,
+    regex_gen::RegexOptions,
+    regions::RegionSource,
+    scheduler::Schedule,
+    sequence::SequenceOptions,
+    sql::SqlOptions,
+    template::BinaryTemplate,
+    utils::{FuzzMode, InputFormat, SizeDistribution, TextAlphabet},
+    xml::XmlOptions,
 };
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 #[derive(Debug, Clone)]
 pub struct FuzzerConfig {
@@ -16,6 +33,8 @@ pub struct FuzzerConfig {
     pub stats_interval: usize,
     pub max_input_size: usize,
     pub min_input_size: usize,
+    pub size_distribution: SizeDistribution,
+    pub text_alphabet: TextAlphabet,
     pub enable_logging: bool,
     pub log_file: Option<String>,
     pub save_crashes: bool,
@@ -33,6 +52,27 @@ pub struct FuzzerConfig {
     pub corpus_sampling_rate: f64,
     pub sanitizer_enabled: bool,
     pub sanitizer_options: SanitizerOptions,
+    pub protobuf_schema: Option<ProtobufSchema>,
+    pub protobuf_options: ProtobufOptions,
+    pub xml_options: XmlOptions,
+    pub json_schema: Option<JsonSchema>,
+    pub json_schema_options: JsonSchemaOptions,
+    pub http_options: HttpOptions,
+    pub sql_options: SqlOptions,
+    pub regex_options: RegexOptions,
+    pub template: Option<BinaryTemplate>,
+    pub messagepack_options: MessagePackOptions,
+    pub cbor_options: CborOptions,
+    pub bincode_layout: Option<BincodeLayout>,
+    pub bincode_options: BincodeOptions,
+    pub encodings: EncodingChain,
+    pub region_source: Option<RegionSource>,
+    pub ngram_options: NGramOptions,
+    pub sequence_options: SequenceOptions,
+    pub protocol_options: ProtocolOptions,
+    pub schedule: Schedule,
+    pub memory_options: MemoryOptions,
+    pub leak_options: LeakOptions,
 }
 
 #[derive(Debug, Clone)]
@@ -68,6 +108,8 @@ impl FuzzerConfigBuilder {
                 stats_interval: 100,
                 max_input_size: 1024,
                 min_input_size: 1,
+                size_distribution: SizeDistribution::Uniform,
+                text_alphabet: TextAlphabet::PrintableAscii,
                 enable_logging: false,
                 log_file: None,
                 save_crashes: false,
@@ -91,6 +133,27 @@ impl FuzzerConfigBuilder {
                     undefined_behavior: false,
                     leak: false,
                 },
+                protobuf_schema: None,
+                protobuf_options: ProtobufOptions::default(),
+                xml_options: XmlOptions::default(),
+                json_schema: None,
+                json_schema_options: JsonSchemaOptions::default(),
+                http_options: HttpOptions::default(),
+                sql_options: SqlOptions::default(),
+                regex_options: RegexOptions::default(),
+                template: None,
+                messagepack_options: MessagePackOptions::default(),
+                cbor_options: CborOptions::default(),
+                bincode_layout: None,
+                bincode_options: BincodeOptions::default(),
+                encodings: EncodingChain::new(),
+                region_source: None,
+                ngram_options: NGramOptions::default(),
+                sequence_options: SequenceOptions::default(),
+                protocol_options: ProtocolOptions::default(),
+                schedule: Schedule::EveryTarget,
+                memory_options: MemoryOptions::default(),
+                leak_options: LeakOptions::default(),
             },
         }
     }
@@ -145,6 +208,16 @@ impl FuzzerConfigBuilder {
         self
     }
 
+    pub const fn size_distribution(mut self, distribution: SizeDistribution) -> Self {
+        self.config.size_distribution = distribution;
+        self
+    }
+
+    pub fn text_alphabet(mut self, alphabet: TextAlphabet) -> Self {
+        self.config.text_alphabet = alphabet;
+        self
+    }
+
     pub fn enable_logging(mut self, enable: bool) -> Self {
         self.config.enable_logging = enable;
         self
@@ -230,6 +303,115 @@ impl FuzzerConfigBuilder {
         self
     }
 
+    pub fn protobuf_schema(mut self, schema: ProtobufSchema) -> Self {
+        self.config.protobuf_schema = Some(schema);
+        self
+    }
+
+    pub const fn protobuf_options(mut self, options: ProtobufOptions) -> Self {
+        self.config.protobuf_options = options;
+        self
+    }
+
+    pub const fn xml_options(mut self, options: XmlOptions) -> Self {
+        self.config.xml_options = options;
+        self
+    }
+
+    pub fn json_schema(mut self, schema: JsonSchema) -> Self {
+        self.config.json_schema = Some(schema);
+        self
+    }
+
+    pub const fn json_schema_options(mut self, options: JsonSchemaOptions) -> Self {
+        self.config.json_schema_options = options;
+        self
+    }
+
+    pub const fn http_options(mut self, options: HttpOptions) -> Self {
+        self.config.http_options = options;
+        self
+    }
+
+    pub fn sql_options(mut self, options: SqlOptions) -> Self {
+        self.config.sql_options = options;
+        self
+    }
+
+    pub const fn regex_options(mut self, options: RegexOptions) -> Self {
+        self.config.regex_options = options;
+        self
+    }
+
+    pub fn template(mut self, template: BinaryTemplate) -> Self {
+        self.config.template = Some(template);
+        self
+    }
+
+    pub const fn messagepack_options(mut self, options: MessagePackOptions) -> Self {
+        self.config.messagepack_options = options;
+        self
+    }
+
+    pub const fn cbor_options(mut self, options: CborOptions) -> Self {
+        self.config.cbor_options = options;
+        self
+    }
+
+    pub fn bincode_layout(mut self, layout: BincodeLayout) -> Self {
+        self.config.bincode_layout = Some(layout);
+        self
+    }
+
+    pub const fn bincode_options(mut self, options: BincodeOptions) -> Self {
+        self.config.bincode_options = options;
+        self
+    }
+
+    /// Adds an encoding layer inside any added before it.
+    pub fn encoding_layer<L>(mut self, layer: L) -> Self
+    where
+        L: EncodingLayer + 'static,
+    {
+        self.config.encodings.push(Arc::new(layer));
+        self
+    }
+
+    pub fn region_source(mut self, source: RegionSource) -> Self {
+        self.config.region_source = Some(source);
+        self
+    }
+
+    pub const fn ngram_options(mut self, options: NGramOptions) -> Self {
+        self.config.ngram_options = options;
+        self
+    }
+
+    pub const fn sequence_options(mut self, options: SequenceOptions) -> Self {
+        self.config.sequence_options = options;
+        self
+    }
+
+    pub const fn protocol_options(mut self, options: ProtocolOptions) -> Self {
+        self.config.protocol_options = options;
+        self
+    }
+
+    pub const fn schedule(mut self, schedule: Schedule) -> Self {
+        self.config.schedule = schedule;
+        self
+    }
+
+    pub const fn memory_options(mut self, options: MemoryOptions) -> Self {
+        self.config.memory_options = options;
+        self
+    }
+
+    pub const fn leak_options(mut self, options: LeakOptions) -> Self {
+        self.config.leak_options = options;
+        self
+    }
+
     pub fn build(self) -> FuzzerConfig {
         self.config
     }
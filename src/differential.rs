@@ -0,0 +1,117 @@
+use crate::errors::FuzzerError;
+use crate::utils::panic_message;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+
+type Implementation<T> = Arc<dyn Fn(&[u8]) -> Result<T, FuzzerError> + Send + Sync>;
+type Equivalence<T> =
+    Arc<dyn Fn(&Result<T, FuzzerError>, &Result<T, FuzzerError>) -> bool + Send + Sync>;
+
+/// Implementations of the same behaviour whose outcomes on each input are cross-checked.
+/// Every implementation is compared against the first one.
+pub struct DifferentialTarget<T> {
+    name: String,
+    implementations: Vec<(String, Implementation<T>)>,
+    equivalence: Equivalence<T>,
+}
+
+impl<T: PartialEq + Debug + 'static> DifferentialTarget<T> {
+    /// Outcomes are equivalent if both implementations succeed with equal outputs, or both
+    /// fail, whatever their errors.
+    pub fn new(name: &str) -> Self {
+        Self::with_equivalence(
+            name,
+            |a: &Result<T, FuzzerError>, b: &Result<T, FuzzerError>| match (a, b) {
+                (Ok(a), Ok(b)) => a == b,
+                (Err(_), Err(_)) => true,
+                _ => false,
+            },
+        )
+    }
+}
+
+impl<T: Debug + 'static> DifferentialTarget<T> {
+    pub fn with_equivalence<F>(name: &str, equivalence: F) -> Self
+    where
+        F: Fn(&Result<T, FuzzerError>, &Result<T, FuzzerError>) -> bool + Send + Sync + 'static,
+    {
+        DifferentialTarget {
+            name: name.to_string(),
+            implementations: Vec::new(),
+            equivalence: Arc::new(equivalence),
+        }
+    }
+
+    pub fn implementation<F>(mut self, name: &str, func: F) -> Self
+    where
+        F: Fn(&[u8]) -> Result<T, FuzzerError> + Send + Sync + 'static,
+    {
+        self.implementations
+            .push((name.to_string(), Arc::new(func)));
+        self
+    }
+}
+
+/// Implementations whose outcomes were not equivalent on an input.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Divergence {
+    pub target: String,
+    pub input: Vec<u8>,
+    /// Every implementation with its outcome, formatted with `Debug`.
+    pub outcomes: Vec<(String, String)>,
+}
+
+/// A [`DifferentialTarget`] with its output type erased, as stored by the engine.
+pub trait DifferentialRunner: Send + Sync {
+    fn name(&self) -> &str;
+    /// Runs every implementation on the input. A panic counts as an error.
+    fn run(&self, input: &[u8]) -> Option<Divergence>;
+}
+
+impl<T: Debug + 'static> DifferentialRunner for DifferentialTarget<T> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, input: &[u8]) -> Option<Divergence> {
+        let outcomes: Vec<Result<T, FuzzerError>> = self
+            .implementations
+            .iter()
+            .map(|(_, func)| {
+                panic::catch_unwind(AssertUnwindSafe(|| func(input))).unwrap_or_else(|payload| {
+                    Err(FuzzerError::ExecutionError(format!(
+                        "panicked: {}",
+                        panic_message(payload.as_ref())
+                    )))
+                })
+            })
+            .collect();
+        let (reference, others) = outcomes.split_first()?;
+        if others
+            .iter()
+            .all(|outcome| (self.equivalence)(reference, outcome))
+        {
+            return None;
+        }
+        Some(Divergence {
+            target: self.name.clone(),
+            input: input.to_vec(),
+            outcomes: self
+                .implementations
+                .iter()
+                .zip(&outcomes)
+                .map(|((name, _), outcome)| {
+                    let outcome = match outcome {
+                        Ok(output) => format!("Ok({:?})", output),
+                        Err(e) => format!("Err({})", e),
+                    };
+                    (name.clone(), outcome)
+                })
+                .collect(),
+        })
+    }
+}
@@ -0,0 +1,374 @@
+use flate2::{
+    read::{DeflateDecoder, GzDecoder, ZlibDecoder},
+    write::{DeflateEncoder, GzEncoder, ZlibEncoder},
+};
+use serde_json::Value;
+use std::{
+    fmt,
+    io::{Read, Write},
+    sync::Arc,
+};
+
+/// Decompressed payloads larger than this are rejected, so a small mutated input cannot
+/// expand into gigabytes.
+const MAX_DECOMPRESSED_SIZE: u64 = 16 << 20;
+
+/// One wrapper around a payload, such as base64 or gzip.
+pub trait EncodingLayer: Send + Sync {
+    fn name(&self) -> &str;
+    /// Returns `None` if the data is not wrapped in this layer.
+    fn decode(&self, data: &[u8]) -> Option<Vec<u8>>;
+    /// Wraps `payload`. `original` is the data the previous payload was decoded from, for
+    /// layers that only replace part of it; it is empty for freshly generated inputs.
+    fn encode(&self, original: &[u8], payload: &[u8]) -> Vec<u8>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Base64 {
+    /// Use `-` and `_` instead of `+` and `/`.
+    pub url_safe: bool,
+    pub padding: bool,
+}
+
+impl Default for Base64 {
+    fn default() -> Self {
+        Base64 {
+            url_safe: false,
+            padding: true,
+        }
+    }
+}
+
+impl Base64 {
+    const fn alphabet(&self) -> &'static [u8; 64] {
+        if self.url_safe {
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_"
+        } else {
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/"
+        }
+    }
+}
+
+impl EncodingLayer for Base64 {
+    fn name(&self) -> &str {
+        "base64"
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let alphabet = self.alphabet();
+        let data = data.trim_ascii();
+        let data = data
+            .strip_suffix(b"==")
+            .or(data.strip_suffix(b"="))
+            .unwrap_or(data);
+        if data.len() % 4 == 1 {
+            return None;
+        }
+        let mut out = Vec::with_capacity(data.len() * 3 / 4);
+        let mut buffer = 0u32;
+        let mut bits = 0;
+        for &byte in data {
+            let index = alphabet.iter().position(|&c| c == byte)?;
+            buffer = (buffer << 6) | index as u32;
+            bits += 6;
+            if bits >= 8 {
+                bits -= 8;
+                out.push((buffer >> bits) as u8);
+            }
+        }
+        Some(out)
+    }
+
+    fn encode(&self, _original: &[u8], payload: &[u8]) -> Vec<u8> {
+        let alphabet = self.alphabet();
+        let mut out = Vec::with_capacity(payload.len().div_ceil(3) * 4);
+        for chunk in payload.chunks(3) {
+            let bytes = [
+                chunk[0],
+                *chunk.get(1).unwrap_or(&0),
+                *chunk.get(2).unwrap_or(&0),
+            ];
+            let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+            for i in 0..=chunk.len() {
+                out.push(alphabet[(group >> (18 - 6 * i)) as usize & 0x3F]);
+            }
+            if self.padding {
+                out.extend(std::iter::repeat_n(b'=', 3 - chunk.len()));
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Hex {
+    pub uppercase: bool,
+}
+
+impl EncodingLayer for Hex {
+    fn name(&self) -> &str {
+        "hex"
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let data = data.trim_ascii();
+        if data.len() % 2 != 0 {
+            return None;
+        }
+        data.chunks(2)
+            .map(|pair| Some((hex_digit(pair[0])? << 4) | hex_digit(pair[1])?))
+            .collect()
+    }
+
+    fn encode(&self, _original: &[u8], payload: &[u8]) -> Vec<u8> {
+        let digits = if self.uppercase {
+            b"0123456789ABCDEF"
+        } else {
+            b"0123456789abcdef"
+        };
+        payload
+            .iter()
+            .flat_map(|&b| [digits[usize::from(b >> 4)], digits[usize::from(b & 0xF)]])
+            .collect()
+    }
+}
+
+/// Percent-encoding as used in URLs. Everything except RFC 3986 unreserved characters is
+/// escaped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Percent {
+    /// Encode spaces as `+` and decode `+` as a space, as in form bodies.
+    pub plus_as_space: bool,
+}
+
+impl EncodingLayer for Percent {
+    fn name(&self) -> &str {
+        "percent"
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let mut out = Vec::with_capacity(data.len());
+        let mut i = 0;
+        while i < data.len() {
+            match data[i] {
+                b'%' => {
+                    let high = hex_digit(*data.get(i + 1)?)?;
+                    let low = hex_digit(*data.get(i + 2)?)?;
+                    out.push((high << 4) | low);
+                    i += 3;
+                    continue;
+                }
+                b'+' if self.plus_as_space => out.push(b' '),
+                byte => out.push(byte),
+            }
+            i += 1;
+        }
+        Some(out)
+    }
+
+    fn encode(&self, _original: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(payload.len());
+        for &byte in payload {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    out.push(byte)
+                }
+                b' ' if self.plus_as_space => out.push(b'+'),
+                _ => out.extend_from_slice(format!("%{byte:02X}").as_bytes()),
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Raw DEFLATE (RFC 1951).
+    Deflate,
+    /// zlib (RFC 1950).
+    Zlib,
+    /// gzip (RFC 1952).
+    Gzip,
+}
+
+impl EncodingLayer for Compression {
+    fn name(&self) -> &str {
+        match self {
+            Compression::Deflate => "deflate",
+            Compression::Zlib => "zlib",
+            Compression::Gzip => "gzip",
+        }
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let reader: Box<dyn Read> = match self {
+            Compression::Deflate => Box::new(DeflateDecoder::new(data)),
+            Compression::Zlib => Box::new(ZlibDecoder::new(data)),
+            Compression::Gzip => Box::new(GzDecoder::new(data)),
+        };
+        let mut out = Vec::new();
+        reader
+            .take(MAX_DECOMPRESSED_SIZE + 1)
+            .read_to_end(&mut out)
+            .ok()?;
+        (out.len() as u64 <= MAX_DECOMPRESSED_SIZE).then_some(out)
+    }
+
+    fn encode(&self, _original: &[u8], payload: &[u8]) -> Vec<u8> {
+        let level = flate2::Compression::default();
+        // Writing to a `Vec` cannot fail.
+        match self {
+            Compression::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), level);
+                encoder.write_all(payload).ok();
+                encoder.finish().unwrap_or_default()
+            }
+            Compression::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), level);
+                encoder.write_all(payload).ok();
+                encoder.finish().unwrap_or_default()
+            }
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), level);
+                encoder.write_all(payload).ok();
+                encoder.finish().unwrap_or_default()
+            }
+        }
+    }
+}
+
+/// A string inside a JSON document, addressed by a JSON pointer such as `/body/data`.
+/// Combined with a later layer it reaches payloads like base64 embedded in JSON.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonField {
+    pub pointer: String,
+}
+
+impl JsonField {
+    pub fn new(pointer: &str) -> Self {
+        JsonField {
+            pointer: pointer.to_string(),
+        }
+    }
+}
+
+impl EncodingLayer for JsonField {
+    fn name(&self) -> &str {
+        "json-field"
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let document: Value = serde_json::from_slice(data).ok()?;
+        let field = document.pointer(&self.pointer)?.as_str()?;
+        Some(field.as_bytes().to_vec())
+    }
+
+    fn encode(&self, original: &[u8], payload: &[u8]) -> Vec<u8> {
+        // Payloads that are not UTF-8 cannot be represented in a JSON string.
+        let field = Value::String(String::from_utf8_lossy(payload).into_owned());
+        let mut document = serde_json::from_slice(original).unwrap_or(Value::Null);
+        let mut node = &mut document;
+        for token in self.pointer.split('/').skip(1) {
+            let key = token.replace("~1", "/").replace("~0", "~");
+            node = match node {
+                Value::Array(items) => match key.parse::<usize>() {
+                    Ok(index) if index < items.len() => &mut items[index],
+                    _ => {
+                        items.push(Value::Null);
+                        items.last_mut().unwrap()
+                    }
+                },
+                _ => {
+                    if !node.is_object() {
+                        *node = Value::Object(Default::default());
+                    }
+                    node.as_object_mut()
+                        .unwrap()
+                        .entry(key)
+                        .or_insert(Value::Null)
+                }
+            };
+        }
+        *node = field;
+        serde_json::to_vec(&document).unwrap_or_default()
+    }
+}
+
+/// An ordered list of layers, outermost first. Mutations are applied to the payload inside
+/// the innermost layer and the result is wrapped again.
+#[derive(Clone, Default)]
+pub struct EncodingChain {
+    layers: Vec<Arc<dyn EncodingLayer>>,
+}
+
+/// The data seen at each layer while decoding, outermost first.
+#[derive(Debug, Clone)]
+pub struct DecodedLayers {
+    levels: Vec<Vec<u8>>,
+}
+
+impl DecodedLayers {
+    pub fn payload(&self) -> &[u8] {
+        self.levels.last().map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+impl EncodingChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a layer inside the existing ones.
+    pub fn push(&mut self, layer: Arc<dyn EncodingLayer>) {
+        self.layers.push(layer);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Peels every layer off `data`. Returns `None` if any layer fails to decode.
+    pub fn decode(&self, data: &[u8]) -> Option<DecodedLayers> {
+        let mut levels = vec![data.to_vec()];
+        for layer in &self.layers {
+            let inner = layer.decode(levels.last()?)?;
+            levels.push(inner);
+        }
+        Some(DecodedLayers { levels })
+    }
+
+    /// Wraps a new payload in the same layers `decoded` was peeled from.
+    pub fn encode(&self, decoded: &DecodedLayers, payload: &[u8]) -> Vec<u8> {
+        let mut data = payload.to_vec();
+        for (layer, original) in self.layers.iter().zip(&decoded.levels).rev() {
+            data = layer.encode(original, &data);
+        }
+        data
+    }
+
+    /// Wraps a freshly generated payload.
+    pub fn wrap(&self, payload: &[u8]) -> Vec<u8> {
+        let mut data = payload.to_vec();
+        for layer in self.layers.iter().rev() {
+            data = layer.encode(&[], &data);
+        }
+        data
+    }
+}
+
+impl fmt::Debug for EncodingChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.layers.iter().map(|layer| layer.name()))
+            .finish()
+    }
+}
+
+const fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
@@ -18,6 +18,15 @@ pub enum FuzzerError {
     #[error("Custom error: {0}")]
     CustomError(String),
 
+    #[error("No custom generator registered under the name: {0}")]
+    UnknownGenerator(String),
+
+    #[error("Structured input error: {0}")]
+    StructuredInputError(String),
+
+    #[error("Target hook failed: {0}")]
+    HookError(String),
+
     #[error("Reproduction failled: {0}")]
     ReproductionFailed(String),
 }
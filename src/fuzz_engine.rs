@@ -1,18 +1,55 @@
+use crate::allocator::{
+    self, AllocationScope, AllocationStats, Leak, LeakReport, LeakTracker, LimitExceeded,
+    LimitGuard, OutOfMemory,
+};
 use crate::analysis::Analyzer;
+use crate::coverage::CoverageTracker;
+use crate::differential::{DifferentialRunner, DifferentialTarget, Divergence};
 use crate::errors::FuzzerError;
-use crate::input::InputGenerator;
+use crate::input::{CustomGenerator, InputGenerator};
 use crate::mutator::Mutator;
+use crate::ngram::NGramModel;
+use crate::property::{PropertyRunner, PropertyTarget, PropertyViolation};
+use crate::protocol::{self, ProtocolCrash, ProtocolTarget, Session, StateMachine};
+use crate::regex_gen::RegexGenerator;
+use crate::scheduler::Scheduler;
+use crate::sequence::{self, SequenceCrash, SequenceFailure, SequenceRunner, SequenceTarget};
 use crate::target::Executable;
 use crate::target::TargetFunction;
-use crate::utils::{ExecutionResult, FuzzMode};
+use crate::utils::{Execution, ExecutionResult, FuzzMode, InputFormat};
 use crate::FuzzerConfig;
 
 use log::{error, info};
+use rand::{prelude::StdRng, Rng, SeedableRng};
+use serde::Serialize;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::fmt::Debug;
+use std::io::Write;
+use std::sync::{Arc, Mutex, PoisonError};
 use std::time::Instant;
 use tokio::task;
 use tokio::time::{timeout, Duration};
+
+/// Consecutive failed generations after which a generator is treated as broken.
+const MAX_GENERATION_FAILURES: usize = 100;
+
+/// Executions running under memory limits, saved by the limit handler if their guard refuses
+/// an allocation.
+static RUNNING: Mutex<Vec<RunningExecution>> = Mutex::new(Vec::new());
+
+/// A finding that comes with a record of its own, beside the execution result.
+enum Finding {
+    OutOfMemory(OutOfMemory),
+    Leak(Leak),
+}
+
+struct RunningExecution {
+    guard: u64,
+    target: String,
+    input: Vec<u8>,
+    config: Arc<FuzzerConfig>,
+}
+
 #[derive(Clone)]
 pub struct Fuzzer {
     config: Arc<FuzzerConfig>,
@@ -20,6 +57,17 @@ pub struct Fuzzer {
     pub mutator: Mutator,
     analyzer: Analyzer,
     targets: Vec<TargetFunction>,
+    sequence_targets: Vec<Arc<dyn SequenceRunner>>,
+    sequence_crashes: Vec<SequenceCrash>,
+    differential_targets: Vec<Arc<dyn DifferentialRunner>>,
+    divergences: Vec<Divergence>,
+    property_targets: Vec<Arc<dyn PropertyRunner>>,
+    property_violations: Vec<PropertyViolation>,
+    protocol_targets: Vec<Arc<ProtocolTarget>>,
+    state_machines: Vec<StateMachine>,
+    protocol_crashes: Vec<ProtocolCrash>,
+    out_of_memory: Vec<OutOfMemory>,
+    leaks: Vec<Leak>,
     stats: Arc<Mutex<FuzzerStats>>,
     start_time: Instant,
 }
@@ -34,9 +82,43 @@ pub struct FuzzerStats {
     pub total_crashes: usize,
     pub total_time: Duration,
     pub inputs_tested: usize,
+    pub divergences: usize,
+    pub property_violations: usize,
+    pub out_of_memory: usize,
+    pub leaks: usize,
+    pub targets: HashMap<String, TargetStats>,
+    /// Highest heap usage of a single execution, with the `tracking-allocator` feature.
+    pub peak_heap_bytes: usize,
     // Additional statistics can be added here
 }
 
+#[derive(Default, Clone, Debug)]
+pub struct TargetStats {
+    pub runs: usize,
+    pub successful_runs: usize,
+    pub crashes: usize,
+    pub timeouts: usize,
+    pub unique_crashes: HashMap<String, usize>,
+    pub divergences: usize,
+    pub property_violations: usize,
+    pub out_of_memory: usize,
+    pub leaks: usize,
+    /// Blocks first covered by this target's executions, for targets that record coverage.
+    pub new_coverage: usize,
+    /// Highest heap usage of a single execution, with the `tracking-allocator` feature.
+    pub peak_heap_bytes: usize,
+    /// Allocations made by all executions, with the `tracking-allocator` feature.
+    pub allocations: usize,
+}
+
+/// The generator and mutator of a target with inputs of its own.
+struct InputLane {
+    config: FuzzerConfig,
+    generator: InputGenerator,
+    mutator: Mutator,
+    failures: usize,
+}
+
 impl Fuzzer {
     pub fn new(config: FuzzerConfig) -> Self {
         if config.enable_logging {
@@ -48,26 +130,188 @@ impl Fuzzer {
         }
 
         let input_generator = InputGenerator::new(config.clone());
-        let mutator = Mutator::new(config.mutator_options.clone(), config.seed);
+        let mutator = Self::build_mutator(&config);
         Fuzzer {
             config: Arc::new(config),
             input_generator,
             mutator,
             analyzer: Analyzer::new(),
             targets: Vec::new(),
+            sequence_targets: Vec::new(),
+            sequence_crashes: Vec::new(),
+            differential_targets: Vec::new(),
+            divergences: Vec::new(),
+            property_targets: Vec::new(),
+            property_violations: Vec::new(),
+            protocol_targets: Vec::new(),
+            state_machines: Vec::new(),
+            protocol_crashes: Vec::new(),
+            out_of_memory: Vec::new(),
+            leaks: Vec::new(),
             stats: Arc::new(Mutex::new(FuzzerStats::default())),
             start_time: Instant::now(),
         }
     }
 
+    fn build_mutator(config: &FuzzerConfig) -> Mutator {
+        let mut mutator = Mutator::new(config.mutator_options.clone(), config.seed);
+        mutator.set_input_format(config.input_format.clone());
+        if let Some(ref template) = config.template {
+            mutator.set_template(template.clone());
+        }
+        mutator.set_messagepack_options(config.messagepack_options.clone());
+        mutator.set_cbor_options(config.cbor_options.clone());
+        if let Some(ref layout) = config.bincode_layout {
+            mutator.set_bincode_layout(layout.clone(), config.bincode_options.clone());
+        }
+        mutator.set_encodings(config.encodings.clone());
+        if let Some(ref source) = config.region_source {
+            mutator.set_region_source(source.clone());
+        }
+        mutator
+    }
+
+    /// A snapshot of the statistics so far.
+    pub fn stats(&self) -> FuzzerStats {
+        self.stats.lock().unwrap().clone()
+    }
+
     pub fn add_target(&mut self, target: TargetFunction) {
         self.targets.push(target);
     }
 
+    /// Adds implementations that are run on every shared input and cross-checked. They run in
+    /// every iteration, whatever the schedule, with the fuzzer's timeout.
+    pub fn add_differential_target<T: Debug + 'static>(&mut self, target: DifferentialTarget<T>) {
+        self.differential_targets.push(Arc::new(target));
+    }
+
+    /// Inputs on which differential targets disagreed so far.
+    pub fn divergences(&self) -> &[Divergence] {
+        &self.divergences
+    }
+
+    /// Adds a target whose outputs are checked against its properties on every shared input.
+    /// It runs in every iteration, whatever the schedule, with the fuzzer's timeout.
+    pub fn add_property_target<T: Debug + 'static>(&mut self, target: PropertyTarget<T>) {
+        self.property_targets.push(Arc::new(target));
+    }
+
+    /// Property violations found so far.
+    pub fn property_violations(&self) -> &[PropertyViolation] {
+        &self.property_violations
+    }
+
+    /// Adds a stateful target driven by `FuzzMode::Sequence`.
+    pub fn add_sequence_target<S: 'static>(&mut self, target: SequenceTarget<S>) {
+        self.sequence_targets.push(Arc::new(target));
+    }
+
+    /// Minimized failing sequences found so far.
+    pub fn sequence_crashes(&self) -> &[SequenceCrash] {
+        &self.sequence_crashes
+    }
+
+    /// Executions that exceeded a memory limit and finished without aborting the process.
+    pub fn out_of_memory(&self) -> &[OutOfMemory] {
+        &self.out_of_memory
+    }
+
+    /// Executions that leaked memory twice in a row.
+    pub fn leaks(&self) -> &[Leak] {
+        &self.leaks
+    }
+
+    /// Adds a network server driven by `FuzzMode::Protocol`.
+    pub fn add_protocol_target(&mut self, target: ProtocolTarget) {
+        self.protocol_targets.push(Arc::new(target));
+        self.state_machines.push(StateMachine::new());
+    }
+
+    /// Sessions that crashed a protocol target so far.
+    pub fn protocol_crashes(&self) -> &[ProtocolCrash] {
+        &self.protocol_crashes
+    }
+
+    /// The states and transitions inferred for the protocol target with the given name.
+    pub fn state_machine(&self, target: &str) -> Option<&StateMachine> {
+        self.protocol_targets
+            .iter()
+            .position(|t| t.name() == target)
+            .map(|index| &self.state_machines[index])
+    }
+
+    pub fn register_generator<G>(&mut self, name: &str, generator: G)
+    where
+        G: CustomGenerator + 'static,
+    {
+        self.input_generator.register_generator(name, generator);
+    }
+
+    /// Fuzzes in the configured mode, on a single worker. Whatever the mode, the setup hooks
+    /// of the function targets run before it starts and their teardown hooks after it stops.
     pub async fn run(&mut self) -> Result<(), FuzzerError> {
+        let lanes = match self.config.fuzz_mode {
+            FuzzMode::Sequence | FuzzMode::Protocol => Vec::new(),
+            _ => {
+                Self::check_input_format(&self.config, &self.input_generator)?;
+                let lanes = self.input_lanes();
+                for lane in lanes.iter().flatten() {
+                    Self::check_input_format(&lane.config, &lane.generator)?;
+                }
+                lanes
+            }
+        };
+
+        let mut ready = 0;
+        let mut outcome = Ok(());
+        for target in &self.targets {
+            if let Err(e) = target.setup() {
+                outcome = Err(e);
+                break;
+            }
+            ready += 1;
+        }
+        if outcome.is_ok() {
+            outcome = match self.config.fuzz_mode {
+                FuzzMode::Sequence => self.run_sequences().await,
+                FuzzMode::Protocol => self.run_protocols().await,
+                _ => self.run_inputs(lanes).await,
+            };
+        }
+
+        // Only targets whose setup succeeded are torn down.
+        for target in &self.targets[..ready] {
+            if let Err(e) = target.teardown() {
+                error!("Teardown of {} failed: {}", target.name, e);
+            }
+        }
+        outcome
+    }
+
+    async fn run_inputs(&mut self, mut lanes: Vec<Option<InputLane>>) -> Result<(), FuzzerError> {
+        if self.config.memory_options.is_limited() {
+            if allocator::is_enabled() {
+                allocator::set_limit_handler(save_running_out_of_memory);
+            } else {
+                error!("Memory limits need the tracking-allocator feature and are not enforced");
+            }
+        }
+        if self.config.leak_options.detect && !allocator::is_enabled() {
+            error!("Leak detection needs the tracking-allocator feature and is disabled");
+        }
+
+        let seed = self.config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut scheduler = Scheduler::new(
+            self.config.schedule,
+            self.targets.iter().map(|t| t.options.weight).collect(),
+        );
         let mut iteration = 0;
         let max_iterations = self.config.max_iterations;
         let stop_time = self.config.max_total_time.map(|t| self.start_time + t);
+        let mut shared_failures = 0;
+        let mut outcome = Ok(());
 
         while iteration < max_iterations {
             if let Some(stop_time) = stop_time {
@@ -77,67 +321,409 @@ impl Fuzzer {
                 }
             }
 
-            let mut x = self.input_generator.clone();
-            let inp = x.generate_input();
-            let mut input = match inp {
-                Ok(input) => input,
-                Err(e) => {
-                    error!("Input generation error: {}", e);
-                    continue;
-                }
-            };
-
-            if matches!(self.config.fuzz_mode, FuzzMode::Mutation | FuzzMode::Hybrid) {
-                let mut mutotator = self.mutator.clone();
-                match mutotator.mutate(&input) {
-                    Ok(mutated_input) => input = mutated_input,
+            let scheduled = scheduler.next(&mut rng);
+            let needs_shared = scheduled.iter().any(|&index| lanes[index].is_none())
+                || !self.differential_targets.is_empty()
+                || !self.property_targets.is_empty();
+            // Targets whose input failed to generate sit this iteration out.
+            let input = if needs_shared {
+                match Self::next_input(
+                    &self.config,
+                    &mut self.input_generator,
+                    &mut self.mutator,
+                    &mut shared_failures,
+                ) {
+                    Ok(input) => input,
                     Err(e) => {
-                        error!("Mutation error: {}", e);
-                        continue;
+                        outcome = Err(e);
+                        break;
                     }
                 }
+            } else {
+                None
+            };
+            if input.is_some() {
+                self.stats.lock().unwrap().inputs_tested += 1;
             }
 
-            self.stats.lock().unwrap().inputs_tested += 1;
-
             let stats = Arc::clone(&self.stats);
             let config = Arc::clone(&self.config);
-            let cloned_input = input.clone();
-            let targets = self.targets.clone();
-
-            let tasks: Vec<_> = targets
-                .iter()
-                .map(|target| {
-                    let timeout_duration = self.config.timeout;
-                    let c = cloned_input.clone();
-                    let target_clone = target.clone();
-                    let target_name = target.name.clone();
-                    let exec_future = Self::execute_target(target_clone, c, timeout_duration);
-
-                    task::spawn({
-                        let stats = Arc::clone(&stats);
-                        let config = Arc::clone(&config);
-                        let cloned_input = cloned_input.clone();
-
-                        async move {
-                            let exec_result = exec_future.await;
-                            Fuzzer::update_stats(&stats, &exec_result);
-
-                            if config.enable_logging {
-                                info!("Target: {}, Result: {:?}", target_name, exec_result);
-                            }
 
-                            if config.save_crashes {
-                                if let ExecutionResult::Crash(ref crash_info) = exec_result {
-                                    Fuzzer::save_crash(&config, &cloned_input, crash_info);
-                                }
+            let mut tasks = Vec::with_capacity(scheduled.len());
+            for &index in &scheduled {
+                let target = self.targets[index].clone();
+                let target_input = match lanes[index] {
+                    Some(ref mut lane) => match Self::next_input(
+                        &lane.config,
+                        &mut lane.generator,
+                        &mut lane.mutator,
+                        &mut lane.failures,
+                    ) {
+                        Ok(Some(input)) => {
+                            self.stats.lock().unwrap().inputs_tested += 1;
+                            input
+                        }
+                        Ok(None) => continue,
+                        Err(e) => {
+                            outcome = Err(e);
+                            continue;
+                        }
+                    },
+                    None => match input {
+                        Some(ref input) => input.clone(),
+                        None => continue,
+                    },
+                };
+                let timeout_duration = target.options.timeout.unwrap_or(self.config.timeout);
+
+                tasks.push(task::spawn({
+                    let stats = Arc::clone(&stats);
+                    let config = Arc::clone(&config);
+
+                    async move {
+                        let covered = target.options.coverage.as_ref().map(covered_blocks);
+                        let (execution, finding) = Self::execute_target(
+                            target.clone(),
+                            target_input.clone(),
+                            timeout_duration,
+                            &config,
+                        )
+                        .await;
+                        if let Some(ref usage) = execution.allocations {
+                            Fuzzer::record_allocations(&stats, &target.name, usage);
+                        }
+                        let exec_result = execution.result;
+                        let new_blocks = target.options.coverage.as_ref().map_or(0, |c| {
+                            covered_blocks(c).saturating_sub(covered.unwrap_or(0))
+                        });
+                        Fuzzer::update_stats(&stats, &target.name, &exec_result);
+                        stats
+                            .lock()
+                            .unwrap()
+                            .targets
+                            .entry(target.name.clone())
+                            .or_default()
+                            .new_coverage += new_blocks;
+                        if let ExecutionResult::Error(ref e) = exec_result {
+                            error!("Target hook failed: {}", e);
+                        }
+
+                        if config.enable_logging {
+                            info!("Target: {}, Result: {:?}", target.name, exec_result);
+                        }
+
+                        if config.save_crashes {
+                            if let ExecutionResult::Crash(ref crash_info) = exec_result {
+                                Fuzzer::save_crash(&config, &target_input, crash_info);
+                            }
+                            if let Some(Finding::Leak(ref leak)) = finding {
+                                Fuzzer::save_finding(&config, "leak", leak);
                             }
                         }
-                    })
+                        (index, new_blocks, finding)
+                    }
+                }));
+            }
+
+            for (index, new_blocks, finding) in
+                futures::future::join_all(tasks).await.into_iter().flatten()
+            {
+                scheduler.record_yield(index, new_blocks);
+                match finding {
+                    Some(Finding::OutOfMemory(out_of_memory)) => {
+                        self.out_of_memory.push(out_of_memory)
+                    }
+                    Some(Finding::Leak(leak)) => self.leaks.push(leak),
+                    None => {}
+                }
+            }
+            if outcome.is_err() {
+                break;
+            }
+
+            if let Some(input) = input {
+                for target in self.differential_targets.clone() {
+                    let (exec_result, divergence) = Self::execute_differential(
+                        Arc::clone(&target),
+                        input.clone(),
+                        self.config.timeout,
+                    )
+                    .await;
+                    Fuzzer::update_stats(&self.stats, target.name(), &exec_result);
+                    if self.config.enable_logging {
+                        info!("Target: {}, Result: {:?}", target.name(), exec_result);
+                    }
+                    if let Some(divergence) = divergence {
+                        if self.config.save_crashes {
+                            Fuzzer::save_finding(&self.config, "divergence", &divergence);
+                        }
+                        self.divergences.push(divergence);
+                    }
+                }
+
+                for target in self.property_targets.clone() {
+                    let (exec_result, violation) = Self::execute_property(
+                        Arc::clone(&target),
+                        input.clone(),
+                        self.config.timeout,
+                    )
+                    .await;
+                    Fuzzer::update_stats(&self.stats, target.name(), &exec_result);
+                    if self.config.enable_logging {
+                        info!("Target: {}, Result: {:?}", target.name(), exec_result);
+                    }
+                    if self.config.save_crashes {
+                        if let ExecutionResult::Crash(ref crash_info) = exec_result {
+                            Fuzzer::save_crash(&self.config, &input, crash_info);
+                        }
+                    }
+                    if let Some(violation) = violation {
+                        if self.config.save_crashes {
+                            Fuzzer::save_finding(&self.config, "violation", &violation);
+                        }
+                        self.property_violations.push(violation);
+                    }
+                }
+            }
+
+            iteration += 1;
+
+            if iteration % self.config.stats_interval as u64 == 0 {
+                self.print_stats(iteration as usize);
+            }
+
+            let found = {
+                let stats = self.stats.lock().unwrap();
+                stats.total_crashes > 0
+                    || stats.divergences > 0
+                    || stats.property_violations > 0
+                    || stats.out_of_memory > 0
+                    || stats.leaks > 0
+            };
+            if self.config.stop_on_first_crash && found {
+                info!("Crash detected. Stopping fuzzing.");
+                break;
+            }
+        }
+
+        self.analyzer.report();
+        outcome
+    }
+
+    fn check_input_format(
+        config: &FuzzerConfig,
+        generator: &InputGenerator,
+    ) -> Result<(), FuzzerError> {
+        match config.input_format {
+            InputFormat::Custom(ref name) if !generator.registry().contains(name) => {
+                Err(FuzzerError::UnknownGenerator(name.clone()))
+            }
+            InputFormat::Regex(ref pattern) => {
+                RegexGenerator::new(pattern, config.regex_options.max_repeat).map(|_| ())
+            }
+            InputFormat::Text if config.text_alphabet.is_empty() => Err(
+                FuzzerError::InputGenerationError("Custom text alphabet is empty".to_string()),
+            ),
+            InputFormat::Template if config.template.is_none() => {
+                Err(FuzzerError::InputGenerationError(
+                    "Template input format requires a binary template".to_string(),
+                ))
+            }
+            InputFormat::Protobuf if config.protobuf_schema.is_none() => {
+                Err(FuzzerError::InputGenerationError(
+                    "Protobuf input format requires a protobuf schema".to_string(),
+                ))
+            }
+            InputFormat::NGram if generator.ngram_model().is_none_or(NGramModel::is_empty) => {
+                Err(FuzzerError::InputGenerationError(
+                    "The n-gram model needs a non-empty seed corpus".to_string(),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Generators and mutators for targets that override the input format or mutator
+    /// options. Custom generators registered with the fuzzer are available to them too.
+    fn input_lanes(&self) -> Vec<Option<InputLane>> {
+        self.targets
+            .iter()
+            .enumerate()
+            .map(|(index, target)| {
+                if !target.options.has_own_inputs() {
+                    return None;
+                }
+                let mut config = (*self.config).clone();
+                if let Some(ref format) = target.options.input_format {
+                    config.input_format = format.clone();
+                }
+                if let Some(ref options) = target.options.mutator_options {
+                    config.mutator_options = options.clone();
+                }
+                // Targets with the same overrides should still see different inputs.
+                config.seed = config.seed.map(|seed| seed.wrapping_add(index as u64 + 1));
+                let mut generator = InputGenerator::new(config.clone());
+                generator.set_registry(self.input_generator.registry().clone());
+                let mutator = Self::build_mutator(&config);
+                Some(InputLane {
+                    config,
+                    generator,
+                    mutator,
+                    failures: 0,
                 })
-                .collect();
+            })
+            .collect()
+    }
+
+    /// Generates the next input, or `None` if generation failed. `failures` counts failures
+    /// in a row, and the error is returned once there have been `MAX_GENERATION_FAILURES`.
+    fn next_input(
+        config: &FuzzerConfig,
+        generator: &mut InputGenerator,
+        mutator: &mut Mutator,
+        failures: &mut usize,
+    ) -> Result<Option<Vec<u8>>, FuzzerError> {
+        match Self::produce_input(config, generator, mutator) {
+            Ok(input) => {
+                *failures = 0;
+                Ok(Some(input))
+            }
+            Err(e) => {
+                *failures += 1;
+                if *failures >= MAX_GENERATION_FAILURES {
+                    return Err(e);
+                }
+                error!("{}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// A mutated corpus entry now and then if the corpus is used, or else a generated input,
+    /// mutated in the mutation modes.
+    fn produce_input(
+        config: &FuzzerConfig,
+        generator: &mut InputGenerator,
+        mutator: &mut Mutator,
+    ) -> Result<Vec<u8>, FuzzerError> {
+        let mutating = matches!(config.fuzz_mode, FuzzMode::Mutation | FuzzMode::Hybrid);
+        if mutating && config.use_corpus {
+            if let Some(input) = mutator.mutate_corpus_entry(config.corpus_sampling_rate)? {
+                return Ok(input);
+            }
+        }
+        let input = generator.generate_input()?;
+        if mutating {
+            return mutator.mutate(&input);
+        }
+        Ok(input)
+    }
+
+    async fn run_sequences(&mut self) -> Result<(), FuzzerError> {
+        if self.sequence_targets.is_empty() {
+            return Err(FuzzerError::ExecutionError(
+                "Sequence mode needs at least one sequence target".to_string(),
+            ));
+        }
+        if let Some(target) = self
+            .sequence_targets
+            .iter()
+            .find(|target| target.operation_names().is_empty())
+        {
+            return Err(FuzzerError::ExecutionError(format!(
+                "Sequence target {} has no operations",
+                target.name()
+            )));
+        }
+
+        let options = self.config.sequence_options.clone();
+        let seed = self.config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        let mut rng = StdRng::seed_from_u64(seed);
+        let targets = self.sequence_targets.clone();
+        let mut pools: Vec<Vec<Vec<sequence::Call>>> = vec![Vec::new(); targets.len()];
+        let mut iteration = 0;
+        let stop_time = self.config.max_total_time.map(|t| self.start_time + t);
+
+        while iteration < self.config.max_iterations {
+            if stop_time.is_some_and(|stop_time| Instant::now() >= stop_time) {
+                info!("Maximum total time reached. Stopping fuzzing.");
+                break;
+            }
+
+            for (target, pool) in targets.iter().zip(&mut pools) {
+                let operations = target.operation_names().len();
+                let calls = if pool.is_empty() || rng.gen_bool(options.generation_rate) {
+                    sequence::generate(operations, &options, &mut rng)
+                } else {
+                    let mut calls = pool[rng.gen_range(0..pool.len())].clone();
+                    let mutations = rng.gen_range(1..=self.config.mutator_options.max_mutations);
+                    for _ in 0..mutations {
+                        sequence::mutate(operations, &options, &mut rng, &mut calls, |argument| {
+                            self.mutator
+                                .mutate(argument)
+                                .unwrap_or_else(|_| argument.to_vec())
+                        });
+                    }
+                    calls
+                };
+                if pool.len() < options.pool_size {
+                    pool.push(calls.clone());
+                } else if !pool.is_empty() {
+                    let index = rng.gen_range(0..pool.len());
+                    pool[index] = calls.clone();
+                }
 
-            futures::future::join_all(tasks).await;
+                self.stats.lock().unwrap().inputs_tested += 1;
+                let result =
+                    Self::execute_sequence(Arc::clone(target), calls.clone(), self.config.timeout)
+                        .await;
+                let exec_result = match result {
+                    Ok(Ok(())) => ExecutionResult::Success,
+                    Ok(Err(ref failure)) => {
+                        let names = target.operation_names();
+                        let operation = names.get(calls[failure.call].operation).unwrap_or(&"?");
+                        ExecutionResult::Crash(format!("{}: {}", operation, failure.error))
+                    }
+                    Err(ref exec_result) => exec_result.clone(),
+                };
+                Fuzzer::update_stats(&self.stats, target.name(), &exec_result);
+                if self.config.enable_logging {
+                    info!("Target: {}, Result: {:?}", target.name(), exec_result);
+                }
+
+                if let Ok(Err(failure)) = result {
+                    let minimal = if options.minimize {
+                        // Every candidate runs under the timeout, and one that times out ends
+                        // minimization.
+                        let runner = Arc::clone(target);
+                        let failing = calls.clone();
+                        let handle = tokio::runtime::Handle::current();
+                        let timeout_duration = self.config.timeout;
+                        task::spawn_blocking(move || {
+                            sequence::minimize(&failing, &failure, |calls| {
+                                let run = Self::execute_sequence(
+                                    Arc::clone(&runner),
+                                    calls.to_vec(),
+                                    timeout_duration,
+                                );
+                                handle.block_on(run).ok()
+                            })
+                        })
+                        .await
+                        .unwrap_or(calls)
+                    } else {
+                        calls[..=failure.call].to_vec()
+                    };
+                    let ExecutionResult::Crash(ref crash_info) = exec_result else {
+                        unreachable!("failed sequences are crashes");
+                    };
+                    let crash = SequenceCrash::new(target.as_ref(), crash_info, &minimal);
+                    if self.config.save_crashes {
+                        Fuzzer::save_finding(&self.config, "crash", &crash);
+                    }
+                    self.sequence_crashes.push(crash);
+                }
+            }
 
             iteration += 1;
 
@@ -155,22 +741,365 @@ impl Fuzzer {
         Ok(())
     }
 
+    async fn run_protocols(&mut self) -> Result<(), FuzzerError> {
+        if self.protocol_targets.is_empty() {
+            return Err(FuzzerError::ExecutionError(
+                "Protocol mode needs at least one protocol target".to_string(),
+            ));
+        }
+
+        let options = self.config.protocol_options.clone();
+        let seed = self.config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        let mut rng = StdRng::seed_from_u64(seed);
+        let targets = self.protocol_targets.clone();
+        for target in &targets {
+            target.network().start_server()?;
+        }
+        let mut pending: Vec<Vec<Vec<Vec<u8>>>> = targets
+            .iter()
+            .map(|target| target.seeds().iter().rev().cloned().collect())
+            .collect();
+        let mut corpora: Vec<Vec<Session>> = vec![Vec::new(); targets.len()];
+        let mut iteration = 0;
+        let stop_time = self.config.max_total_time.map(|t| self.start_time + t);
+        let mut generation_failures = 0;
+        let mut outcome = Ok(());
+
+        'fuzzing: while iteration < self.config.max_iterations {
+            if stop_time.is_some_and(|stop_time| Instant::now() >= stop_time) {
+                info!("Maximum total time reached. Stopping fuzzing.");
+                break;
+            }
+
+            for (index, target) in targets.iter().enumerate() {
+                let corpus = &mut corpora[index];
+                let machine = &mut self.state_machines[index];
+                let messages = if let Some(seed) = pending[index].pop() {
+                    seed
+                } else if corpus.is_empty() {
+                    let input = match Self::next_input(
+                        &self.config,
+                        &mut self.input_generator,
+                        &mut self.mutator,
+                        &mut generation_failures,
+                    ) {
+                        Ok(Some(input)) => input,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            outcome = Err(e);
+                            break 'fuzzing;
+                        }
+                    };
+                    let mut messages = target.network().split(&input);
+                    protocol::truncate(&mut messages, &options);
+                    messages
+                } else {
+                    let state = machine
+                        .select(&mut rng, |state| {
+                            corpus
+                                .iter()
+                                .any(|session| session.prefix_len(state).is_some())
+                        })
+                        .unwrap_or_else(|| protocol::INITIAL_STATE.to_string());
+                    let reaching: Vec<&Session> = corpus
+                        .iter()
+                        .filter(|session| session.prefix_len(&state).is_some())
+                        .collect();
+                    let session = reaching[rng.gen_range(0..reaching.len())];
+                    let prefix_len = session.prefix_len(&state).unwrap_or(0);
+                    let donor = corpus[rng.gen_range(0..corpus.len())].messages.clone();
+                    let mut messages = session.messages.clone();
+                    let mutations = rng.gen_range(1..=self.config.mutator_options.max_mutations);
+                    for _ in 0..mutations {
+                        protocol::mutate(
+                            &mut messages,
+                            prefix_len,
+                            &donor,
+                            &options,
+                            &mut rng,
+                            |message| {
+                                self.mutator
+                                    .mutate(message)
+                                    .unwrap_or_else(|_| message.to_vec())
+                            },
+                        );
+                    }
+                    messages
+                };
+
+                self.stats.lock().unwrap().inputs_tested += 1;
+                let exec_result = match timeout(self.config.timeout, target.run(&messages)).await {
+                    Ok(Ok(states)) => {
+                        if machine.record(&states) {
+                            let session = Session { messages, states };
+                            if corpus.len() < options.corpus_size {
+                                corpus.push(session);
+                            } else {
+                                let replaced = rng.gen_range(0..corpus.len());
+                                corpus[replaced] = session;
+                            }
+                        }
+                        ExecutionResult::Success
+                    }
+                    Ok(Err(e)) => {
+                        let error = e.to_string();
+                        let messages = if options.minimize {
+                            // Every candidate runs under the timeout, and one that times out
+                            // ends minimization.
+                            let timeout_duration = self.config.timeout;
+                            protocol::minimize(&messages, &error, |candidate| async move {
+                                timeout(timeout_duration, target.run(&candidate))
+                                    .await
+                                    .ok()
+                                    .map(|result| result.map(|_| ()).map_err(|e| e.to_string()))
+                            })
+                            .await
+                        } else {
+                            messages
+                        };
+                        let crash = ProtocolCrash {
+                            target: target.name().to_string(),
+                            error,
+                            messages,
+                        };
+                        if self.config.save_crashes {
+                            Fuzzer::save_finding(&self.config, "crash", &crash);
+                        }
+                        self.protocol_crashes.push(crash);
+                        ExecutionResult::Crash(e.to_string())
+                    }
+                    Err(_) => ExecutionResult::Timeout,
+                };
+                Fuzzer::update_stats(&self.stats, target.name(), &exec_result);
+                if self.config.enable_logging {
+                    info!("Target: {}, Result: {:?}", target.name(), exec_result);
+                }
+            }
+
+            iteration += 1;
+
+            if iteration % self.config.stats_interval as u64 == 0 {
+                self.print_stats(iteration as usize);
+            }
+
+            if self.config.stop_on_first_crash && self.stats.lock().unwrap().total_crashes > 0 {
+                info!("Crash detected. Stopping fuzzing.");
+                break;
+            }
+        }
+
+        for target in &targets {
+            target.network().stop_server();
+        }
+
+        self.analyzer.report();
+        outcome
+    }
+
+    /// Runs a sequence on a blocking thread. Timeouts and failures outside the operations
+    /// are returned as the execution result.
+    async fn execute_sequence(
+        target: Arc<dyn SequenceRunner>,
+        calls: Vec<sequence::Call>,
+        timeout_duration: Duration,
+    ) -> Result<Result<(), SequenceFailure>, ExecutionResult> {
+        let run = task::spawn_blocking(move || target.run(&calls));
+        match timeout(timeout_duration, run).await {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(e)) => Err(ExecutionResult::Crash(e.to_string())),
+            Err(_) => Err(ExecutionResult::Timeout),
+        }
+    }
+
+    async fn execute_differential(
+        target: Arc<dyn DifferentialRunner>,
+        input: Vec<u8>,
+        timeout_duration: Duration,
+    ) -> (ExecutionResult, Option<Divergence>) {
+        let name = target.name().to_string();
+        let run = task::spawn_blocking(move || target.run(&input));
+        match timeout(timeout_duration, run).await {
+            Ok(Ok(None)) => (ExecutionResult::Success, None),
+            Ok(Ok(Some(divergence))) => (ExecutionResult::Divergence(name), Some(divergence)),
+            Ok(Err(e)) => (ExecutionResult::Crash(e.to_string()), None),
+            Err(_) => (ExecutionResult::Timeout, None),
+        }
+    }
+
+    async fn execute_property(
+        target: Arc<dyn PropertyRunner>,
+        input: Vec<u8>,
+        timeout_duration: Duration,
+    ) -> (ExecutionResult, Option<PropertyViolation>) {
+        let run = task::spawn_blocking(move || target.run(&input));
+        match timeout(timeout_duration, run).await {
+            Ok(Ok(Ok(None))) => (ExecutionResult::Success, None),
+            Ok(Ok(Ok(Some(violation)))) => (
+                ExecutionResult::PropertyViolation(violation.property.clone()),
+                Some(violation),
+            ),
+            Ok(Ok(Err(e))) => (ExecutionResult::Crash(e.to_string()), None),
+            Ok(Err(e)) => (ExecutionResult::Crash(e.to_string()), None),
+            Err(_) => (ExecutionResult::Timeout, None),
+        }
+    }
+
     async fn execute_target(
         target: TargetFunction,
         input: Vec<u8>,
         timeout_duration: Duration,
-    ) -> ExecutionResult {
-        let exec = target.execute(&input);
+        config: &Arc<FuzzerConfig>,
+    ) -> (Execution, Option<Finding>) {
+        let limits = LimitGuard::enforce(&config.memory_options);
+        if let Some(ref limits) = limits {
+            RUNNING.lock().unwrap().push(RunningExecution {
+                guard: limits.id(),
+                target: target.name.clone(),
+                input: input.clone(),
+                config: Arc::clone(config),
+            });
+        }
+        let scope = AllocationScope::start();
+        // The first run only counts leaked allocations; backtraces are sampled when confirming.
+        let (result, leaked) = Self::run_target(
+            &target,
+            &input,
+            timeout_duration,
+            config,
+            limits.as_ref(),
+            scope.as_ref(),
+            0,
+        )
+        .await;
+        let allocations = scope.map(AllocationScope::finish);
+        if let Some(ref limits) = limits {
+            RUNNING
+                .lock()
+                .unwrap()
+                .retain(|execution| execution.guard != limits.id());
+        }
+        let exceeded = limits.and_then(LimitGuard::finish);
 
-        match timeout(timeout_duration, exec).await {
+        // A target that fails because an allocation was refused has run out of memory.
+        if let Some(exceeded) = exceeded {
+            let result = ExecutionResult::OutOfMemory(exceeded.requested_bytes);
+            let finding = Finding::OutOfMemory(OutOfMemory {
+                target: target.name.clone(),
+                exceeded,
+                input,
+            });
+            return (
+                Execution {
+                    result,
+                    allocations,
+                },
+                Some(finding),
+            );
+        }
+
+        // Allocations can outlive a run for good reasons, such as statics initialized on first
+        // use, so a leak only counts if running the input again leaks too.
+        let mut leak = None;
+        if result == ExecutionResult::Success && leaked.is_some_and(|report| !report.is_empty()) {
+            let interval = config.leak_options.backtrace_interval;
+            if let (ExecutionResult::Success, Some(report)) = Self::run_target(
+                &target,
+                &input,
+                timeout_duration,
+                config,
+                None,
+                None,
+                interval,
+            )
+            .await
+            {
+                if !report.is_empty() {
+                    leak = Some(Leak {
+                        target: target.name.clone(),
+                        report,
+                        input,
+                    });
+                }
+            }
+        }
+        let result = match leak {
+            Some(ref leak) => ExecutionResult::Leak(leak.report.leaked_bytes),
+            None => result,
+        };
+        (
+            Execution {
+                result,
+                allocations,
+            },
+            leak.map(Finding::Leak),
+        )
+    }
+
+    /// Runs the target once under `limits`, counting its heap usage in `scope` and tracking
+    /// its allocations if leaks are detected.
+    async fn run_target(
+        target: &TargetFunction,
+        input: &[u8],
+        timeout_duration: Duration,
+        config: &FuzzerConfig,
+        limits: Option<&LimitGuard>,
+        scope: Option<&AllocationScope>,
+        backtrace_interval: usize,
+    ) -> (ExecutionResult, Option<LeakReport>) {
+        let tracker = config
+            .leak_options
+            .detect
+            .then(|| LeakTracker::start(backtrace_interval))
+            .flatten();
+        let mut exec = target.execute(input);
+        if let Some(ref tracker) = tracker {
+            exec = Box::pin(tracker.track(exec));
+        }
+        if let Some(limits) = limits {
+            exec = Box::pin(limits.track(exec));
+        }
+        if let Some(scope) = scope {
+            exec = Box::pin(scope.track(exec));
+        }
+        let result = match timeout(timeout_duration, exec).await {
             Ok(Ok(_)) => ExecutionResult::Success,
+            // A failing hook is a problem with the harness, not a finding.
+            Ok(Err(FuzzerError::HookError(e))) => ExecutionResult::Error(e),
             Ok(Err(e)) => ExecutionResult::Crash(e.to_string()),
             Err(_) => ExecutionResult::Timeout,
-        }
+        };
+        let report = tracker.map(|tracker| tracker.finish(config.leak_options.max_backtraces));
+        (result, report)
     }
 
-    fn update_stats(stats: &Arc<Mutex<FuzzerStats>>, result: &ExecutionResult) {
+    fn record_allocations(stats: &Arc<Mutex<FuzzerStats>>, target: &str, usage: &AllocationStats) {
         let mut stats = stats.lock().unwrap();
+        stats.peak_heap_bytes = stats.peak_heap_bytes.max(usage.peak_bytes);
+        let per_target = stats.targets.entry(target.to_string()).or_default();
+        per_target.peak_heap_bytes = per_target.peak_heap_bytes.max(usage.peak_bytes);
+        per_target.allocations += usage.allocations;
+    }
+
+    fn update_stats(stats: &Arc<Mutex<FuzzerStats>>, target: &str, result: &ExecutionResult) {
+        let mut stats = stats.lock().unwrap();
+        let per_target = stats.targets.entry(target.to_string()).or_default();
+        per_target.runs += 1;
+        match result {
+            ExecutionResult::Success => per_target.successful_runs += 1,
+            ExecutionResult::Crash(crash_info) => {
+                per_target.crashes += 1;
+                *per_target
+                    .unique_crashes
+                    .entry(crash_info.clone())
+                    .or_insert(0) += 1;
+            }
+            ExecutionResult::Timeout => per_target.timeouts += 1,
+            ExecutionResult::Divergence(_) => per_target.divergences += 1,
+            ExecutionResult::PropertyViolation(_) => per_target.property_violations += 1,
+            ExecutionResult::OutOfMemory(_) => per_target.out_of_memory += 1,
+            ExecutionResult::Leak(_) => per_target.leaks += 1,
+            _ => {}
+        }
         stats.total_runs += 1;
         match result {
             ExecutionResult::Success => stats.successful_runs += 1,
@@ -180,6 +1109,10 @@ impl Fuzzer {
                 *stats.unique_crashes.entry(crash_info.clone()).or_insert(0) += 1;
             }
             ExecutionResult::Timeout => stats.timeouts += 1,
+            ExecutionResult::Divergence(_) => stats.divergences += 1,
+            ExecutionResult::PropertyViolation(_) => stats.property_violations += 1,
+            ExecutionResult::OutOfMemory(_) => stats.out_of_memory += 1,
+            ExecutionResult::Leak(_) => stats.leaks += 1,
             _ => {}
         }
     }
@@ -195,11 +1128,42 @@ impl Fuzzer {
         println!("Unique crashes: {}", stats.unique_crashes.len());
         println!("Total crashes: {}", stats.total_crashes);
         println!("Inputs tested: {}", stats.inputs_tested);
+        println!("Divergences: {}", stats.divergences);
+        println!("Property violations: {}", stats.property_violations);
+        if allocator::is_enabled() {
+            println!("Peak heap per execution: {} bytes", stats.peak_heap_bytes);
+            println!("Out of memory: {}", stats.out_of_memory);
+            println!("Leaks: {}", stats.leaks);
+        }
+        let mut targets: Vec<_> = stats.targets.iter().collect();
+        targets.sort_by_key(|(name, _)| name.as_str());
+        for (name, target) in targets {
+            println!(
+                "  {}: {} runs, {} crashes ({} unique), {} timeouts, {} new blocks",
+                name,
+                target.runs,
+                target.crashes,
+                target.unique_crashes.len(),
+                target.timeouts,
+                target.new_coverage
+            );
+        }
         println!("Elapsed time: {:?}", elapsed);
         // Additional statistics can be printed here
         println!("==============================");
     }
 
+    /// Saves a finding as `<prefix>_<uuid>.json` in the crash directory.
+    fn save_finding<T: Serialize>(config: &FuzzerConfig, prefix: &str, finding: &T) {
+        if let Some(ref dir) = config.crash_directory {
+            let _ = std::fs::create_dir_all(dir);
+            let filename = format!("{}/{}_{}.json", dir, prefix, uuid::Uuid::new_v4());
+            if let Ok(json) = serde_json::to_vec_pretty(finding) {
+                let _ = std::fs::write(filename, json);
+            }
+        }
+    }
+
     fn save_crash(config: &FuzzerConfig, input: &Vec<u8>, crash_info: &str) {
         if let Some(ref dir) = config.crash_directory {
             use std::fs::{self, OpenOptions};
@@ -218,3 +1182,26 @@ impl Fuzzer {
         }
     }
 }
+
+/// Saves the execution whose guard refused an allocation, since the process usually aborts as
+/// soon as the handler returns. If the execution survives, the engine records it without
+/// saving it again.
+fn save_running_out_of_memory(guard: u64, exceeded: &LimitExceeded) {
+    let _ = writeln!(std::io::stderr(), "Memory limit exceeded: {}", exceeded);
+    // Only allocations made by targets are checked, so the lock is never held by the thread
+    // that allocated.
+    let running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+    let execution = running.iter().find(|execution| execution.guard == guard);
+    if let Some(execution) = execution.filter(|execution| execution.config.save_crashes) {
+        let out_of_memory = OutOfMemory {
+            target: execution.target.clone(),
+            exceeded: *exceeded,
+            input: execution.input.clone(),
+        };
+        Fuzzer::save_finding(&execution.config, "oom", &out_of_memory);
+    }
+}
+
+fn covered_blocks(coverage: &CoverageTracker) -> usize {
+    coverage.data.lock().unwrap().covered_blocks.len()
+}
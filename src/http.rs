@@ -0,0 +1,555 @@
+use rand::{prelude::StdRng, Rng};
+
+const METHODS: [&str; 9] = [
+    "GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "PATCH", "TRACE", "CONNECT",
+];
+const ODD_METHODS: [&str; 6] = ["get", "G ET", "GET\t", "PRI", "M-SEARCH", "BREW"];
+const VERSIONS: [&str; 4] = ["HTTP/1.1", "HTTP/1.0", "HTTP/1.1", "HTTP/1.1"];
+const ODD_VERSIONS: [&str; 6] = [
+    "HTTP/0.9",
+    "HTTP/2.0",
+    "http/1.1",
+    "HTTP/1.1 ",
+    "HTTP/1.10",
+    "HTTP/01.1",
+];
+const PATH_SEGMENTS: [&str; 10] = [
+    "api",
+    "v1",
+    "users",
+    "index.html",
+    "..",
+    ".",
+    "%2e%2e",
+    "%2F",
+    "a%00b",
+    "%252e",
+];
+const HEADER_NAMES: [&str; 10] = [
+    "Accept",
+    "Accept-Encoding",
+    "Authorization",
+    "Connection",
+    "Content-Type",
+    "Cookie",
+    "Expect",
+    "Referer",
+    "Upgrade",
+    "User-Agent",
+];
+const TRANSFER_ENCODINGS: [&str; 10] = [
+    "chunked",
+    "Chunked",
+    " chunked",
+    "chunked ",
+    "chunked, identity",
+    "identity, chunked",
+    "gzip, chunked",
+    "xchunked",
+    "chunked\t",
+    "\"chunked\"",
+];
+
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+    pub max_headers: usize,
+    pub max_body_size: usize,
+    pub max_path_segments: usize,
+    pub chunked_rate: f64,
+    /// Probability of adding request smuggling edge cases: conflicting length headers,
+    /// obs-fold, whitespace before the colon and bare LF line endings.
+    pub smuggling_rate: f64,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        HttpOptions {
+            max_headers: 8,
+            max_body_size: 256,
+            max_path_segments: 4,
+            chunked_rate: 0.3,
+            smuggling_rate: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpHeader {
+    pub name: String,
+    /// Everything after the colon, including leading whitespace and any folded lines.
+    pub value: String,
+    pub line_ending: String,
+}
+
+impl HttpHeader {
+    pub fn new(name: &str, value: &str) -> Self {
+        HttpHeader {
+            name: name.to_string(),
+            value: format!(" {}", value),
+            line_ending: "\r\n".to_string(),
+        }
+    }
+}
+
+/// An HTTP/1.1 request kept close enough to its wire form that malformed framing survives a
+/// parse and serialize round trip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub target: String,
+    pub version: String,
+    pub headers: Vec<HttpHeader>,
+    pub line_ending: String,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let (head_end, body_start) = find_head_end(data)?;
+        let head = std::str::from_utf8(&data[..head_end]).ok()?;
+        let body = data[body_start..].to_vec();
+
+        let mut lines = split_lines(head).into_iter();
+        let (request_line, line_ending) = lines.next()?;
+        let mut parts = request_line.splitn(3, ' ');
+        let method = parts.next()?.to_string();
+        let target = parts.next()?.to_string();
+        let version = parts.next().unwrap_or_default().to_string();
+
+        let mut headers: Vec<HttpHeader> = Vec::new();
+        for (line, ending) in lines {
+            if line.starts_with([' ', '\t']) {
+                if let Some(last) = headers.last_mut() {
+                    last.value.push_str(&last.line_ending);
+                    last.value.push_str(line);
+                    last.line_ending = ending.to_string();
+                    continue;
+                }
+            }
+            let (name, value) = line.split_once(':').unwrap_or((line, ""));
+            headers.push(HttpHeader {
+                name: name.to_string(),
+                value: value.to_string(),
+                line_ending: ending.to_string(),
+            });
+        }
+
+        Some(HttpRequest {
+            method,
+            target,
+            version,
+            headers,
+            line_ending: line_ending.to_string(),
+            body,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(128 + self.body.len());
+        out.extend_from_slice(self.method.as_bytes());
+        out.push(b' ');
+        out.extend_from_slice(self.target.as_bytes());
+        out.push(b' ');
+        out.extend_from_slice(self.version.as_bytes());
+        out.extend_from_slice(self.line_ending.as_bytes());
+        for header in &self.headers {
+            out.extend_from_slice(header.name.as_bytes());
+            out.push(b':');
+            out.extend_from_slice(header.value.as_bytes());
+            out.extend_from_slice(header.line_ending.as_bytes());
+        }
+        out.extend_from_slice(self.line_ending.as_bytes());
+        out.extend_from_slice(&self.body);
+        out
+    }
+}
+
+pub fn generate(options: &HttpOptions, rng: &mut StdRng) -> Vec<u8> {
+    let method = if rng.gen_bool(0.95) {
+        METHODS[rng.gen_range(0..METHODS.len())]
+    } else {
+        ODD_METHODS[rng.gen_range(0..ODD_METHODS.len())]
+    };
+    let version = if rng.gen_bool(0.95) {
+        VERSIONS[rng.gen_range(0..VERSIONS.len())]
+    } else {
+        ODD_VERSIONS[rng.gen_range(0..ODD_VERSIONS.len())]
+    };
+    let host = format!("example{}.com", rng.gen_range(0..4));
+    let target = match (method, rng.gen_range(0..10)) {
+        ("CONNECT", _) => format!("{}:{}", host, [443, 80, 0, 65535][rng.gen_range(0..4)]),
+        ("OPTIONS", 0) => "*".to_string(),
+        (_, 0) => format!("http://{}{}", host, random_path(options, rng)),
+        _ => random_path(options, rng),
+    };
+
+    let mut request = HttpRequest {
+        method: method.to_string(),
+        target,
+        version: version.to_string(),
+        headers: vec![HttpHeader::new("Host", &host)],
+        line_ending: "\r\n".to_string(),
+        body: Vec::new(),
+    };
+
+    for _ in 0..rng.gen_range(0..=options.max_headers) {
+        let name = HEADER_NAMES[rng.gen_range(0..HEADER_NAMES.len())];
+        let value = header_value(name, rng);
+        request.headers.push(HttpHeader::new(name, &value));
+    }
+
+    let body_len = rng.gen_range(0..=options.max_body_size);
+    let body: Vec<u8> = (0..body_len).map(|_| rng.gen_range(0x20u8..0x7f)).collect();
+    if rng.gen_bool(options.chunked_rate) {
+        request
+            .headers
+            .push(HttpHeader::new("Transfer-Encoding", "chunked"));
+        request.body = chunk(&body, rng);
+    } else if body_len > 0 || rng.gen_bool(0.3) {
+        request
+            .headers
+            .push(HttpHeader::new("Content-Length", &body_len.to_string()));
+        request.body = body;
+    }
+
+    if rng.gen_bool(options.smuggling_rate) {
+        add_smuggling_case(&mut request, rng);
+    }
+    request.to_bytes()
+}
+
+/// Mutates a request at the level of its request line, headers and body framing. Returns
+/// `None` if the data does not parse as an HTTP request.
+pub fn mutate(rng: &mut StdRng, data: &[u8]) -> Option<Vec<u8>> {
+    let mut request = HttpRequest::parse(data)?;
+    match rng.gen_range(0..10) {
+        0 => {
+            request.method = if rng.gen_bool(0.7) {
+                METHODS[rng.gen_range(0..METHODS.len())].to_string()
+            } else {
+                ODD_METHODS[rng.gen_range(0..ODD_METHODS.len())].to_string()
+            };
+        }
+        1 => mutate_target(&mut request.target, rng),
+        2 => request.version = ODD_VERSIONS[rng.gen_range(0..ODD_VERSIONS.len())].to_string(),
+        3 if !request.headers.is_empty() => {
+            let index = rng.gen_range(0..request.headers.len());
+            let header = request.headers[index].clone();
+            let at = rng.gen_range(0..=request.headers.len());
+            request.headers.insert(at, header);
+        }
+        4 if !request.headers.is_empty() => {
+            let index = rng.gen_range(0..request.headers.len());
+            request.headers.remove(index);
+        }
+        5 if request.headers.len() > 1 => {
+            let a = rng.gen_range(0..request.headers.len());
+            let b = rng.gen_range(0..request.headers.len());
+            request.headers.swap(a, b);
+        }
+        6 if !request.headers.is_empty() => {
+            let index = rng.gen_range(0..request.headers.len());
+            mutate_header(&mut request.headers[index], rng);
+        }
+        7 => add_smuggling_case(&mut request, rng),
+        8 => {
+            // Re-frame the body, usually as chunked with fresh chunk boundaries.
+            let body = dechunk(&request.body).unwrap_or_else(|| request.body.clone());
+            request.body = if rng.gen_bool(0.7) {
+                chunk(&body, rng)
+            } else {
+                body
+            };
+        }
+        _ => {
+            let ending = if rng.gen_bool(0.5) { "\n" } else { "\r\n" };
+            match request.headers.len() {
+                0 => request.line_ending = ending.to_string(),
+                n => request.headers[rng.gen_range(0..n)].line_ending = ending.to_string(),
+            }
+        }
+    }
+    Some(request.to_bytes())
+}
+
+fn add_smuggling_case(request: &mut HttpRequest, rng: &mut StdRng) {
+    let length = request.body.len();
+    match rng.gen_range(0..7) {
+        0 => {
+            // Duplicate Content-Length headers that disagree.
+            let other = [0, length + 1, length.saturating_sub(1), 4][rng.gen_range(0..4)];
+            request
+                .headers
+                .push(HttpHeader::new("Content-Length", &length.to_string()));
+            request
+                .headers
+                .push(HttpHeader::new("Content-Length", &other.to_string()));
+        }
+        1 => {
+            // Content-Length next to Transfer-Encoding, in either order.
+            let te = TRANSFER_ENCODINGS[rng.gen_range(0..TRANSFER_ENCODINGS.len())];
+            let cl = HttpHeader::new("Content-Length", &length.to_string());
+            let te = HttpHeader::new("Transfer-Encoding", te);
+            if rng.gen_bool(0.5) {
+                request.headers.extend([cl, te]);
+            } else {
+                request.headers.extend([te, cl]);
+            }
+        }
+        2 => {
+            let te = TRANSFER_ENCODINGS[rng.gen_range(0..TRANSFER_ENCODINGS.len())];
+            request
+                .headers
+                .push(HttpHeader::new("Transfer-Encoding", te));
+            request
+                .headers
+                .push(HttpHeader::new("Transfer-Encoding", "identity"));
+        }
+        3 => {
+            // obs-fold: the value continues on the next line after leading whitespace.
+            let mut header = HttpHeader::new("Transfer-Encoding", "");
+            header.value = format!("\r\n{}chunked", if rng.gen_bool(0.5) { " " } else { "\t" });
+            request.headers.push(header);
+        }
+        4 => {
+            let mut header = HttpHeader::new("Transfer-Encoding ", "chunked");
+            if rng.gen_bool(0.5) {
+                header.name = "Transfer-Encoding\t".to_string();
+            }
+            request.headers.push(header);
+        }
+        5 => {
+            // Bare LF on some lines only, so front-end and back-end may split differently.
+            request.line_ending = if rng.gen_bool(0.5) { "\n" } else { "\r\n" }.to_string();
+            for header in &mut request.headers {
+                if rng.gen_bool(0.5) {
+                    header.line_ending = "\n".to_string();
+                }
+            }
+        }
+        _ => {
+            let value =
+                ["-1", "+4", "0x10", "1_0", "99999999999999999999", " 4 4"][rng.gen_range(0..6)];
+            request
+                .headers
+                .push(HttpHeader::new("Content-Length", value));
+        }
+    }
+}
+
+fn mutate_header(header: &mut HttpHeader, rng: &mut StdRng) {
+    match rng.gen_range(0..6) {
+        0 => header.name = header.name.to_uppercase(),
+        1 => header.name = header.name.replace('-', "_"),
+        2 => header.name.push([' ', '\t'][rng.gen_range(0..2)]),
+        3 => {
+            let trimmed = header.value.trim_start().to_string();
+            header.value = format!("\r\n {}", trimmed);
+        }
+        4 => {
+            // Numeric values are nudged; others get a delimiter or control byte inserted.
+            let trimmed = header.value.trim();
+            header.value = match trimmed.parse::<i64>() {
+                Ok(number) => {
+                    let delta = [-1, 1, i64::MAX / 2][rng.gen_range(0..3)];
+                    format!(" {}", number.saturating_add(delta))
+                }
+                Err(_) => format!(
+                    " {}{}",
+                    trimmed,
+                    [",", ";", "\0", "\r", "\x7f"][rng.gen_range(0..5)]
+                ),
+            };
+        }
+        _ => {
+            let len = rng.gen_range(256..8192);
+            header.value.push_str(&"A".repeat(len));
+        }
+    }
+}
+
+fn mutate_target(target: &mut String, rng: &mut StdRng) {
+    match rng.gen_range(0..5) {
+        0 => {
+            let segment = PATH_SEGMENTS[rng.gen_range(0..PATH_SEGMENTS.len())];
+            let at = target.find('?').unwrap_or(target.len());
+            target.insert_str(at, &format!("/{}", segment));
+        }
+        1 => {
+            let separator = if target.contains('?') { '&' } else { '?' };
+            target.push(separator);
+            target.push_str(&format!(
+                "{}={}",
+                percent_encode(&random_token(rng, 4)),
+                percent_encode(&random_token(rng, 6))
+            ));
+        }
+        2 => {
+            // Percent-encode a character that is normally left alone, or break an encoding.
+            if let Some(index) = (0..target.len()).rev().find(|&i| {
+                target.is_char_boundary(i) && target.as_bytes()[i].is_ascii_alphanumeric()
+            }) {
+                let byte = target.as_bytes()[index];
+                let replacement = if rng.gen_bool(0.7) {
+                    format!("%{:02X}", byte)
+                } else {
+                    ["%", "%G1", "%2", "%%"][rng.gen_range(0..4)].to_string()
+                };
+                target.replace_range(index..index + 1, &replacement);
+            }
+        }
+        3 => target.push('#'),
+        _ => *target = format!("{}{}", target, "/..".repeat(rng.gen_range(1..8))),
+    }
+}
+
+fn random_path(options: &HttpOptions, rng: &mut StdRng) -> String {
+    let mut path = String::new();
+    for _ in 0..rng.gen_range(0..=options.max_path_segments) {
+        path.push('/');
+        if rng.gen_bool(0.3) {
+            path.push_str(PATH_SEGMENTS[rng.gen_range(0..PATH_SEGMENTS.len())]);
+        } else {
+            path.push_str(&percent_encode(&random_token(rng, 8)));
+        }
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+    if rng.gen_bool(0.4) {
+        path.push('?');
+        for i in 0..rng.gen_range(1..4) {
+            if i > 0 {
+                path.push('&');
+            }
+            path.push_str(&format!(
+                "{}={}",
+                percent_encode(&random_token(rng, 4)),
+                percent_encode(&random_token(rng, 6))
+            ));
+        }
+    }
+    path
+}
+
+fn header_value(name: &str, rng: &mut StdRng) -> String {
+    match name {
+        "Connection" => ["keep-alive", "close", "Upgrade", "keep-alive, Upgrade"]
+            [rng.gen_range(0..4)]
+        .to_string(),
+        "Content-Type" => [
+            "application/json",
+            "text/plain; charset=utf-8",
+            "multipart/form-data; boundary=x",
+            "application/x-www-form-urlencoded",
+        ][rng.gen_range(0..4)]
+        .to_string(),
+        "Expect" => "100-continue".to_string(),
+        "Upgrade" => ["websocket", "h2c"][rng.gen_range(0..2)].to_string(),
+        "Cookie" => format!("session={}; theme=dark", random_token(rng, 16)),
+        _ => {
+            let len = rng.gen_range(1..24);
+            random_token(rng, len)
+        }
+    }
+}
+
+fn chunk(body: &[u8], rng: &mut StdRng) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 32);
+    let mut rest = body;
+    while !rest.is_empty() {
+        let len = rng.gen_range(1..=rest.len());
+        let size = if rng.gen_bool(0.2) {
+            format!("{:X}", len)
+        } else {
+            format!("{:x}", len)
+        };
+        out.extend_from_slice(size.as_bytes());
+        if rng.gen_bool(0.1) {
+            out.extend_from_slice(b";ext=1");
+        }
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(&rest[..len]);
+        out.extend_from_slice(b"\r\n");
+        rest = &rest[len..];
+    }
+    out.extend_from_slice(b"0\r\n");
+    if rng.gen_bool(0.1) {
+        out.extend_from_slice(b"Trailer: value\r\n");
+    }
+    out.extend_from_slice(b"\r\n");
+    out
+}
+
+fn dechunk(body: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut rest = body;
+    loop {
+        let line_end = rest.windows(2).position(|w| w == b"\r\n")?;
+        let size_field = std::str::from_utf8(&rest[..line_end]).ok()?;
+        let size_field = size_field.split(';').next()?.trim();
+        let size = usize::from_str_radix(size_field, 16).ok()?;
+        rest = &rest[line_end + 2..];
+        if size == 0 {
+            return Some(out);
+        }
+        out.extend_from_slice(rest.get(..size)?);
+        rest = rest.get(size + 2..)?;
+    }
+}
+
+/// Finds the blank line ending the head, accepting both CRLF and bare LF on either side.
+/// Returns the length of the head including its last line ending, and the start of the body.
+fn find_head_end(data: &[u8]) -> Option<(usize, usize)> {
+    data.iter().enumerate().find_map(|(i, &byte)| {
+        if byte != b'\n' {
+            return None;
+        }
+        match &data[i + 1..] {
+            [b'\n', ..] => Some((i + 1, i + 2)),
+            [b'\r', b'\n', ..] => Some((i + 1, i + 3)),
+            _ => None,
+        }
+    })
+}
+
+fn split_lines(head: &str) -> Vec<(&str, &str)> {
+    let mut lines = Vec::new();
+    let mut rest = head;
+    while !rest.is_empty() {
+        match rest.find('\n') {
+            Some(index) => {
+                let (line, ending) = match rest[..index].strip_suffix('\r') {
+                    Some(line) => (line, "\r\n"),
+                    None => (&rest[..index], "\n"),
+                };
+                lines.push((line, ending));
+                rest = &rest[index + 1..];
+            }
+            None => {
+                lines.push((rest, "\r\n"));
+                rest = "";
+            }
+        }
+    }
+    lines
+}
+
+fn random_token(rng: &mut StdRng, len: usize) -> String {
+    const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789-._~ /";
+    (0..len)
+        .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
+        .collect()
+}
+
+fn percent_encode(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    out
+}
@@ -1,29 +1,211 @@
+use crate::bincode;
+use crate::cbor;
 use crate::config::FuzzerConfig;
 use crate::errors::FuzzerError;
-use crate::utils::InputFormat;
+use crate::http;
+use crate::msgpack;
+use crate::ngram::NGramModel;
+use crate::regex_gen::RegexGenerator;
+use crate::sql::SqlGenerator;
+use crate::unicode;
+use crate::utils::{InputFormat, SizeDistribution, TextAlphabet};
+use crate::xml::XmlGenerator;
 use rand::{prelude::StdRng, Rng, SeedableRng};
 use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizeLimits {
+    pub min: usize,
+    pub max: usize,
+    pub distribution: SizeDistribution,
+}
+
+impl SizeLimits {
+    pub fn sample(&self, rng: &mut impl Rng) -> usize {
+        let min = self.min;
+        let max = self.max.max(min);
+        match self.distribution {
+            SizeDistribution::Uniform => rng.gen_range(min..=max),
+            SizeDistribution::LogUniform => {
+                let low = (min as f64 + 1.0).ln();
+                let high = (max as f64 + 1.0).ln();
+                let size = (rng.gen_range(low..=high).exp().round() as usize).saturating_sub(1);
+                size.clamp(min, max)
+            }
+            SizeDistribution::BoundaryBiased => {
+                let boundaries = self.boundaries();
+                if boundaries.is_empty() || rng.gen_bool(0.2) {
+                    rng.gen_range(min..=max)
+                } else {
+                    boundaries[rng.gen_range(0..boundaries.len())]
+                }
+            }
+        }
+    }
+
+    /// Sizes at which length handling tends to break: 0, 1, powers of two +-1 and the limits.
+    pub fn boundaries(&self) -> Vec<usize> {
+        let max = self.max.max(self.min);
+        let mut sizes = vec![0, 1, self.min, max.saturating_sub(1), max];
+        let mut power = 2usize;
+        while power <= max {
+            sizes.extend([power - 1, power, power + 1]);
+            power = match power.checked_mul(2) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        sizes.retain(|size| (self.min..=max).contains(size));
+        sizes.sort_unstable();
+        sizes.dedup();
+        sizes
+    }
+}
+
+/// A user-supplied generator selected through `InputFormat::Custom(name)`.
+pub trait CustomGenerator: Send + Sync {
+    fn generate(&self, rng: &mut StdRng, limits: &SizeLimits) -> Result<Vec<u8>, FuzzerError>;
+}
+
+impl<F> CustomGenerator for F
+where
+    F: Fn(&mut StdRng, &SizeLimits) -> Result<Vec<u8>, FuzzerError> + Send + Sync,
+{
+    fn generate(&self, rng: &mut StdRng, limits: &SizeLimits) -> Result<Vec<u8>, FuzzerError> {
+        self(rng, limits)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct GeneratorRegistry {
+    generators: HashMap<String, Arc<dyn CustomGenerator>>,
+}
+
+impl GeneratorRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, name: &str, generator: Arc<dyn CustomGenerator>) {
+        self.generators.insert(name.to_string(), generator);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn CustomGenerator>> {
+        self.generators.get(name).cloned()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.generators.contains_key(name)
+    }
+}
+
+impl fmt::Debug for GeneratorRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.generators.keys()).finish()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct InputGenerator {
     config: FuzzerConfig,
     rng: StdRng,
+    registry: GeneratorRegistry,
+    regex: Option<RegexGenerator>,
+    ngram: Option<NGramModel>,
 }
 
 impl InputGenerator {
     pub fn new(config: FuzzerConfig) -> Self {
         let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        let regex = match config.input_format {
+            InputFormat::Regex(ref pattern) => {
+                RegexGenerator::new(pattern, config.regex_options.max_repeat).ok()
+            }
+            _ => None,
+        };
+        let ngram = match config.input_format {
+            InputFormat::NGram => Some(NGramModel::train(
+                &seed_corpus(&config),
+                &config.ngram_options,
+            )),
+            _ => None,
+        };
         InputGenerator {
             config,
             rng: StdRng::seed_from_u64(seed),
+            registry: GeneratorRegistry::new(),
+            regex,
+            ngram,
+        }
+    }
+
+    pub fn register_generator<G>(&mut self, name: &str, generator: G)
+    where
+        G: CustomGenerator + 'static,
+    {
+        self.registry.register(name, Arc::new(generator));
+    }
+
+    pub fn set_registry(&mut self, registry: GeneratorRegistry) {
+        self.registry = registry;
+    }
+
+    pub const fn registry(&self) -> &GeneratorRegistry {
+        &self.registry
+    }
+
+    /// The model `InputFormat::NGram` samples from, learned from the seed corpus.
+    pub const fn ngram_model(&self) -> Option<&NGramModel> {
+        self.ngram.as_ref()
+    }
+
+    pub const fn size_limits(&self) -> SizeLimits {
+        SizeLimits {
+            min: self.config.min_input_size,
+            max: self.config.max_input_size,
+            distribution: self.config.size_distribution,
         }
     }
 
+    /// Generates a payload in the configured format and wraps it in the configured
+    /// encoding layers.
     pub fn generate_input(&mut self) -> Result<Vec<u8>, FuzzerError> {
+        let payload = self.generate_payload()?;
+        if self.config.encodings.is_empty() {
+            return Ok(payload);
+        }
+        Ok(self.config.encodings.wrap(&payload))
+    }
+
+    fn generate_payload(&mut self) -> Result<Vec<u8>, FuzzerError> {
         match self.config.input_format {
             InputFormat::Binary => self.generate_binary(),
             InputFormat::Text => self.generate_text(),
             InputFormat::JSON => self.generate_json(),
             InputFormat::XML => self.generate_xml(),
+            InputFormat::Protobuf => self.generate_protobuf(),
+            InputFormat::Unicode => self.generate_unicode(),
+            InputFormat::Http => Ok(http::generate(&self.config.http_options, &mut self.rng)),
+            InputFormat::Sql => {
+                Ok(SqlGenerator::new(&self.config.sql_options, &mut self.rng).generate())
+            }
+            InputFormat::Regex(ref pattern) => self.generate_regex(pattern.clone().as_str()),
+            InputFormat::Template => self.generate_template(),
+            InputFormat::MessagePack => Ok(msgpack::generate(
+                &self.config.messagepack_options,
+                &mut self.rng,
+            )),
+            InputFormat::Cbor => Ok(cbor::generate(&self.config.cbor_options, &mut self.rng)),
+            InputFormat::Bincode => Ok(bincode::generate(
+                self.config.bincode_layout.as_ref(),
+                &self.config.bincode_options,
+                &mut self.rng,
+            )),
+            InputFormat::NGram => self.generate_ngram(),
             InputFormat::Custom(ref format) => self.generate_custom(format.clone().as_str()),
             //todo
             _ => self.generate_binary(),
@@ -31,19 +213,76 @@ impl InputGenerator {
     }
 
     fn generate_binary(&mut self) -> Result<Vec<u8>, FuzzerError> {
-        let size = self.rng.gen_range(1..1024);
+        let size = self.size_limits().sample(&mut self.rng);
         Ok((0..size).map(|_| self.rng.gen::<u8>()).collect())
     }
 
     fn generate_text(&mut self) -> Result<Vec<u8>, FuzzerError> {
-        let size = self.rng.gen_range(1..1024);
-        let text: String = (0..size)
-            .map(|_| self.rng.gen_range(32u8..127u8) as char)
-            .collect();
+        const ALPHANUMERIC: &[u8] =
+            b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        let size = self.size_limits().sample(&mut self.rng);
+        if self.config.text_alphabet.is_empty() {
+            return Err(FuzzerError::InputGenerationError(
+                "Custom text alphabet is empty".to_string(),
+            ));
+        }
+
+        // Sizes are in bytes, so multi-byte characters are only added while they still fit.
+        let mut text = String::with_capacity(size);
+        let mut misses = 0;
+        while text.len() < size && misses < 16 {
+            let c = match self.config.text_alphabet {
+                TextAlphabet::PrintableAscii => self.rng.gen_range(32u8..127u8) as char,
+                TextAlphabet::Ascii => self.rng.gen_range(0u8..128u8) as char,
+                TextAlphabet::Alphanumeric => {
+                    ALPHANUMERIC[self.rng.gen_range(0..ALPHANUMERIC.len())] as char
+                }
+                TextAlphabet::Unicode => random_char_within(&mut self.rng, size - text.len()),
+                TextAlphabet::Custom(ref chars) => chars[self.rng.gen_range(0..chars.len())],
+            };
+            if text.len() + c.len_utf8() <= size {
+                text.push(c);
+            } else {
+                misses += 1;
+            }
+        }
         Ok(text.into_bytes())
     }
 
+    fn generate_unicode(&mut self) -> Result<Vec<u8>, FuzzerError> {
+        let limits = self.size_limits();
+        Ok(unicode::generate(&mut self.rng, &limits))
+    }
+
+    fn generate_regex(&mut self, pattern: &str) -> Result<Vec<u8>, FuzzerError> {
+        if self.regex.as_ref().map(RegexGenerator::pattern) != Some(pattern) {
+            let max_repeat = self.config.regex_options.max_repeat;
+            self.regex = Some(RegexGenerator::new(pattern, max_repeat)?);
+        }
+        let generator = self.regex.as_ref().expect("regex generator was just built");
+        if self.rng.gen_bool(self.config.regex_options.near_miss_rate) {
+            Ok(generator.generate_near_miss(&mut self.rng))
+        } else {
+            Ok(generator.generate(&mut self.rng))
+        }
+    }
+
+    fn generate_ngram(&mut self) -> Result<Vec<u8>, FuzzerError> {
+        let limits = self.size_limits();
+        match self.ngram {
+            Some(ref model) if !model.is_empty() => Ok(model.generate(&mut self.rng, &limits)),
+            _ => Err(FuzzerError::InputGenerationError(
+                "The n-gram model needs a non-empty seed corpus".to_string(),
+            )),
+        }
+    }
+
     fn generate_json(&mut self) -> Result<Vec<u8>, FuzzerError> {
+        if let Some(ref schema) = self.config.json_schema {
+            let document = schema.generate(&self.config.json_schema_options, &mut self.rng);
+            return serde_json::to_vec(&document)
+                .map_err(|e| FuzzerError::InputGenerationError(e.to_string()));
+        }
         let obj = json!({
             "key": self.rng.gen::<u64>(),
             "value": self.rng.gen::<f64>(),
@@ -57,17 +296,62 @@ impl InputGenerator {
     }
 
     fn generate_xml(&mut self) -> Result<Vec<u8>, FuzzerError> {
-        let xml = format!(
-            "<root><value>{}</value><flag>{}</flag></root>",
-            self.rng.gen::<u64>(),
-            self.rng.gen::<bool>()
-        );
-        Ok(xml.into_bytes())
+        Ok(XmlGenerator::new(&self.config.xml_options, &mut self.rng).generate())
+    }
+
+    fn generate_template(&mut self) -> Result<Vec<u8>, FuzzerError> {
+        let template = self.config.template.as_ref().ok_or_else(|| {
+            FuzzerError::InputGenerationError(
+                "Template input format requires a binary template".to_string(),
+            )
+        })?;
+        Ok(template.generate(&mut self.rng))
+    }
+
+    fn generate_protobuf(&mut self) -> Result<Vec<u8>, FuzzerError> {
+        let schema = self.config.protobuf_schema.as_ref().ok_or_else(|| {
+            FuzzerError::InputGenerationError(
+                "Protobuf input format requires a protobuf schema".to_string(),
+            )
+        })?;
+        Ok(schema.generate(&self.config.protobuf_options, &mut self.rng))
     }
 
     fn generate_custom(&mut self, format: &str) -> Result<Vec<u8>, FuzzerError> {
-        // Custom format handling logic
-        let data = format!("Custom format: {}", format);
-        Ok(data.into_bytes())
+        let generator = self
+            .registry
+            .get(format)
+            .ok_or_else(|| FuzzerError::UnknownGenerator(format.to_string()))?;
+        let limits = self.size_limits();
+        generator.generate(&mut self.rng, &limits)
+    }
+}
+
+fn random_char_within(rng: &mut StdRng, max_width: usize) -> char {
+    // Picking the encoded width first keeps short characters from being drowned out by
+    // the far larger four-byte planes.
+    let code = match rng.gen_range(1..=max_width.clamp(1, 4)) {
+        1 => rng.gen_range(0..0x80),
+        2 => rng.gen_range(0x80..0x800),
+        3 => rng.gen_range(0x800..0x10000),
+        _ => rng.gen_range(0x10000..0x110000),
+    };
+    char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// The configured initial inputs followed by the files in the corpus directory.
+fn seed_corpus(config: &FuzzerConfig) -> Vec<Vec<u8>> {
+    let mut corpus = config.initial_inputs.clone();
+    if let Some(ref directory) = config.corpus_directory {
+        let mut paths: Vec<_> = fs::read_dir(directory)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect();
+        paths.sort();
+        corpus.extend(paths.iter().filter_map(|path| fs::read(path).ok()));
     }
+    corpus
 }
@@ -0,0 +1,968 @@
+use crate::errors::FuzzerError;
+use crate::regex_gen::RegexGenerator;
+use rand::{prelude::StdRng, Rng};
+use regex::Regex;
+use serde_json::{json, Map, Number, Value};
+use std::collections::HashMap;
+use std::fs::File;
+
+const JSON_TYPES: [&str; 7] = [
+    "null", "boolean", "integer", "number", "string", "array", "object",
+];
+
+#[derive(Debug, Clone)]
+pub struct JsonSchemaOptions {
+    pub max_depth: usize,
+    pub max_items: usize,
+    pub max_string_length: usize,
+    pub optional_property_rate: f64,
+    /// Probability that a generated document violates exactly one schema constraint.
+    pub near_miss_rate: f64,
+}
+
+impl Default for JsonSchemaOptions {
+    fn default() -> Self {
+        JsonSchemaOptions {
+            max_depth: 8,
+            max_items: 8,
+            max_string_length: 32,
+            optional_property_rate: 0.5,
+            near_miss_rate: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JsonSchema {
+    root: Value,
+    patterns: HashMap<String, (RegexGenerator, Regex)>,
+}
+
+impl JsonSchema {
+    pub fn load_from_file(filename: &str) -> Result<Self, FuzzerError> {
+        let file = File::open(filename).map_err(|e| {
+            FuzzerError::InputGenerationError(format!(
+                "Failed to open JSON schema {}: {}",
+                filename, e
+            ))
+        })?;
+        let root = serde_json::from_reader(file).map_err(|e| {
+            FuzzerError::InputGenerationError(format!("Invalid JSON schema {}: {}", filename, e))
+        })?;
+        Self::from_value(root)
+    }
+
+    pub fn from_value(mut root: Value) -> Result<Self, FuzzerError> {
+        let original = root.clone();
+        merge_all_of(&mut root, &original, 0);
+        let mut patterns = HashMap::new();
+        collect_patterns(&root, &mut patterns)?;
+        Ok(JsonSchema { root, patterns })
+    }
+
+    pub fn generate(&self, options: &JsonSchemaOptions, rng: &mut StdRng) -> Value {
+        let near_miss = rng.gen_bool(options.near_miss_rate);
+        let mut walker = SchemaWalker {
+            schema: self,
+            options,
+            rng,
+            path: Vec::new(),
+            sites: Vec::new(),
+        };
+        let mut document = walker.value(&self.root, 0);
+
+        // Some constraints cannot be broken without breaking another one next to them, so
+        // sites are tried until one yields a value.
+        while near_miss && !walker.sites.is_empty() {
+            let index = walker.rng.gen_range(0..walker.sites.len());
+            let site = walker.sites.swap_remove(index);
+            let Some(target) = value_at_path(&mut document, &site.path) else {
+                continue;
+            };
+            if let Some(value) = walker.violate(&site, target) {
+                *target = value;
+                break;
+            }
+        }
+        document
+    }
+
+    fn resolve<'a>(&'a self, schema: &'a Value) -> &'a Value {
+        let mut schema = schema;
+        // Bounded so that `$ref` cycles without intermediate structure cannot loop forever.
+        for _ in 0..32 {
+            let Some(reference) = schema.get("$ref").and_then(Value::as_str) else {
+                break;
+            };
+            match reference.strip_prefix('#') {
+                Some("") => schema = &self.root,
+                Some(pointer) => match self.root.pointer(pointer) {
+                    Some(target) => schema = target,
+                    None => break,
+                },
+                None => break,
+            }
+        }
+        schema
+    }
+}
+
+/// Folds every `allOf` into its parent schema so that generation sees one set of constraints.
+fn merge_all_of(schema: &mut Value, root: &Value, depth: usize) {
+    if depth > 64 {
+        return;
+    }
+    match schema {
+        Value::Object(map) => {
+            if let Some(Value::Array(subschemas)) = map.remove("allOf") {
+                for subschema in subschemas {
+                    let mut subschema = resolve_owned(subschema, root);
+                    merge_all_of(&mut subschema, root, depth + 1);
+                    if let Value::Object(submap) = subschema {
+                        merge_schema_maps(map, submap);
+                    }
+                }
+            }
+            for (key, value) in map.iter_mut() {
+                if key != "enum" && key != "const" && key != "default" && key != "examples" {
+                    merge_all_of(value, root, depth + 1);
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                merge_all_of(value, root, depth + 1);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn resolve_owned(mut schema: Value, root: &Value) -> Value {
+    for _ in 0..32 {
+        let target = match schema.get("$ref").and_then(Value::as_str) {
+            Some("#") => root.clone(),
+            Some(reference) => match reference.strip_prefix('#').and_then(|p| root.pointer(p)) {
+                Some(target) => target.clone(),
+                None => break,
+            },
+            None => break,
+        };
+        schema = target;
+    }
+    schema
+}
+
+fn merge_schema_maps(map: &mut Map<String, Value>, other: Map<String, Value>) {
+    for (key, value) in other {
+        match (key.as_str(), map.get_mut(&key), value) {
+            ("properties", Some(Value::Object(properties)), Value::Object(more)) => {
+                for (name, schema) in more {
+                    properties.entry(name).or_insert(schema);
+                }
+            }
+            ("required", Some(Value::Array(required)), Value::Array(more)) => {
+                for name in more {
+                    if !required.contains(&name) {
+                        required.push(name);
+                    }
+                }
+            }
+            (_, Some(_), _) => {}
+            (_, None, value) => {
+                map.insert(key, value);
+            }
+        }
+    }
+}
+
+fn collect_patterns(
+    schema: &Value,
+    patterns: &mut HashMap<String, (RegexGenerator, Regex)>,
+) -> Result<(), FuzzerError> {
+    match schema {
+        Value::Object(map) => {
+            if let Some(Value::String(pattern)) = map.get("pattern") {
+                if !patterns.contains_key(pattern) {
+                    let generator = RegexGenerator::new(pattern, 8)?;
+                    let regex = Regex::new(pattern).map_err(|e| {
+                        FuzzerError::InputGenerationError(format!(
+                            "Invalid pattern {}: {}",
+                            pattern, e
+                        ))
+                    })?;
+                    patterns.insert(pattern.clone(), (generator, regex));
+                }
+            }
+            for (key, value) in map {
+                // Enum and const values are data, not subschemas.
+                if key != "enum" && key != "const" && key != "default" && key != "examples" {
+                    collect_patterns(value, patterns)?;
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                collect_patterns(value, patterns)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone)]
+enum Constraint {
+    Type,
+    Enum,
+    Const,
+    Minimum,
+    Maximum,
+    ExclusiveMinimum,
+    ExclusiveMaximum,
+    MultipleOf,
+    MinLength,
+    MaxLength,
+    Pattern,
+    MinItems,
+    MaxItems,
+    Required(String),
+    AdditionalProperties,
+}
+
+struct ViolationSite<'a> {
+    path: Vec<PathSegment>,
+    schema: &'a Value,
+    constraint: Constraint,
+}
+
+struct SchemaWalker<'a, 'r> {
+    schema: &'a JsonSchema,
+    options: &'a JsonSchemaOptions,
+    rng: &'r mut StdRng,
+    path: Vec<PathSegment>,
+    sites: Vec<ViolationSite<'a>>,
+}
+
+impl<'a> SchemaWalker<'a, '_> {
+    fn site(&mut self, schema: &'a Value, constraint: Constraint) {
+        self.sites.push(ViolationSite {
+            path: self.path.clone(),
+            schema,
+            constraint,
+        });
+    }
+
+    fn value(&mut self, schema: &'a Value, depth: usize) -> Value {
+        let schema = self.schema.resolve(schema);
+        let map = match schema {
+            Value::Object(map) => map,
+            Value::Bool(false) => return Value::Null,
+            _ => return self.any_value(depth),
+        };
+        if depth > self.options.max_depth * 2 {
+            return Value::Null;
+        }
+
+        for keyword in ["anyOf", "oneOf"] {
+            if let Some(Value::Array(subschemas)) = map.get(keyword) {
+                if !subschemas.is_empty() {
+                    let sub = &subschemas[self.rng.gen_range(0..subschemas.len())];
+                    return self.value(sub, depth);
+                }
+            }
+        }
+
+        if let Some(value) = map.get("const") {
+            self.site(schema, Constraint::Const);
+            return value.clone();
+        }
+        if let Some(Value::Array(values)) = map.get("enum") {
+            if !values.is_empty() {
+                self.site(schema, Constraint::Enum);
+                return values[self.rng.gen_range(0..values.len())].clone();
+            }
+        }
+
+        let types = allowed_types(map);
+        // An inferred type is not a constraint: the keywords it is inferred from accept values
+        // of every other type.
+        if !declared_types(map).is_empty() && types.len() < JSON_TYPES.len() {
+            self.site(schema, Constraint::Type);
+        }
+        let kind = types[self.rng.gen_range(0..types.len())];
+        match kind {
+            "null" => Value::Null,
+            "boolean" => Value::Bool(self.rng.gen()),
+            "integer" => self.integer(schema, map),
+            "number" => self.number(schema, map),
+            "string" => self.string(schema, map),
+            "array" => self.array(schema, map, depth),
+            _ => self.object(schema, map, depth),
+        }
+    }
+
+    fn integer(&mut self, schema: &'a Value, map: &Map<String, Value>) -> Value {
+        let (low, high) = self.numeric_bounds(schema, map);
+        let low = low.ceil().max(i64::MIN as f64) as i64;
+        let high = high.floor().min(i64::MAX as f64) as i64;
+        let mut value = if low <= high {
+            self.rng.gen_range(low..=high)
+        } else {
+            low
+        };
+        if let Some(step) = map.get("multipleOf").and_then(Value::as_i64) {
+            if step > 1 {
+                self.site(schema, Constraint::MultipleOf);
+                value -= value.rem_euclid(step);
+                if value < low {
+                    value = value.saturating_add(step);
+                }
+                // Rounding up can pass the maximum, which the bias towards the edges may have
+                // moved down to the minimum.
+                let (_, max) = numeric_range(map);
+                if value as f64 > max {
+                    value = value.saturating_sub(step);
+                }
+            }
+        }
+        json!(value)
+    }
+
+    fn number(&mut self, schema: &'a Value, map: &Map<String, Value>) -> Value {
+        let (low, high) = self.numeric_bounds(schema, map);
+        let mut value = if low < high {
+            self.rng.gen_range(low..high)
+        } else {
+            low
+        };
+        if let Some(step) = map.get("multipleOf").and_then(Value::as_f64) {
+            if step > 0.0 {
+                self.site(schema, Constraint::MultipleOf);
+                value = (value / step).ceil() * step;
+                let (_, max) = numeric_range(map);
+                if value > max {
+                    value = (max / step).floor() * step;
+                }
+            }
+        }
+        Number::from_f64(value).map_or(Value::Null, Value::Number)
+    }
+
+    fn numeric_bounds(&mut self, schema: &'a Value, map: &Map<String, Value>) -> (f64, f64) {
+        for (keyword, constraint) in [
+            ("minimum", Constraint::Minimum),
+            ("exclusiveMinimum", Constraint::ExclusiveMinimum),
+            ("maximum", Constraint::Maximum),
+            ("exclusiveMaximum", Constraint::ExclusiveMaximum),
+        ] {
+            if map.get(keyword).is_some_and(Value::is_number) {
+                self.site(schema, constraint);
+            }
+        }
+        let (low, high) = numeric_range(map);
+        // Bias towards the edges of the allowed range, where off-by-one bugs live.
+        if self.rng.gen_bool(0.2) {
+            return (low, low);
+        }
+        if self.rng.gen_bool(0.2) {
+            return (high, high);
+        }
+        (low, high)
+    }
+
+    fn string(&mut self, schema: &'a Value, map: &Map<String, Value>) -> Value {
+        let min_len = map
+            .get("minLength")
+            .and_then(Value::as_u64)
+            .map(|n| n as usize);
+        let max_len = map
+            .get("maxLength")
+            .and_then(Value::as_u64)
+            .map(|n| n as usize);
+        if min_len.is_some_and(|n| n > 0) {
+            self.site(schema, Constraint::MinLength);
+        }
+        if max_len.is_some() {
+            self.site(schema, Constraint::MaxLength);
+        }
+        let min_len = min_len.unwrap_or(0);
+        let max_len = max_len
+            .unwrap_or(min_len + self.options.max_string_length)
+            .max(min_len);
+
+        if let Some(pattern) = map.get("pattern").and_then(Value::as_str) {
+            if let Some((generator, _)) = self.schema.patterns.get(pattern) {
+                self.site(schema, Constraint::Pattern);
+                let mut candidate = generator.generate_string(self.rng);
+                for _ in 0..16 {
+                    let len = candidate.chars().count();
+                    if len >= min_len && len <= max_len {
+                        break;
+                    }
+                    candidate = generator.generate_string(self.rng);
+                }
+                return Value::String(candidate);
+            }
+        }
+
+        if let Some(format) = map.get("format").and_then(Value::as_str) {
+            if let Some(value) = self.formatted_string(format) {
+                return Value::String(value);
+            }
+        }
+
+        let len = self.rng.gen_range(min_len..=max_len);
+        Value::String(self.random_string(len))
+    }
+
+    fn formatted_string(&mut self, format: &str) -> Option<String> {
+        let value = match format {
+            "date" => format!(
+                "{:04}-{:02}-{:02}",
+                self.rng.gen_range(1900..2100),
+                self.rng.gen_range(1..=12),
+                self.rng.gen_range(1..=28)
+            ),
+            "date-time" => format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                self.rng.gen_range(1900..2100),
+                self.rng.gen_range(1..=12),
+                self.rng.gen_range(1..=28),
+                self.rng.gen_range(0..24),
+                self.rng.gen_range(0..60),
+                self.rng.gen_range(0..60)
+            ),
+            "email" => format!("{}@example.com", self.random_string(8)),
+            "ipv4" => format!(
+                "{}.{}.{}.{}",
+                self.rng.gen::<u8>(),
+                self.rng.gen::<u8>(),
+                self.rng.gen::<u8>(),
+                self.rng.gen::<u8>()
+            ),
+            "uri" => format!("https://example.com/{}", self.random_string(8)),
+            "uuid" => uuid::Uuid::from_u128(self.rng.gen()).to_string(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn array(&mut self, schema: &'a Value, map: &'a Map<String, Value>, depth: usize) -> Value {
+        let min_items = map
+            .get("minItems")
+            .and_then(Value::as_u64)
+            .map(|n| n as usize);
+        let max_items = map
+            .get("maxItems")
+            .and_then(Value::as_u64)
+            .map(|n| n as usize);
+        if min_items.is_some_and(|n| n > 0) {
+            self.site(schema, Constraint::MinItems);
+        }
+        if max_items.is_some() {
+            self.site(schema, Constraint::MaxItems);
+        }
+        let min_items = min_items.unwrap_or(0);
+        let max_items = if depth >= self.options.max_depth {
+            min_items
+        } else {
+            max_items
+                .unwrap_or(min_items + self.options.max_items)
+                .max(min_items)
+        };
+        let count = self.rng.gen_range(min_items..=max_items);
+
+        let tuple = map
+            .get("prefixItems")
+            .or_else(|| map.get("items").filter(|items| items.is_array()))
+            .and_then(Value::as_array);
+        let items = map.get("items").filter(|items| !items.is_array());
+
+        let mut values = Vec::with_capacity(count);
+        for index in 0..count {
+            self.path.push(PathSegment::Index(index));
+            let value = match tuple.and_then(|tuple| tuple.get(index)) {
+                Some(item_schema) => self.value(item_schema, depth + 1),
+                None => match items {
+                    Some(item_schema) => self.value(item_schema, depth + 1),
+                    None => self.any_value(depth + 1),
+                },
+            };
+            self.path.pop();
+            values.push(value);
+        }
+        Value::Array(values)
+    }
+
+    fn object(&mut self, schema: &'a Value, map: &'a Map<String, Value>, depth: usize) -> Value {
+        let required: Vec<&str> = map
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|keys| keys.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let properties = map.get("properties").and_then(Value::as_object);
+
+        let mut object = Map::new();
+        if let Some(properties) = properties {
+            for (key, property_schema) in properties {
+                let is_required = required.contains(&key.as_str());
+                if !is_required
+                    && (depth >= self.options.max_depth
+                        || !self.rng.gen_bool(self.options.optional_property_rate))
+                {
+                    continue;
+                }
+                self.path.push(PathSegment::Key(key.clone()));
+                let value = self.value(property_schema, depth + 1);
+                self.path.pop();
+                object.insert(key.clone(), value);
+            }
+        }
+        for key in &required {
+            if !object.contains_key(*key) {
+                self.path.push(PathSegment::Key(key.to_string()));
+                let value = self.any_value(depth + 1);
+                self.path.pop();
+                object.insert(key.to_string(), value);
+            }
+            self.site(schema, Constraint::Required(key.to_string()));
+        }
+
+        match map.get("additionalProperties") {
+            Some(Value::Bool(false)) => self.site(schema, Constraint::AdditionalProperties),
+            Some(extra_schema @ Value::Object(_)) if depth < self.options.max_depth => {
+                for _ in 0..self.rng.gen_range(0..=2) {
+                    let key = self.random_string(6);
+                    self.path.push(PathSegment::Key(key.clone()));
+                    let value = self.value(extra_schema, depth + 1);
+                    self.path.pop();
+                    object.entry(key).or_insert(value);
+                }
+            }
+            _ => {}
+        }
+        Value::Object(object)
+    }
+
+    fn any_value(&mut self, depth: usize) -> Value {
+        let choices = if depth >= self.options.max_depth {
+            5
+        } else {
+            7
+        };
+        match self.rng.gen_range(0..choices) {
+            0 => Value::Null,
+            1 => Value::Bool(self.rng.gen()),
+            2 => json!(self.rng.gen::<i32>()),
+            3 => Number::from_f64(self.rng.gen_range(-1e6..1e6)).map_or(Value::Null, Value::Number),
+            4 => Value::String(self.random_string(self.options.max_string_length / 2)),
+            5 => Value::Array(
+                (0..self.rng.gen_range(0..3))
+                    .map(|_| self.any_value(depth + 1))
+                    .collect(),
+            ),
+            _ => {
+                let mut object = Map::new();
+                for _ in 0..self.rng.gen_range(0..3) {
+                    let key = self.random_string(6);
+                    let value = self.any_value(depth + 1);
+                    object.insert(key, value);
+                }
+                Value::Object(object)
+            }
+        }
+    }
+
+    fn random_string(&mut self, len: usize) -> String {
+        const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        (0..len)
+            .map(|_| CHARSET[self.rng.gen_range(0..CHARSET.len())] as char)
+            .collect()
+    }
+
+    /// A value for the site that breaks its constraint and no other one of the same schema, or
+    /// `None` if there is none.
+    fn violate(&mut self, site: &ViolationSite<'a>, current: &Value) -> Option<Value> {
+        let Value::Object(map) = site.schema else {
+            return None;
+        };
+        match &site.constraint {
+            Constraint::Type => {
+                let allowed = allowed_types(map);
+                let mut candidates: Vec<&str> = JSON_TYPES
+                    .iter()
+                    .copied()
+                    .filter(|kind| !allowed.contains(kind))
+                    .collect();
+                // Every integer is also a number, so only fractional values escape "number".
+                if allowed.contains(&"number") {
+                    candidates.retain(|kind| *kind != "integer");
+                }
+                // A fraction escapes "integer", but must still satisfy the numeric keywords.
+                let (low, high) = numeric_range(map);
+                let fraction = ((low + high) / 2.0).floor() + 0.5;
+                if map.contains_key("multipleOf") || fraction < low || fraction > high {
+                    candidates.retain(|kind| *kind != "number");
+                }
+                if candidates.is_empty() {
+                    return None;
+                }
+                Some(match candidates[self.rng.gen_range(0..candidates.len())] {
+                    "null" => Value::Null,
+                    "boolean" => Value::Bool(self.rng.gen()),
+                    "integer" => json!(self.rng.gen::<i32>()),
+                    "number" => json!(fraction),
+                    "string" => Value::String(self.random_string(4)),
+                    "array" => Value::Array(vec![current.clone()]),
+                    _ => json!({ "value": current }),
+                })
+            }
+            Constraint::Enum | Constraint::Const => {
+                let forbidden: Vec<&Value> = match map.get("enum") {
+                    Some(Value::Array(values)) => values.iter().collect(),
+                    _ => map.get("const").into_iter().collect(),
+                };
+                (0..16)
+                    .map(|_| match current {
+                        Value::String(_) => Value::String(self.random_string(6)),
+                        Value::Number(_) => json!(self.rng.gen::<i32>()),
+                        _ => self.any_value(self.options.max_depth),
+                    })
+                    .find(|candidate| !forbidden.contains(&candidate))
+            }
+            Constraint::Minimum
+            | Constraint::Maximum
+            | Constraint::ExclusiveMinimum
+            | Constraint::ExclusiveMaximum => bound_violation(map, &site.constraint),
+            Constraint::MultipleOf => {
+                let value = current.as_f64()?;
+                let (low, high) = numeric_range(map);
+                let offset = match map.get("multipleOf")?.as_i64() {
+                    // The neighbours of a multiple of 2 or more are not multiples.
+                    Some(_) if current.is_i64() || current.is_u64() => 1.0,
+                    _ => map.get("multipleOf")?.as_f64()? / 2.0,
+                };
+                let candidate = if value + offset <= high {
+                    value + offset
+                } else if value - offset >= low {
+                    value - offset
+                } else {
+                    return None;
+                };
+                if offset == 1.0 {
+                    Some(json!(candidate as i64))
+                } else {
+                    Number::from_f64(candidate).map(Value::Number)
+                }
+            }
+            Constraint::MinLength => {
+                let min = map.get("minLength").and_then(Value::as_u64).unwrap_or(1) as usize;
+                let shorter: String = current
+                    .as_str()?
+                    .chars()
+                    .take(min.saturating_sub(1))
+                    .collect();
+                self.matches_pattern(map, &shorter)
+                    .then_some(Value::String(shorter))
+            }
+            Constraint::MaxLength => {
+                let max = map.get("maxLength").and_then(Value::as_u64).unwrap_or(0) as usize;
+                let mut longer = current.as_str()?.to_string();
+                while longer.chars().count() <= max {
+                    longer.push('a');
+                }
+                self.matches_pattern(map, &longer)
+                    .then_some(Value::String(longer))
+            }
+            Constraint::Pattern => {
+                let pattern = map.get("pattern").and_then(Value::as_str)?;
+                let (_, regex) = self.schema.patterns.get(pattern)?;
+                let min = map.get("minLength").and_then(Value::as_u64).unwrap_or(0) as usize;
+                let max = map
+                    .get("maxLength")
+                    .and_then(Value::as_u64)
+                    .map_or(usize::MAX, |n| n as usize);
+                let base = current.as_str().unwrap_or_default().to_string();
+                for attempt in 0..32 {
+                    let candidate = match attempt % 3 {
+                        0 => format!("{} ", base),
+                        1 => base.chars().skip(1).collect(),
+                        _ => self.random_string(base.chars().count().max(1)),
+                    };
+                    let len = candidate.chars().count();
+                    if len >= min && len <= max && !regex.is_match(&candidate) {
+                        return Some(Value::String(candidate));
+                    }
+                }
+                None
+            }
+            Constraint::MinItems => {
+                let min = map.get("minItems").and_then(Value::as_u64).unwrap_or(1) as usize;
+                let mut items = current.as_array()?.clone();
+                items.truncate(min.saturating_sub(1));
+                Some(Value::Array(items))
+            }
+            Constraint::MaxItems => {
+                // Repeated items break `uniqueItems`, and made-up ones the item schemas.
+                if map.get("uniqueItems") == Some(&Value::Bool(true)) {
+                    return None;
+                }
+                let max = map.get("maxItems").and_then(Value::as_u64).unwrap_or(0) as usize;
+                let mut items = current.as_array()?.clone();
+                let filler = match items.last() {
+                    Some(item) => item.clone(),
+                    None if map.contains_key("items") || map.contains_key("prefixItems") => {
+                        return None
+                    }
+                    None => Value::Null,
+                };
+                while items.len() <= max {
+                    items.push(filler.clone());
+                }
+                Some(Value::Array(items))
+            }
+            Constraint::Required(key) => {
+                let mut object = current.as_object()?.clone();
+                object.remove(key);
+                Some(Value::Object(object))
+            }
+            Constraint::AdditionalProperties => {
+                let mut object = current.as_object()?.clone();
+                let mut key = "additional".to_string();
+                let properties = map.get("properties").and_then(Value::as_object);
+                while properties.is_some_and(|properties| properties.contains_key(&key)) {
+                    key.push('_');
+                }
+                object.insert(key, Value::Null);
+                Some(Value::Object(object))
+            }
+        }
+    }
+
+    /// True if the schema has no pattern or `text` matches it.
+    fn matches_pattern(&self, map: &Map<String, Value>, text: &str) -> bool {
+        match map.get("pattern").and_then(Value::as_str) {
+            Some(pattern) => self
+                .schema
+                .patterns
+                .get(pattern)
+                .is_some_and(|(_, regex)| regex.is_match(text)),
+            None => true,
+        }
+    }
+}
+
+/// The known types listed by the `type` keyword.
+fn declared_types(map: &Map<String, Value>) -> Vec<&'static str> {
+    let declared: Vec<&str> = match map.get("type") {
+        Some(Value::String(kind)) => vec![kind.as_str()],
+        Some(Value::Array(kinds)) => kinds.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    JSON_TYPES
+        .iter()
+        .copied()
+        .filter(|kind| declared.contains(kind))
+        .collect()
+}
+
+fn allowed_types(map: &Map<String, Value>) -> Vec<&'static str> {
+    let mut types = declared_types(map);
+    if types.is_empty() {
+        types = if map.contains_key("properties") || map.contains_key("required") {
+            vec!["object"]
+        } else if map.contains_key("items") || map.contains_key("prefixItems") {
+            vec!["array"]
+        } else if map.contains_key("pattern") || map.contains_key("minLength") {
+            vec!["string"]
+        } else if map.contains_key("minimum") || map.contains_key("maximum") {
+            vec!["number"]
+        } else {
+            JSON_TYPES.to_vec()
+        };
+    }
+    types
+}
+
+/// The range the numeric keywords of a schema allow, with exclusive bounds moved inside.
+fn numeric_range(map: &Map<String, Value>) -> (f64, f64) {
+    let mut low = -1e6;
+    let mut high = 1e6;
+    if let Some(minimum) = map.get("minimum").and_then(Value::as_f64) {
+        low = minimum;
+        // Draft 4 expresses exclusivity as a boolean next to `minimum`.
+        if map.get("exclusiveMinimum") == Some(&Value::Bool(true)) {
+            low = next_up(minimum);
+        }
+    }
+    if let Some(minimum) = map.get("exclusiveMinimum").and_then(Value::as_f64) {
+        low = next_up(minimum);
+    }
+    if let Some(maximum) = map.get("maximum").and_then(Value::as_f64) {
+        high = maximum;
+        if map.get("exclusiveMaximum") == Some(&Value::Bool(true)) {
+            high = next_down(maximum);
+        }
+    }
+    if let Some(maximum) = map.get("exclusiveMaximum").and_then(Value::as_f64) {
+        high = next_down(maximum);
+    }
+    (low, high.max(low))
+}
+
+/// The closest value on the wrong side of a bound that still has the schema's numeric type and
+/// step. `None` if the bound comes with another one on the same side, which the value would
+/// break too or not at all, or with a fractional step.
+fn bound_violation(map: &Map<String, Value>, constraint: &Constraint) -> Option<Value> {
+    let (keyword, other, below) = match constraint {
+        Constraint::Minimum => ("minimum", "exclusiveMinimum", true),
+        Constraint::ExclusiveMinimum => ("exclusiveMinimum", "minimum", true),
+        Constraint::Maximum => ("maximum", "exclusiveMaximum", false),
+        Constraint::ExclusiveMaximum => ("exclusiveMaximum", "maximum", false),
+        _ => return None,
+    };
+    if map.get(other).is_some_and(Value::is_number) {
+        return None;
+    }
+    let exclusive = keyword == "exclusiveMinimum"
+        || keyword == "exclusiveMaximum"
+        || map.get(other) == Some(&Value::Bool(true));
+    let step = match map.get("multipleOf") {
+        Some(step) => step.as_i64().filter(|&step| step > 0)?,
+        None if allowed_types(map).contains(&"number") => {
+            let offset = match (exclusive, below) {
+                // The exclusive bound itself is the closest value that fails.
+                (true, _) => 0.0,
+                (false, true) => -1.0,
+                (false, false) => 1.0,
+            };
+            return Some(offset_number(map, keyword, offset));
+        }
+        None => 1,
+    };
+    let bound = map.get(keyword)?.as_f64()?;
+    if bound.abs() > 1e15 {
+        return None;
+    }
+    let value = if below {
+        let limit = if exclusive {
+            bound.floor()
+        } else {
+            bound.ceil() - 1.0
+        } as i64;
+        limit - limit.rem_euclid(step)
+    } else {
+        let limit = if exclusive {
+            bound.ceil()
+        } else {
+            bound.floor() + 1.0
+        } as i64;
+        limit + (step - limit.rem_euclid(step)) % step
+    };
+    Some(json!(value))
+}
+
+fn offset_number(map: &Map<String, Value>, keyword: &str, offset: f64) -> Value {
+    match map.get(keyword) {
+        Some(Value::Number(bound)) => match bound.as_i64() {
+            Some(bound) => json!(bound.saturating_add(offset as i64)),
+            None => {
+                let bound = bound.as_f64().unwrap_or_default();
+                let value = if offset < 0.0 {
+                    next_down(bound)
+                } else if offset > 0.0 {
+                    next_up(bound)
+                } else {
+                    bound
+                };
+                Number::from_f64(value).map_or(Value::Null, Value::Number)
+            }
+        },
+        _ => Value::Null,
+    }
+}
+
+fn next_up(value: f64) -> f64 {
+    value + value.abs().max(1.0) * f64::EPSILON
+}
+
+fn next_down(value: f64) -> f64 {
+    value - value.abs().max(1.0) * f64::EPSILON
+}
+
+fn value_at_path<'v>(value: &'v mut Value, path: &[PathSegment]) -> Option<&'v mut Value> {
+    let mut value = value;
+    for segment in path {
+        value = match segment {
+            PathSegment::Key(key) => value.get_mut(key.as_str())?,
+            PathSegment::Index(index) => value.get_mut(*index)?,
+        };
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn documents(schema: Value, near_miss_rate: f64) -> Vec<Value> {
+        let schema = JsonSchema::from_value(schema).unwrap();
+        let options = JsonSchemaOptions {
+            near_miss_rate,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+        (0..500)
+            .map(|_| schema.generate(&options, &mut rng))
+            .collect()
+    }
+
+    #[test]
+    fn steps_stay_within_bounds() {
+        let schema = json!({ "type": "integer", "minimum": 1, "maximum": 10, "multipleOf": 4 });
+        for document in documents(schema, 0.0) {
+            assert!(matches!(document.as_i64(), Some(4 | 8)), "{}", document);
+        }
+    }
+
+    #[test]
+    fn numeric_near_misses_break_one_constraint() {
+        let schema = json!({ "type": "integer", "minimum": 1, "maximum": 20, "multipleOf": 5 });
+        for document in documents(schema, 1.0) {
+            // Other types are only checked by `type`.
+            let Some(value) = document.as_i64() else {
+                assert!(!document.is_number(), "{}", document);
+                continue;
+            };
+            let broken = [value < 1, value > 20, value % 5 != 0];
+            assert_eq!(broken.iter().filter(|&&b| b).count(), 1, "{}", value);
+        }
+    }
+
+    #[test]
+    fn string_near_misses_break_one_constraint() {
+        let schema = json!({
+            "type": "string",
+            "pattern": "^[a-z]+$",
+            "minLength": 3,
+            "maxLength": 5
+        });
+        let pattern = Regex::new("^[a-z]+$").unwrap();
+        for document in documents(schema, 1.0) {
+            let Some(text) = document.as_str() else {
+                continue;
+            };
+            let len = text.chars().count();
+            let broken = [!pattern.is_match(text), len < 3, len > 5];
+            assert_eq!(broken.iter().filter(|&&b| b).count(), 1, "{:?}", text);
+        }
+    }
+}
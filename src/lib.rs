@@ -1,19 +1,40 @@
 pub mod allocator;
 pub mod analysis;
+pub mod bincode;
+pub mod cbor;
 pub mod config;
 pub mod coverage;
 pub mod crash_analysis;
+pub mod differential;
 pub mod distributed;
+pub mod encoding;
 pub mod errors;
 pub mod fuzz_engine;
+pub mod http;
 pub mod input;
+pub mod json_schema;
 pub mod logger;
+pub mod msgpack;
 pub mod mutator;
 pub mod mutator_options;
+pub mod network;
+pub mod ngram;
+pub mod property;
+pub mod protobuf;
+pub mod protocol;
+pub mod regex_gen;
+pub mod regions;
 
 pub mod reproducer;
+pub mod scheduler;
+pub mod sequence;
+pub mod sql;
+pub mod structured;
 pub mod target;
+pub mod template;
+pub mod unicode;
 pub mod utils;
+pub mod xml;
 pub use crate::config::FuzzerConfig;
 pub use crate::errors::FuzzerError;
 pub use crate::fuzz_engine::Fuzzer;
@@ -0,0 +1,660 @@
+use crate::utils::{random_text, special_float};
+use rand::{prelude::StdRng, Rng};
+
+const MAX_DECODE_DEPTH: usize = 256;
+const INTERESTING_UINTS: [u64; 10] = [
+    0,
+    127,
+    128,
+    255,
+    256,
+    65535,
+    65536,
+    u32::MAX as u64,
+    u32::MAX as u64 + 1,
+    u64::MAX,
+];
+const INTERESTING_INTS: [i64; 10] = [
+    -1,
+    -32,
+    -33,
+    -128,
+    -129,
+    -32768,
+    -32769,
+    i32::MIN as i64,
+    i64::MIN,
+    i64::MAX,
+];
+const INTERESTING_LENGTHS: [usize; 8] = [0, 1, 15, 16, 31, 32, 255, 256];
+
+#[derive(Debug, Clone)]
+pub struct MessagePackOptions {
+    pub max_depth: usize,
+    pub max_items: usize,
+    pub max_length: usize,
+    /// Probability of encoding a value with a wider header than it needs.
+    pub non_canonical_rate: f64,
+    pub ext_rate: f64,
+}
+
+impl Default for MessagePackOptions {
+    fn default() -> Self {
+        MessagePackOptions {
+            max_depth: 5,
+            max_items: 6,
+            max_length: 32,
+            non_canonical_rate: 0.1,
+            ext_rate: 0.05,
+        }
+    }
+}
+
+/// Header width of an encoded value. Encoding uses the wider of the recorded width and the
+/// smallest one that fits, so parsed non-canonical encodings survive re-encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Width {
+    Fix,
+    W8,
+    W16,
+    W32,
+    W64,
+}
+
+const WIDTHS: [Width; 5] = [Width::Fix, Width::W8, Width::W16, Width::W32, Width::W64];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessagePackValue {
+    Nil,
+    Bool(bool),
+    Uint(u64, Width),
+    Int(i64, Width),
+    F32(f32),
+    F64(f64),
+    Str(Vec<u8>, Width),
+    Bin(Vec<u8>, Width),
+    Array(Vec<MessagePackValue>, Width),
+    Map(Vec<(MessagePackValue, MessagePackValue)>, Width),
+    Ext(i8, Vec<u8>, Width),
+}
+
+impl MessagePackValue {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            MessagePackValue::Nil => out.push(0xC0),
+            MessagePackValue::Bool(b) => out.push(if *b { 0xC3 } else { 0xC2 }),
+            MessagePackValue::Uint(v, width) => write_header(
+                out,
+                *v,
+                *width,
+                Some((0x00, 0x7F)),
+                [Some(0xCC), Some(0xCD), Some(0xCE), Some(0xCF)],
+            ),
+            MessagePackValue::Int(v, width) => write_int(out, *v, *width),
+            MessagePackValue::F32(f) => {
+                out.push(0xCA);
+                out.extend_from_slice(&f.to_be_bytes());
+            }
+            MessagePackValue::F64(f) => {
+                out.push(0xCB);
+                out.extend_from_slice(&f.to_be_bytes());
+            }
+            MessagePackValue::Str(bytes, width) => {
+                write_header(
+                    out,
+                    bytes.len() as u64,
+                    *width,
+                    Some((0xA0, 31)),
+                    [Some(0xD9), Some(0xDA), Some(0xDB), None],
+                );
+                out.extend_from_slice(bytes);
+            }
+            MessagePackValue::Bin(bytes, width) => {
+                write_header(
+                    out,
+                    bytes.len() as u64,
+                    *width,
+                    None,
+                    [Some(0xC4), Some(0xC5), Some(0xC6), None],
+                );
+                out.extend_from_slice(bytes);
+            }
+            MessagePackValue::Array(items, width) => {
+                write_header(
+                    out,
+                    items.len() as u64,
+                    *width,
+                    Some((0x90, 15)),
+                    [None, Some(0xDC), Some(0xDD), None],
+                );
+                for item in items {
+                    item.encode_into(out);
+                }
+            }
+            MessagePackValue::Map(entries, width) => {
+                write_header(
+                    out,
+                    entries.len() as u64,
+                    *width,
+                    Some((0x80, 15)),
+                    [None, Some(0xDE), Some(0xDF), None],
+                );
+                for (key, value) in entries {
+                    key.encode_into(out);
+                    value.encode_into(out);
+                }
+            }
+            MessagePackValue::Ext(ty, data, width) => {
+                let fixext = [1, 2, 4, 8, 16].iter().position(|&len| len == data.len());
+                match fixext {
+                    Some(index) if *width == Width::Fix => out.push(0xD4 + index as u8),
+                    _ => write_header(
+                        out,
+                        data.len() as u64,
+                        (*width).max(Width::W8),
+                        None,
+                        [Some(0xC7), Some(0xC8), Some(0xC9), None],
+                    ),
+                }
+                out.push(*ty as u8);
+                out.extend_from_slice(data);
+            }
+        }
+    }
+
+    /// Decodes the first value in the data, ignoring any bytes after it.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        Self::decode_prefix(data).map(|(value, _)| value)
+    }
+
+    /// Decodes the first value in the data and returns it with the number of bytes it took.
+    pub fn decode_prefix(data: &[u8]) -> Option<(Self, usize)> {
+        let mut decoder = Decoder { data, pos: 0 };
+        let value = decoder.value(0)?;
+        Some((value, decoder.pos))
+    }
+}
+
+/// Writes a header for `value` (an integer or a length), using the fix form if there is one.
+/// `codes` are the type bytes of the 8, 16, 32 and 64-bit forms.
+fn write_header(
+    out: &mut Vec<u8>,
+    value: u64,
+    width: Width,
+    fix: Option<(u8, u64)>,
+    codes: [Option<u8>; 4],
+) {
+    let usable = |w: Width| match w {
+        Width::Fix => fix.is_some_and(|(_, max)| value <= max),
+        Width::W8 => codes[0].is_some() && value <= 0xFF,
+        Width::W16 => codes[1].is_some() && value <= 0xFFFF,
+        Width::W32 => codes[2].is_some() && value <= 0xFFFF_FFFF,
+        Width::W64 => codes[3].is_some(),
+    };
+    let chosen = WIDTHS
+        .into_iter()
+        .find(|&w| w >= width && usable(w))
+        .or_else(|| WIDTHS.into_iter().find(|&w| usable(w)))
+        .unwrap_or(Width::W32);
+    match chosen {
+        Width::Fix => out.push(fix.map_or(0, |(base, _)| base) | value as u8),
+        Width::W8 => out.extend_from_slice(&[codes[0].unwrap_or(0), value as u8]),
+        Width::W16 => {
+            out.push(codes[1].unwrap_or(0));
+            out.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+        Width::W32 => {
+            out.push(codes[2].unwrap_or(0));
+            out.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        Width::W64 => {
+            out.push(codes[3].unwrap_or(0));
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+fn write_int(out: &mut Vec<u8>, value: i64, width: Width) {
+    let fits = |w: Width| match w {
+        Width::Fix => (-32..=127).contains(&value),
+        Width::W8 => i8::try_from(value).is_ok(),
+        Width::W16 => i16::try_from(value).is_ok(),
+        Width::W32 => i32::try_from(value).is_ok(),
+        Width::W64 => true,
+    };
+    let chosen = WIDTHS
+        .into_iter()
+        .find(|&w| w >= width && fits(w))
+        .unwrap_or(Width::W64);
+    match chosen {
+        Width::Fix => out.push(value as u8),
+        Width::W8 => out.extend_from_slice(&[0xD0, value as u8]),
+        Width::W16 => {
+            out.push(0xD1);
+            out.extend_from_slice(&(value as i16).to_be_bytes());
+        }
+        Width::W32 => {
+            out.push(0xD2);
+            out.extend_from_slice(&(value as i32).to_be_bytes());
+        }
+        Width::W64 => {
+            out.push(0xD3);
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        let end = self.pos.checked_add(len)?;
+        let bytes = self.data.get(self.pos..end)?;
+        self.pos = end;
+        Some(bytes)
+    }
+
+    fn uint(&mut self, len: usize) -> Option<u64> {
+        let bytes = self.take(len)?;
+        Some(bytes.iter().fold(0u64, |acc, &b| (acc << 8) | u64::from(b)))
+    }
+
+    fn bytes(&mut self, len: u64) -> Option<Vec<u8>> {
+        self.take(usize::try_from(len).ok()?).map(<[u8]>::to_vec)
+    }
+
+    fn items(&mut self, len: u64, depth: usize) -> Option<Vec<MessagePackValue>> {
+        // Every item takes at least one byte, which bounds the allocation.
+        if len > (self.data.len() - self.pos) as u64 {
+            return None;
+        }
+        (0..len).map(|_| self.value(depth + 1)).collect()
+    }
+
+    fn entries(
+        &mut self,
+        len: u64,
+        depth: usize,
+    ) -> Option<Vec<(MessagePackValue, MessagePackValue)>> {
+        if len > (self.data.len() - self.pos) as u64 / 2 {
+            return None;
+        }
+        (0..len)
+            .map(|_| Some((self.value(depth + 1)?, self.value(depth + 1)?)))
+            .collect()
+    }
+
+    fn value(&mut self, depth: usize) -> Option<MessagePackValue> {
+        if depth > MAX_DECODE_DEPTH {
+            return None;
+        }
+        let byte = self.take(1)?[0];
+        use MessagePackValue as V;
+        Some(match byte {
+            0x00..=0x7F => V::Uint(u64::from(byte), Width::Fix),
+            0x80..=0x8F => V::Map(self.entries(u64::from(byte & 0x0F), depth)?, Width::Fix),
+            0x90..=0x9F => V::Array(self.items(u64::from(byte & 0x0F), depth)?, Width::Fix),
+            0xA0..=0xBF => V::Str(self.bytes(u64::from(byte & 0x1F))?, Width::Fix),
+            0xC0 => V::Nil,
+            0xC2 => V::Bool(false),
+            0xC3 => V::Bool(true),
+            0xC4..=0xC6 => {
+                let width = [Width::W8, Width::W16, Width::W32][(byte - 0xC4) as usize];
+                let len = self.uint(1 << (byte - 0xC4))?;
+                V::Bin(self.bytes(len)?, width)
+            }
+            0xC7..=0xC9 => {
+                let width = [Width::W8, Width::W16, Width::W32][(byte - 0xC7) as usize];
+                let len = self.uint(1 << (byte - 0xC7))?;
+                let ty = self.take(1)?[0] as i8;
+                V::Ext(ty, self.bytes(len)?, width)
+            }
+            0xCA => V::F32(f32::from_bits(self.uint(4)? as u32)),
+            0xCB => V::F64(f64::from_bits(self.uint(8)?)),
+            0xCC..=0xCF => {
+                let index = (byte - 0xCC) as usize;
+                V::Uint(self.uint(1 << index)?, WIDTHS[index + 1])
+            }
+            0xD0..=0xD3 => {
+                let index = (byte - 0xD0) as usize;
+                let len = 1 << index;
+                let raw = self.uint(len)?;
+                // Sign-extend from the encoded width.
+                let shift = 64 - 8 * len as u32;
+                V::Int(((raw << shift) as i64) >> shift, WIDTHS[index + 1])
+            }
+            0xD4..=0xD8 => {
+                let ty = self.take(1)?[0] as i8;
+                V::Ext(ty, self.bytes(1 << (byte - 0xD4))?, Width::Fix)
+            }
+            0xD9..=0xDB => {
+                let index = (byte - 0xD9) as usize;
+                let len = self.uint(1 << index)?;
+                V::Str(self.bytes(len)?, WIDTHS[index + 1])
+            }
+            0xDC | 0xDD => {
+                let len = self.uint(if byte == 0xDC { 2 } else { 4 })?;
+                let width = if byte == 0xDC { Width::W16 } else { Width::W32 };
+                V::Array(self.items(len, depth)?, width)
+            }
+            0xDE | 0xDF => {
+                let len = self.uint(if byte == 0xDE { 2 } else { 4 })?;
+                let width = if byte == 0xDE { Width::W16 } else { Width::W32 };
+                V::Map(self.entries(len, depth)?, width)
+            }
+            0xE0..=0xFF => V::Int(i64::from(byte as i8), Width::Fix),
+            // 0xC1 is never used.
+            _ => return None,
+        })
+    }
+}
+
+pub fn generate(options: &MessagePackOptions, rng: &mut StdRng) -> Vec<u8> {
+    let value = if rng.gen_bool(0.8) {
+        generate_container(options, rng, 0)
+    } else {
+        generate_value(options, rng, 0)
+    };
+    value.encode()
+}
+
+/// Decodes the first value in the data, mutates one value in its tree and re-encodes it,
+/// followed by the bytes after it unchanged. Returns `None` if the data does not start with a
+/// MessagePack value.
+pub fn mutate(options: &MessagePackOptions, rng: &mut StdRng, data: &[u8]) -> Option<Vec<u8>> {
+    let (mut root, len) = MessagePackValue::decode_prefix(data)?;
+    let mut paths = Vec::new();
+    collect_paths(&root, &mut Vec::new(), &mut paths);
+    let path = &paths[rng.gen_range(0..paths.len())];
+    let depth = path.len();
+    mutate_value(options, rng, node_at(&mut root, path)?, depth);
+    let mut mutated = root.encode();
+    mutated.extend_from_slice(&data[len..]);
+    Some(mutated)
+}
+
+fn generate_container(
+    options: &MessagePackOptions,
+    rng: &mut StdRng,
+    depth: usize,
+) -> MessagePackValue {
+    let len = rng.gen_range(0..=options.max_items);
+    let width = random_width(options, rng, len <= 15);
+    if rng.gen_bool(0.5) {
+        MessagePackValue::Array(
+            (0..len)
+                .map(|_| generate_value(options, rng, depth + 1))
+                .collect(),
+            width,
+        )
+    } else {
+        MessagePackValue::Map(
+            (0..len)
+                .map(|_| {
+                    let key = if rng.gen_bool(0.8) {
+                        let len = rng.gen_range(1..=8);
+                        MessagePackValue::Str(random_text(rng, len), Width::Fix)
+                    } else {
+                        generate_value(options, rng, options.max_depth)
+                    };
+                    (key, generate_value(options, rng, depth + 1))
+                })
+                .collect(),
+            width,
+        )
+    }
+}
+
+fn generate_value(
+    options: &MessagePackOptions,
+    rng: &mut StdRng,
+    depth: usize,
+) -> MessagePackValue {
+    let choice = if depth >= options.max_depth {
+        rng.gen_range(0..8)
+    } else {
+        rng.gen_range(0..10)
+    };
+    match choice {
+        0 => MessagePackValue::Nil,
+        1 => MessagePackValue::Bool(rng.gen()),
+        2 => {
+            let value = if rng.gen_bool(0.3) {
+                INTERESTING_UINTS[rng.gen_range(0..INTERESTING_UINTS.len())]
+            } else {
+                rng.gen_range(0..1000)
+            };
+            MessagePackValue::Uint(value, random_width(options, rng, true))
+        }
+        3 => {
+            let value = if rng.gen_bool(0.3) {
+                INTERESTING_INTS[rng.gen_range(0..INTERESTING_INTS.len())]
+            } else {
+                rng.gen_range(-1000..0)
+            };
+            MessagePackValue::Int(value, random_width(options, rng, true))
+        }
+        4 => {
+            if rng.gen_bool(0.5) {
+                MessagePackValue::F32(rng.gen())
+            } else {
+                MessagePackValue::F64(special_float(rng))
+            }
+        }
+        5 | 6 => {
+            let len = rng.gen_range(0..=options.max_length);
+            MessagePackValue::Str(random_text(rng, len), random_width(options, rng, len <= 31))
+        }
+        7 => {
+            if rng.gen_bool(options.ext_rate) {
+                let len = [1, 2, 4, 8, 16, 3][rng.gen_range(0..6)];
+                let data = (0..len).map(|_| rng.gen()).collect();
+                MessagePackValue::Ext(rng.gen(), data, random_width(options, rng, true))
+            } else {
+                let len = rng.gen_range(0..=options.max_length);
+                let data = (0..len).map(|_| rng.gen()).collect();
+                MessagePackValue::Bin(data, random_width(options, rng, false))
+            }
+        }
+        _ => generate_container(options, rng, depth),
+    }
+}
+
+fn mutate_value(
+    options: &MessagePackOptions,
+    rng: &mut StdRng,
+    value: &mut MessagePackValue,
+    depth: usize,
+) {
+    use MessagePackValue as V;
+    if rng.gen_bool(0.2) {
+        *value = generate_value(options, rng, depth);
+        return;
+    }
+    let wider = WIDTHS[rng.gen_range(1..WIDTHS.len())];
+    match value {
+        V::Nil | V::Bool(_) => *value = generate_value(options, rng, depth),
+        V::Uint(v, width) => {
+            if rng.gen_bool(0.3) {
+                *width = wider;
+            } else {
+                *v = INTERESTING_UINTS[rng.gen_range(0..INTERESTING_UINTS.len())];
+            }
+        }
+        V::Int(v, width) => {
+            if rng.gen_bool(0.3) {
+                *width = wider;
+            } else {
+                *v = INTERESTING_INTS[rng.gen_range(0..INTERESTING_INTS.len())];
+            }
+        }
+        V::F32(f) => *f = special_float(rng) as f32,
+        V::F64(f) => *f = special_float(rng),
+        V::Str(bytes, width) | V::Bin(bytes, width) | V::Ext(_, bytes, width) => {
+            match rng.gen_range(0..4) {
+                0 => *width = wider,
+                1 => {
+                    let len = INTERESTING_LENGTHS[rng.gen_range(0..INTERESTING_LENGTHS.len())];
+                    bytes.resize(len, b'a');
+                }
+                2 if !bytes.is_empty() => {
+                    let index = rng.gen_range(0..bytes.len());
+                    bytes[index] = [0x00, 0xFF, 0xC0, 0x80, b'"'][rng.gen_range(0..5)];
+                }
+                _ => {
+                    let len = rng.gen_range(0..=options.max_length);
+                    *bytes = random_text(rng, len);
+                }
+            }
+        }
+        V::Array(items, width) => match rng.gen_range(0..4) {
+            0 => *width = wider,
+            1 if !items.is_empty() => {
+                items.remove(rng.gen_range(0..items.len()));
+            }
+            2 if !items.is_empty() => {
+                let index = rng.gen_range(0..items.len());
+                items.insert(index, items[index].clone());
+            }
+            _ => {
+                let at = rng.gen_range(0..=items.len());
+                items.insert(at, generate_value(options, rng, depth + 1));
+            }
+        },
+        V::Map(entries, width) => match rng.gen_range(0..4) {
+            0 => *width = wider,
+            1 if !entries.is_empty() => {
+                entries.remove(rng.gen_range(0..entries.len()));
+            }
+            // Duplicate keys are valid on the wire but handled inconsistently by decoders.
+            2 if !entries.is_empty() => {
+                let index = rng.gen_range(0..entries.len());
+                let mut entry = entries[index].clone();
+                entry.1 = generate_value(options, rng, depth + 1);
+                entries.push(entry);
+            }
+            _ => {
+                let len = rng.gen_range(1..=8);
+                let key = V::Str(random_text(rng, len), Width::Fix);
+                entries.push((key, generate_value(options, rng, depth + 1)));
+            }
+        },
+    }
+}
+
+/// Lists the paths of all values in the tree. For maps, child `2 * i` is the key of entry `i`
+/// and `2 * i + 1` its value.
+fn collect_paths(value: &MessagePackValue, prefix: &mut Vec<usize>, paths: &mut Vec<Vec<usize>>) {
+    paths.push(prefix.clone());
+    match value {
+        MessagePackValue::Array(items, _) => {
+            for (i, item) in items.iter().enumerate() {
+                prefix.push(i);
+                collect_paths(item, prefix, paths);
+                prefix.pop();
+            }
+        }
+        MessagePackValue::Map(entries, _) => {
+            for (i, (key, value)) in entries.iter().enumerate() {
+                prefix.push(2 * i);
+                collect_paths(key, prefix, paths);
+                prefix.pop();
+                prefix.push(2 * i + 1);
+                collect_paths(value, prefix, paths);
+                prefix.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+fn node_at<'a>(
+    value: &'a mut MessagePackValue,
+    path: &[usize],
+) -> Option<&'a mut MessagePackValue> {
+    let Some((&first, rest)) = path.split_first() else {
+        return Some(value);
+    };
+    let child = match value {
+        MessagePackValue::Array(items, _) => items.get_mut(first)?,
+        MessagePackValue::Map(entries, _) => {
+            let entry = entries.get_mut(first / 2)?;
+            if first % 2 == 0 {
+                &mut entry.0
+            } else {
+                &mut entry.1
+            }
+        }
+        _ => return None,
+    };
+    node_at(child, rest)
+}
+
+fn random_width(options: &MessagePackOptions, rng: &mut StdRng, fix: bool) -> Width {
+    if rng.gen_bool(options.non_canonical_rate) {
+        WIDTHS[rng.gen_range(1..WIDTHS.len())]
+    } else if fix {
+        Width::Fix
+    } else {
+        Width::W8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn generated_values_round_trip() {
+        let options = MessagePackOptions::default();
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..200 {
+            let data = generate(&options, &mut rng);
+            let (value, len) = MessagePackValue::decode_prefix(&data).unwrap();
+            assert_eq!(len, data.len());
+            assert_eq!(value.encode(), data);
+        }
+    }
+
+    #[test]
+    fn decodes_known_encodings() {
+        let value = MessagePackValue::decode(&[0x92, 0x01, 0xD9, 0x03, b'a', b'b', b'c']).unwrap();
+        assert_eq!(
+            value,
+            MessagePackValue::Array(
+                vec![
+                    MessagePackValue::Uint(1, Width::Fix),
+                    MessagePackValue::Str(b"abc".to_vec(), Width::W8),
+                ],
+                Width::Fix
+            )
+        );
+        // The wide string header survives re-encoding.
+        assert_eq!(value.encode()[2], 0xD9);
+        assert_eq!(MessagePackValue::decode(&[0xA3, b'a']), None);
+        assert_eq!(MessagePackValue::decode(&[0xC1]), None);
+    }
+
+    #[test]
+    fn mutation_keeps_trailing_bytes() {
+        let options = MessagePackOptions::default();
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..200 {
+            let mut data = generate(&options, &mut rng);
+            data.extend_from_slice(b"tail");
+            let mutated = mutate(&options, &mut rng, &data).unwrap();
+            let (_, len) = MessagePackValue::decode_prefix(&mutated).unwrap();
+            assert_eq!(&mutated[len..], b"tail");
+        }
+    }
+}
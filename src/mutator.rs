@@ -1,15 +1,38 @@
 use crate::{
+    bincode::{self, BincodeLayout, BincodeOptions},
+    cbor::{self, CborOptions},
     coverage::CoverageTracker,
+    encoding::{EncodingChain, EncodingLayer},
     errors::FuzzerError,
+    http,
+    msgpack::{self, MessagePackOptions},
     mutator_options::{MutationType, MutatorOptions},
+    regions::{RegionKind, RegionMap, RegionSource},
+    template::BinaryTemplate,
+    unicode,
+    utils::InputFormat,
 };
 use rand::{prelude::StdRng, Rng, SeedableRng};
+use std::{collections::HashMap, ops::Range, sync::Arc};
 #[derive(Debug, Clone)]
 pub struct Mutator {
     options: MutatorOptions,
     rng: StdRng,
     corpus: Vec<Vec<u8>>,
     coverage_tracker: Option<CoverageTracker>,
+    input_format: Option<InputFormat>,
+    template: Option<BinaryTemplate>,
+    messagepack_options: MessagePackOptions,
+    cbor_options: CborOptions,
+    bincode: Option<(BincodeLayout, BincodeOptions)>,
+    encodings: EncodingChain,
+    /// Region maps attached to corpus entries, by index in the corpus.
+    region_maps: HashMap<usize, RegionMap>,
+    region_source: Option<RegionSource>,
+    /// The map attached to the corpus entry being mutated, if any.
+    entry_regions: Option<RegionMap>,
+    /// Regions of the input being mutated, kept in step with insertions and deletions.
+    active_regions: Option<RegionMap>,
 }
 
 impl Mutator {
@@ -20,12 +43,57 @@ impl Mutator {
             rng: StdRng::seed_from_u64(seed),
             corpus: Vec::new(),
             coverage_tracker: None,
+            input_format: None,
+            template: None,
+            messagepack_options: MessagePackOptions::default(),
+            cbor_options: CborOptions::default(),
+            bincode: None,
+            encodings: EncodingChain::new(),
+            region_maps: HashMap::new(),
+            region_source: None,
+            entry_regions: None,
+            active_regions: None,
         }
     }
     pub fn set_coverage_tracker(&mut self, tracker: CoverageTracker) {
         self.coverage_tracker = Some(tracker);
     }
+    pub fn set_input_format(&mut self, format: InputFormat) {
+        self.input_format = Some(format);
+    }
+    pub fn set_template(&mut self, template: BinaryTemplate) {
+        self.template = Some(template);
+    }
+    pub fn set_messagepack_options(&mut self, options: MessagePackOptions) {
+        self.messagepack_options = options;
+    }
+    pub fn set_cbor_options(&mut self, options: CborOptions) {
+        self.cbor_options = options;
+    }
+    pub fn set_bincode_layout(&mut self, layout: BincodeLayout, options: BincodeOptions) {
+        self.bincode = Some((layout, options));
+    }
+    pub fn set_encodings(&mut self, encodings: EncodingChain) {
+        self.encodings = encodings;
+    }
+    /// Adds an encoding layer inside any added before it.
+    pub fn add_encoding_layer<L>(&mut self, layer: L)
+    where
+        L: EncodingLayer + 'static,
+    {
+        self.encodings.push(Arc::new(layer));
+    }
+    pub fn set_region_source(&mut self, source: RegionSource) {
+        self.region_source = Some(source);
+    }
+    /// The region map the region source computes for `data`. `None` if the input is
+    /// unrestricted.
+    pub fn regions_for(&self, data: &[u8]) -> Option<RegionMap> {
+        let regions = self.region_source.as_ref()?.regions(data);
+        (!regions.is_empty()).then_some(regions)
+    }
     pub fn mutate_with_feedback(&mut self, data: &[u8]) -> Result<Vec<u8>, FuzzerError> {
+        self.active_regions = self.regions_for(data);
         let mut mutated = data.to_vec();
         let mutations = self.rng.gen_range(1..=self.options.max_mutations);
 
@@ -46,6 +114,7 @@ impl Mutator {
             }
         }
 
+        self.active_regions = None;
         Ok(mutated)
     }
     fn select_mutation_type(&mut self) -> MutationType {
@@ -53,58 +122,228 @@ impl Mutator {
         self.options.mutation_types[idx].clone()
     }
 
+    /// Mutates the payload inside the encoding layers and wraps the result again. Inputs
+    /// that do not decode, and a small share of those that do, are mutated as they are so
+    /// that the layers' own decoders are exercised too.
     pub fn mutate(&mut self, data: &[u8]) -> Result<Vec<u8>, FuzzerError> {
+        if self.encodings.is_empty() || self.rng.gen_bool(0.1) {
+            return self.mutate_payload(data);
+        }
+        let Some(decoded) = self.encodings.decode(data) else {
+            return self.mutate_payload(data);
+        };
+        let payload = self.mutate_payload(decoded.payload())?;
+        Ok(self.encodings.encode(&decoded, &payload))
+    }
+
+    /// With probability `rate`, mutates a random corpus entry within the region map attached
+    /// to it. `None` if the corpus is empty or the entry was not picked.
+    pub fn mutate_corpus_entry(&mut self, rate: f64) -> Result<Option<Vec<u8>>, FuzzerError> {
+        if self.corpus.is_empty() || !self.rng.gen_bool(rate) {
+            return Ok(None);
+        }
+        let index = self.rng.gen_range(0..self.corpus.len());
+        let entry = self.corpus[index].clone();
+        self.entry_regions = self.region_maps.get(&index).cloned();
+        let mutated = self.mutate(&entry);
+        self.entry_regions = None;
+        mutated.map(Some)
+    }
+
+    fn mutate_payload(&mut self, data: &[u8]) -> Result<Vec<u8>, FuzzerError> {
+        self.active_regions = match self.entry_regions {
+            Some(ref regions) if !regions.is_empty() => Some(regions.clone()),
+            _ => self.regions_for(data),
+        };
+        // Format-aware mutators do not know about regions, so they only see unrestricted inputs.
+        if self.active_regions.is_none() {
+            if let Some(mutated) = self.mutate_structured(data)? {
+                return Ok(mutated);
+            }
+        }
+
         let mut mutated = data.to_vec();
         let mutations = self.rng.gen_range(1..=self.options.max_mutations);
+        let choices = if self.active_regions.is_some() { 6 } else { 4 };
 
         for _ in 0..mutations {
-            let choice = self.rng.gen_range(0..4);
+            let choice = self.rng.gen_range(0..choices);
             match choice {
                 0 => self.bit_flip(&mut mutated),
                 1 => self.byte_flip(&mut mutated),
                 2 => self.insert_byte(&mut mutated),
                 3 => self.delete_byte(&mut mutated),
+                4 => self.numeric_mutation(&mut mutated),
+                5 => self.dictionary_mutation(&mut mutated),
                 _ => (),
             }
         }
+        self.active_regions = None;
         Ok(mutated)
     }
 
+    /// Format-aware mutation for input formats that have one. Byte-level mutation still
+    /// runs some of the time so that inputs can also leave the format's structure.
+    fn mutate_structured(&mut self, data: &[u8]) -> Result<Option<Vec<u8>>, FuzzerError> {
+        let structured = matches!(
+            self.input_format,
+            Some(
+                InputFormat::Unicode
+                    | InputFormat::Http
+                    | InputFormat::Template
+                    | InputFormat::MessagePack
+                    | InputFormat::Cbor
+                    | InputFormat::Bincode
+            )
+        );
+        if !structured || !self.rng.gen_bool(0.8) {
+            return Ok(None);
+        }
+        let mutations = self.rng.gen_range(1..=self.options.max_mutations);
+        match self.input_format {
+            Some(InputFormat::Unicode) => {
+                let mut mutated = data.to_vec();
+                for _ in 0..mutations {
+                    unicode::mutate(&mut self.rng, &mut mutated);
+                }
+                Ok(Some(mutated))
+            }
+            Some(InputFormat::Http) => {
+                // Inputs that no longer parse as a request fall back to byte-level mutation.
+                let mut mutated = data.to_vec();
+                for _ in 0..mutations {
+                    match http::mutate(&mut self.rng, &mutated) {
+                        Some(next) => mutated = next,
+                        None => return Ok(None),
+                    }
+                }
+                Ok(Some(mutated))
+            }
+            Some(InputFormat::Template) => {
+                // Lengths, counts and checksums are recomputed when the instance is encoded.
+                let Some(template) = self.template.as_ref() else {
+                    return Ok(None);
+                };
+                let Some(mut values) = template.decode(data) else {
+                    return Ok(None);
+                };
+                for _ in 0..mutations {
+                    template.mutate(&mut self.rng, &mut values);
+                }
+                Ok(Some(template.encode(&values)))
+            }
+            Some(InputFormat::MessagePack) => {
+                let mut mutated = data.to_vec();
+                for _ in 0..mutations {
+                    match msgpack::mutate(&self.messagepack_options, &mut self.rng, &mutated) {
+                        Some(next) => mutated = next,
+                        None => return Ok(None),
+                    }
+                }
+                Ok(Some(mutated))
+            }
+            Some(InputFormat::Cbor) => {
+                let mut mutated = data.to_vec();
+                for _ in 0..mutations {
+                    match cbor::mutate(&self.cbor_options, &mut self.rng, &mutated) {
+                        Some(next) => mutated = next,
+                        None => return Ok(None),
+                    }
+                }
+                Ok(Some(mutated))
+            }
+            // Without a layout the bytes cannot be decoded, so only byte-level mutation applies.
+            Some(InputFormat::Bincode) => {
+                let Some((ref layout, ref options)) = self.bincode else {
+                    return Ok(None);
+                };
+                let mut mutated = data.to_vec();
+                for _ in 0..mutations {
+                    match bincode::mutate(layout, options, &mut self.rng, &mutated) {
+                        Some(next) => mutated = next,
+                        None => return Ok(None),
+                    }
+                }
+                Ok(Some(mutated))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Picks an index below `len` whose region kind is `allowed`, or any index if the input
+    /// is unrestricted.
+    fn pick_index(&mut self, len: usize, allowed: fn(RegionKind) -> bool) -> Option<usize> {
+        match self.active_regions {
+            None => (len > 0).then(|| self.rng.gen_range(0..len)),
+            Some(ref regions) => {
+                let positions = regions.positions(len, allowed);
+                (!positions.is_empty()).then(|| positions[self.rng.gen_range(0..positions.len())])
+            }
+        }
+    }
+
+    /// Picks a position at most `len` where bytes may be inserted.
+    fn pick_insertion(&mut self, len: usize) -> Option<usize> {
+        match self.active_regions {
+            None => Some(self.rng.gen_range(0..=len)),
+            Some(ref regions) => {
+                let positions = regions.positions(len + 1, is_free);
+                (!positions.is_empty()).then(|| positions[self.rng.gen_range(0..positions.len())])
+            }
+        }
+    }
+
+    /// Keeps the active region map in step with an edit replacing `range` by `len` bytes.
+    fn splice_regions(&mut self, range: Range<usize>, len: usize) {
+        if let Some(ref mut regions) = self.active_regions {
+            regions.splice(range, len);
+        }
+    }
+
+    fn is_free(&self, index: usize) -> bool {
+        self.active_regions
+            .as_ref()
+            .is_none_or(|regions| is_free(regions.kind_at(index)))
+    }
+
     fn bit_flip(&mut self, data: &mut Vec<u8>) {
-        if data.is_empty() {
+        let Some(idx) = self.pick_index(data.len(), is_bit_flippable) else {
             return;
-        }
-        let idx = self.rng.gen_range(0..data.len());
+        };
         let bit = self.rng.gen_range(0..8);
         data[idx] ^= 1 << bit;
     }
 
     fn byte_flip(&mut self, data: &mut Vec<u8>) {
-        if data.is_empty() {
+        let Some(idx) = self.pick_index(data.len(), is_free) else {
             return;
-        }
-        let idx = self.rng.gen_range(0..data.len());
+        };
         data[idx] = self.rng.gen::<u8>();
     }
 
     fn insert_byte(&mut self, data: &mut Vec<u8>) {
-        let idx = self.rng.gen_range(0..=data.len());
+        let Some(idx) = self.pick_insertion(data.len()) else {
+            return;
+        };
         let byte = self.rng.gen::<u8>();
         data.insert(idx, byte);
+        self.splice_regions(idx..idx, 1);
     }
 
     fn delete_byte(&mut self, data: &mut Vec<u8>) {
-        if data.is_empty() {
+        let Some(idx) = self.pick_index(data.len(), is_free) else {
             return;
-        }
-        let idx = self.rng.gen_range(0..data.len());
+        };
         data.remove(idx);
+        self.splice_regions(idx..idx + 1, 0);
     }
     fn arithmetic_mutation(&mut self, data: &mut Vec<u8>) {
-        if !self.options.enable_arithmetics || data.is_empty() {
+        if !self.options.enable_arithmetics {
             return;
         }
-        let idx = self.rng.gen_range(0..data.len());
+        let Some(idx) = self.pick_index(data.len(), is_bit_flippable) else {
+            return;
+        };
         let delta = self
             .rng
             .gen_range(-self.options.arithmetics_range..=self.options.arithmetics_range)
@@ -119,9 +358,17 @@ impl Mutator {
         if data.len() < size {
             return;
         }
-        let idx = self.rng.gen_range(0..(data.len() - size));
-        for i in idx..(idx + size) {
-            data[i] = self.rng.gen::<u8>();
+        let idx = match self.active_regions {
+            None => self.rng.gen_range(0..(data.len() - size)),
+            Some(_) => match self.pick_index(data.len(), is_free) {
+                Some(idx) => idx,
+                None => return,
+            },
+        };
+        for i in idx..(idx + size).min(data.len()) {
+            if self.is_free(i) {
+                data[i] = self.rng.gen::<u8>();
+            }
         }
     }
 
@@ -132,20 +379,36 @@ impl Mutator {
         let other = &self.corpus[self.rng.gen_range(0..self.corpus.len())];
         let min_len = data.len().min(other.len());
         let crossover_point = self.rng.gen_range(0..min_len);
-        data[crossover_point..].copy_from_slice(&other[crossover_point..]);
+        match self.active_regions {
+            None => data[crossover_point..].copy_from_slice(&other[crossover_point..]),
+            Some(ref regions) => {
+                for i in crossover_point..min_len {
+                    if is_free(regions.kind_at(i)) {
+                        data[i] = other[i];
+                    }
+                }
+            }
+        }
     }
 
     fn splicing(&mut self, data: &mut Vec<u8>) {
         if !self.options.enable_splicing || self.corpus.is_empty() {
             return;
         }
-        let other = &self.corpus[self.rng.gen_range(0..self.corpus.len())];
+        let other = self.corpus[self.rng.gen_range(0..self.corpus.len())].clone();
         let splice_len = self.rng.gen_range(1..=other.len());
-        let splice_point = self.rng.gen_range(0..data.len());
+        let splice_point = match self.active_regions {
+            None => self.rng.gen_range(0..data.len()),
+            Some(_) => match self.pick_insertion(data.len()) {
+                Some(idx) => idx,
+                None => return,
+            },
+        };
         data.splice(
             splice_point..splice_point,
             other[..splice_len].iter().cloned(),
         );
+        self.splice_regions(splice_point..splice_point, splice_len);
     }
 
     fn replacement(&mut self, data: &mut Vec<u8>) {
@@ -156,18 +419,47 @@ impl Mutator {
         if dictionary.is_empty() {
             return;
         }
-        let word = &dictionary[self.rng.gen_range(0..dictionary.len())];
-        let idx = self.rng.gen_range(0..data.len());
-        let end = (idx + word.len()).min(data.len());
+        let word = dictionary[self.rng.gen_range(0..dictionary.len())].clone();
+        let idx = match self.active_regions {
+            None => self.rng.gen_range(0..data.len()),
+            Some(ref regions) => {
+                // A dictionary-only region is replaced as a whole.
+                let targets = regions.positions(data.len(), |kind| {
+                    matches!(kind, RegionKind::Free | RegionKind::DictionaryOnly)
+                });
+                if targets.is_empty() {
+                    return;
+                }
+                let idx = targets[self.rng.gen_range(0..targets.len())];
+                if let Some(region) = regions.region_at(idx) {
+                    if region.kind == RegionKind::DictionaryOnly {
+                        let range = region.range.start..region.range.end.min(data.len());
+                        data.splice(range.clone(), word.iter().cloned());
+                        self.splice_regions(range, word.len());
+                        return;
+                    }
+                }
+                idx
+            }
+        };
+        let end = match self.active_regions {
+            None => (idx + word.len()).min(data.len()),
+            Some(ref regions) => (idx + word.len()).min(regions.free_run_end(idx, data.len())),
+        };
         data.splice(idx..end, word.iter().cloned());
+        self.splice_regions(idx..end, word.len());
     }
 
     fn shuffling(&mut self, data: &mut Vec<u8>) {
         if !self.options.enable_shuffling || data.len() < 2 {
             return;
         }
-        let idx1 = self.rng.gen_range(0..data.len());
-        let idx2 = self.rng.gen_range(0..data.len());
+        let (Some(idx1), Some(idx2)) = (
+            self.pick_index(data.len(), is_free),
+            self.pick_index(data.len(), is_free),
+        ) else {
+            return;
+        };
         data.swap(idx1, idx2);
     }
 
@@ -177,13 +469,123 @@ impl Mutator {
         {
             return;
         }
-        let value = &self.options.interesting_values
-            [self.rng.gen_range(0..self.options.interesting_values.len())];
-        let idx = self.rng.gen_range(0..=data.len());
+        let value = self.options.interesting_values
+            [self.rng.gen_range(0..self.options.interesting_values.len())]
+        .clone();
+        let Some(idx) = self.pick_insertion(data.len()) else {
+            return;
+        };
         data.splice(idx..idx, value.iter().cloned());
+        self.splice_regions(idx..idx, value.len());
+    }
+
+    /// Treats a numeric region of up to 8 bytes as an integer of either byte order and adds
+    /// a small delta to it or sets it to a boundary value.
+    fn numeric_mutation(&mut self, data: &mut [u8]) {
+        let Some(ref regions) = self.active_regions else {
+            return;
+        };
+        let numeric: Vec<Range<usize>> = regions
+            .regions()
+            .iter()
+            .filter(|region| region.kind == RegionKind::Numeric)
+            .map(|region| region.range.start..region.range.end.min(data.len()))
+            .filter(|range| !range.is_empty() && range.len() <= 8)
+            .collect();
+        if numeric.is_empty() {
+            return;
+        }
+        let range = numeric[self.rng.gen_range(0..numeric.len())].clone();
+        let width = range.len();
+        let big_endian = self.rng.gen_bool(0.5);
+        let mut bytes = [0u8; 8];
+        bytes[..width].copy_from_slice(&data[range.clone()]);
+        if big_endian {
+            bytes[..width].reverse();
+        }
+        let mask = u64::MAX >> (64 - 8 * width);
+        let value = u64::from_le_bytes(bytes);
+        let value = if self.rng.gen_bool(0.5) {
+            let delta = self
+                .rng
+                .gen_range(-self.options.arithmetics_range..=self.options.arithmetics_range);
+            value.wrapping_add(delta as u64)
+        } else {
+            let sign = 1u64 << (8 * width - 1);
+            [0, 1, mask, sign, sign - 1, mask - 1][self.rng.gen_range(0..6)]
+        } & mask;
+        let mut bytes = value.to_le_bytes();
+        if big_endian {
+            bytes[..width].reverse();
+        }
+        data[range].copy_from_slice(&bytes[..width]);
+    }
+
+    /// Replaces a dictionary-only region with a dictionary word.
+    fn dictionary_mutation(&mut self, data: &mut Vec<u8>) {
+        let (Some(ref regions), Some(ref dictionary)) =
+            (&self.active_regions, &self.options.dictionary)
+        else {
+            return;
+        };
+        let targets: Vec<Range<usize>> = regions
+            .regions()
+            .iter()
+            .filter(|region| region.kind == RegionKind::DictionaryOnly)
+            .map(|region| region.range.start..region.range.end.min(data.len()))
+            .filter(|range| range.start < range.end)
+            .collect();
+        if targets.is_empty() || dictionary.is_empty() {
+            return;
+        }
+        let range = targets[self.rng.gen_range(0..targets.len())].clone();
+        let word = &dictionary[self.rng.gen_range(0..dictionary.len())];
+        let len = word.len();
+        data.splice(range.clone(), word.iter().cloned());
+        self.splice_regions(range, len);
     }
 
     pub fn add_to_corpus(&mut self, data: Vec<u8>) {
         self.corpus.push(data);
     }
+
+    /// Adds a corpus entry whose mutations are confined by `regions`, which take precedence
+    /// over the region source.
+    pub fn add_to_corpus_with_regions(&mut self, data: Vec<u8>, regions: RegionMap) {
+        self.region_maps.insert(self.corpus.len(), regions);
+        self.corpus.push(data);
+    }
+}
+
+const fn is_free(kind: RegionKind) -> bool {
+    matches!(kind, RegionKind::Free)
+}
+
+const fn is_bit_flippable(kind: RegionKind) -> bool {
+    matches!(kind, RegionKind::Free | RegionKind::Numeric)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corpus_entries_keep_their_frozen_regions() {
+        let mut mutator = Mutator::new(MutatorOptions::default(), Some(1));
+        let mut entry = b"FUZZ".to_vec();
+        entry.extend(0..60);
+        mutator.add_to_corpus_with_regions(entry, RegionMap::new().with(0..4, RegionKind::Frozen));
+        for _ in 0..500 {
+            let mutated = mutator.mutate_corpus_entry(1.0).unwrap().unwrap();
+            assert!(mutated.starts_with(b"FUZZ"));
+        }
+    }
+
+    #[test]
+    fn empty_corpus_yields_nothing() {
+        let mut mutator = Mutator::new(MutatorOptions::default(), Some(1));
+        assert!(mutator.mutate_corpus_entry(1.0).unwrap().is_none());
+        mutator.add_to_corpus(vec![1, 2, 3]);
+        assert!(mutator.mutate_corpus_entry(0.0).unwrap().is_none());
+    }
 }
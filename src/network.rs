@@ -0,0 +1,479 @@
+use crate::errors::FuzzerError;
+use crate::target::TargetFunction;
+use log::{debug, error};
+use std::{
+    fmt, io,
+    net::SocketAddr,
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    time::{sleep_until, timeout, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+type Splitter = Arc<dyn Fn(&[u8]) -> Vec<Vec<u8>> + Send + Sync>;
+
+/// How an input is split into the messages sent to the server.
+#[derive(Clone)]
+pub enum Framing {
+    /// The whole input is one message.
+    Whole,
+    /// The input is split after every occurrence of the delimiter, which stays at the end of
+    /// its message.
+    Delimiter(Vec<u8>),
+    Custom(Splitter),
+}
+
+impl Framing {
+    pub fn custom<F>(split: F) -> Self
+    where
+        F: Fn(&[u8]) -> Vec<Vec<u8>> + Send + Sync + 'static,
+    {
+        Framing::Custom(Arc::new(split))
+    }
+
+    pub fn split(&self, input: &[u8]) -> Vec<Vec<u8>> {
+        match self {
+            Framing::Whole => vec![input.to_vec()],
+            Framing::Delimiter(delimiter) if delimiter.is_empty() => vec![input.to_vec()],
+            Framing::Delimiter(delimiter) => {
+                let mut messages = Vec::new();
+                let mut start = 0;
+                let mut i = 0;
+                while i + delimiter.len() <= input.len() {
+                    if input[i..].starts_with(delimiter) {
+                        i += delimiter.len();
+                        messages.push(input[start..i].to_vec());
+                        start = i;
+                    } else {
+                        i += 1;
+                    }
+                }
+                if start < input.len() {
+                    messages.push(input[start..].to_vec());
+                }
+                messages
+            }
+            Framing::Custom(split) => split(input),
+        }
+    }
+}
+
+impl fmt::Debug for Framing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Framing::Whole => f.write_str("Whole"),
+            Framing::Delimiter(delimiter) => f.debug_tuple("Delimiter").field(delimiter).finish(),
+            Framing::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// A server process the target starts before fuzzing, restarts after it exits and stops at
+/// teardown.
+#[derive(Debug, Clone)]
+pub struct ServerProcess {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Time given to the server to start listening.
+    pub startup_delay: Duration,
+}
+
+impl ServerProcess {
+    pub fn new(program: &str) -> Self {
+        ServerProcess {
+            program: program.to_string(),
+            args: Vec::new(),
+            startup_delay: Duration::from_millis(500),
+        }
+    }
+
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    pub const fn startup_delay(mut self, delay: Duration) -> Self {
+        self.startup_delay = delay;
+        self
+    }
+}
+
+/// A running [`ServerProcess`], killed when dropped.
+#[derive(Debug)]
+struct ManagedServer {
+    process: ServerProcess,
+    child: Mutex<Option<Child>>,
+    /// When the startup delay of the last start ends.
+    ready_at: Mutex<Option<Instant>>,
+}
+
+impl ManagedServer {
+    /// Starts the server unless it is running. A server that exited after the previous input
+    /// had been judged is logged, since that input most likely brought it down.
+    fn ensure_running(&self) -> Result<(), FuzzerError> {
+        let mut child = self.child.lock().unwrap();
+        if let Some(ref mut running) = *child {
+            match running.try_wait() {
+                Ok(None) => return Ok(()),
+                Ok(Some(status)) => error!(
+                    "Server {} exited between inputs ({}), restarting it",
+                    self.process.program, status
+                ),
+                Err(e) => {
+                    error!(
+                        "Cannot check server {} ({}), restarting it",
+                        self.process.program, e
+                    );
+                    let _ = running.kill();
+                    let _ = running.wait();
+                }
+            }
+        }
+        let spawned = Command::new(&self.process.program)
+            .args(&self.process.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| {
+                FuzzerError::HookError(format!("failed to start {}: {}", self.process.program, e))
+            })?;
+        debug!("Started server {} ({})", self.process.program, spawned.id());
+        *child = Some(spawned);
+        *self.ready_at.lock().unwrap() = Some(Instant::now() + self.process.startup_delay);
+        Ok(())
+    }
+
+    /// Waits until the startup delay of the last start has passed.
+    async fn ready(&self) {
+        let ready_at = *self.ready_at.lock().unwrap();
+        if let Some(ready_at) = ready_at {
+            sleep_until(ready_at).await;
+        }
+    }
+
+    /// The exit status if the server has stopped since it was started.
+    fn exit_status(&self) -> Option<String> {
+        let mut child = self.child.lock().unwrap();
+        let status = child.as_mut()?.try_wait().ok()??;
+        *child = None;
+        Some(status.to_string())
+    }
+
+    fn stop(&self) {
+        if let Some(mut child) = self.child.lock().unwrap().take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Drop for ManagedServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// How the server left the connection after a message.
+enum Exchange {
+    Open,
+    Closed,
+    Reset,
+}
+
+/// A server reached over TCP or UDP. Each input is split into messages that are sent in
+/// order, reading the server's responses after each one.
+#[derive(Debug)]
+pub struct NetworkTarget {
+    address: String,
+    transport: Transport,
+    framing: Framing,
+    connect_timeout: Duration,
+    response_timeout: Duration,
+    crash_on_reset: bool,
+    server: Option<ManagedServer>,
+}
+
+impl NetworkTarget {
+    pub fn tcp(address: &str) -> Self {
+        Self::new(address, Transport::Tcp)
+    }
+
+    pub fn udp(address: &str) -> Self {
+        Self::new(address, Transport::Udp)
+    }
+
+    fn new(address: &str, transport: Transport) -> Self {
+        NetworkTarget {
+            address: address.to_string(),
+            transport,
+            framing: Framing::Whole,
+            connect_timeout: Duration::from_secs(1),
+            response_timeout: Duration::from_millis(100),
+            crash_on_reset: false,
+            server: None,
+        }
+    }
+
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    pub const fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// How long to wait for more response data before sending the next message.
+    pub const fn response_timeout(mut self, timeout: Duration) -> Self {
+        self.response_timeout = timeout;
+        self
+    }
+
+    /// Reports a reset connection as a crash even if the server still accepts new ones, as
+    /// for servers that handle each connection in a task or thread of its own.
+    pub const fn crash_on_reset(mut self, crash_on_reset: bool) -> Self {
+        self.crash_on_reset = crash_on_reset;
+        self
+    }
+
+    pub fn server(mut self, process: ServerProcess) -> Self {
+        self.server = Some(ManagedServer {
+            process,
+            child: Mutex::new(None),
+            ready_at: Mutex::new(None),
+        });
+        self
+    }
+
+    /// Wraps the target for the engine. A managed server is started by the setup hook and
+    /// stopped by the teardown hook.
+    pub fn into_target(self, name: &str) -> TargetFunction {
+        let target = Arc::new(self);
+        let (setup, teardown) = (Arc::clone(&target), Arc::clone(&target));
+        TargetFunction::new_async(name, move |input: &[u8]| {
+            let target = Arc::clone(&target);
+            let input = input.to_vec();
+            Box::pin(async move { target.execute(&input).await })
+        })
+        .with_setup(move || setup.start_server())
+        .with_teardown(move || {
+            teardown.stop_server();
+            Ok(())
+        })
+    }
+
+    /// Starts the managed server, if there is one. Messages are only sent once its startup
+    /// delay has passed.
+    pub fn start_server(&self) -> Result<(), FuzzerError> {
+        if let Some(ref server) = self.server {
+            server.ensure_running()?;
+        }
+        Ok(())
+    }
+
+    pub fn stop_server(&self) {
+        if let Some(ref server) = self.server {
+            server.stop();
+        }
+    }
+
+    /// Splits an input into messages with the target's framing.
+    pub fn split(&self, input: &[u8]) -> Vec<Vec<u8>> {
+        self.framing.split(input)
+    }
+
+    /// Sends the input and reads the responses. Returns an error if the server crashed: its
+    /// process exited, it refused a new connection after closing or resetting this one, or,
+    /// with `crash_on_reset`, it reset the connection.
+    pub async fn execute(&self, input: &[u8]) -> Result<(), FuzzerError> {
+        self.send_messages(&self.split(input)).await.map(|_| ())
+    }
+
+    /// Sends the messages in order and returns what the server answered to each, for the
+    /// messages sent before it closed the connection. Crashes are detected as by `execute`.
+    pub async fn send_messages(&self, messages: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, FuzzerError> {
+        if let Some(ref server) = self.server {
+            server.ensure_running()?;
+            server.ready().await;
+        }
+        let mut responses = Vec::with_capacity(messages.len());
+        let exchange = match self.transport {
+            Transport::Tcp => self.exchange_tcp(messages, &mut responses).await?,
+            Transport::Udp => self.exchange_udp(messages, &mut responses).await?,
+        };
+
+        if !matches!(exchange, Exchange::Open) {
+            // A crashing server drops its connections before it stops listening or exits.
+            tokio::time::sleep(self.response_timeout).await;
+        }
+        if let Some(status) = self.server.as_ref().and_then(ManagedServer::exit_status) {
+            return Err(FuzzerError::ExecutionError(format!(
+                "server exited: {}",
+                status
+            )));
+        }
+        match exchange {
+            Exchange::Open => Ok(responses),
+            Exchange::Reset if self.crash_on_reset => Err(FuzzerError::ExecutionError(
+                "server reset the connection".to_string(),
+            )),
+            Exchange::Closed | Exchange::Reset => self.check_alive().await.map(|_| responses),
+        }
+    }
+
+    async fn exchange_tcp(
+        &self,
+        messages: &[Vec<u8>],
+        responses: &mut Vec<Vec<u8>>,
+    ) -> Result<Exchange, FuzzerError> {
+        let mut stream = self.connect_tcp().await?;
+        for message in messages {
+            if let Err(e) = stream.write_all(message).await {
+                return classify(e);
+            }
+            responses.push(Vec::new());
+            match self.read_responses(&mut stream, responses.last_mut()).await {
+                Ok(true) => {}
+                Ok(false) => return Ok(Exchange::Closed),
+                Err(e) => return classify(e),
+            }
+        }
+        // Closing our side lets the server act on a trailing partial message while we still
+        // watch the connection.
+        if let Err(e) = stream.shutdown().await {
+            return classify(e);
+        }
+        match self.read_responses(&mut stream, responses.last_mut()).await {
+            Ok(true) => Ok(Exchange::Open),
+            Ok(false) => Ok(Exchange::Closed),
+            Err(e) => classify(e),
+        }
+    }
+
+    /// Reads until the server is quiet for the response timeout, appending to `response`.
+    /// Returns false if the server closed the connection.
+    async fn read_responses(
+        &self,
+        stream: &mut TcpStream,
+        mut response: Option<&mut Vec<u8>>,
+    ) -> io::Result<bool> {
+        let mut buf = [0u8; 4096];
+        loop {
+            match timeout(self.response_timeout, stream.read(&mut buf)).await {
+                Ok(Ok(0)) => return Ok(false),
+                Ok(Ok(n)) => {
+                    debug!("Received {} bytes from {}", n, self.address);
+                    if let Some(ref mut response) = response {
+                        response.extend_from_slice(&buf[..n]);
+                    }
+                }
+                Ok(Err(e)) => return Err(e),
+                Err(_) => return Ok(true),
+            }
+        }
+    }
+
+    async fn exchange_udp(
+        &self,
+        messages: &[Vec<u8>],
+        responses: &mut Vec<Vec<u8>>,
+    ) -> Result<Exchange, FuzzerError> {
+        let address = self.resolve().await?;
+        let local = if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)
+            .await
+            .map_err(|e| FuzzerError::ExecutionError(format!("bind failed: {}", e)))?;
+        socket
+            .connect(address)
+            .await
+            .map_err(|e| FuzzerError::ExecutionError(format!("connect failed: {}", e)))?;
+        let mut buf = vec![0u8; 65536];
+        for message in messages {
+            if let Err(e) = socket.send(message).await {
+                return classify(e);
+            }
+            let mut response = Vec::new();
+            loop {
+                match timeout(self.response_timeout, socket.recv(&mut buf)).await {
+                    Ok(Ok(n)) => {
+                        debug!("Received {} bytes from {}", n, self.address);
+                        response.extend_from_slice(&buf[..n]);
+                    }
+                    Ok(Err(e)) => return classify(e),
+                    Err(_) => break,
+                }
+            }
+            responses.push(response);
+        }
+        Ok(Exchange::Open)
+    }
+
+    async fn connect_tcp(&self) -> Result<TcpStream, FuzzerError> {
+        match timeout(self.connect_timeout, TcpStream::connect(&self.address)).await {
+            Ok(Ok(stream)) => Ok(stream),
+            // A server that is not listening was most likely brought down by an earlier input.
+            Ok(Err(e)) => Err(FuzzerError::ExecutionError(format!(
+                "connection to {} failed: {}",
+                self.address, e
+            ))),
+            Err(_) => Err(FuzzerError::ExecutionError(format!(
+                "connection to {} timed out",
+                self.address
+            ))),
+        }
+    }
+
+    /// Checks that a server that dropped the connection still accepts new ones. UDP servers
+    /// have no connections to probe, so only their process is checked.
+    async fn check_alive(&self) -> Result<(), FuzzerError> {
+        if self.transport == Transport::Udp {
+            return Ok(());
+        }
+        match self.connect_tcp().await {
+            Ok(_) => Ok(()),
+            Err(FuzzerError::ExecutionError(e)) => Err(FuzzerError::ExecutionError(format!(
+                "server stopped accepting connections: {}",
+                e
+            ))),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn resolve(&self) -> Result<SocketAddr, FuzzerError> {
+        tokio::net::lookup_host(&self.address)
+            .await
+            .ok()
+            .and_then(|mut addresses| addresses.next())
+            .ok_or_else(|| FuzzerError::ExecutionError(format!("cannot resolve {}", self.address)))
+    }
+}
+
+/// Maps an I/O error during the exchange to what it says about the server. A refused UDP
+/// datagram means nothing is listening.
+fn classify(e: io::Error) -> Result<Exchange, FuzzerError> {
+    match e.kind() {
+        io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::BrokenPipe => Ok(Exchange::Reset),
+        io::ErrorKind::ConnectionRefused => Err(FuzzerError::ExecutionError(
+            "server is not listening".to_string(),
+        )),
+        _ => Err(FuzzerError::ExecutionError(format!("network error: {}", e))),
+    }
+}
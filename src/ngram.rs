@@ -0,0 +1,206 @@
+use crate::input::SizeLimits;
+use rand::{prelude::StdRng, Rng};
+use std::collections::{BTreeMap, HashMap};
+
+/// Marks the positions before the first token of an input.
+const START: u32 = u32::MAX - 1;
+/// Ends an input.
+const END: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tokenization {
+    /// Every byte is a token.
+    Bytes,
+    /// Runs of word characters and of whitespace are tokens, other bytes are tokens of their
+    /// own.
+    Tokens,
+}
+
+#[derive(Debug, Clone)]
+pub struct NGramOptions {
+    /// Number of tokens in an n-gram, including the one being predicted.
+    pub order: usize,
+    pub tokenization: Tokenization,
+    /// Probability at each step of predicting from a shorter context than is available,
+    /// which yields inputs further from the corpus.
+    pub backoff_rate: f64,
+}
+
+impl Default for NGramOptions {
+    fn default() -> Self {
+        NGramOptions {
+            order: 4,
+            tokenization: Tokenization::Bytes,
+            backoff_rate: 0.05,
+        }
+    }
+}
+
+/// A Markov model over the tokens of a seed corpus, with backoff to shorter contexts for
+/// sequences the corpus does not contain.
+#[derive(Debug, Clone)]
+pub struct NGramModel {
+    order: usize,
+    backoff_rate: f64,
+    vocabulary: Vec<Vec<u8>>,
+    /// For each context of up to `order - 1` tokens, the tokens seen after it with their
+    /// cumulative counts, in token order.
+    transitions: HashMap<Vec<u32>, Vec<(u32, u32)>>,
+}
+
+impl NGramModel {
+    pub fn train(corpus: &[Vec<u8>], options: &NGramOptions) -> Self {
+        let order = options.order.max(1);
+        let mut vocabulary = Vec::new();
+        let mut ids: HashMap<&[u8], u32> = HashMap::new();
+        let mut counts: HashMap<Vec<u32>, BTreeMap<u32, u32>> = HashMap::new();
+
+        // Empty inputs would only teach the model to stop right away.
+        for input in corpus.iter().filter(|input| !input.is_empty()) {
+            let tokens = match options.tokenization {
+                Tokenization::Bytes => input.chunks(1).collect(),
+                Tokenization::Tokens => tokenize(input),
+            };
+            let mut history = vec![START; order - 1];
+            for token in tokens.into_iter().map(Some).chain([None]) {
+                let id = match token {
+                    Some(token) => *ids.entry(token).or_insert_with(|| {
+                        vocabulary.push(token.to_vec());
+                        (vocabulary.len() - 1) as u32
+                    }),
+                    None => END,
+                };
+                for k in 0..order {
+                    let context = history[history.len() - k..].to_vec();
+                    *counts.entry(context).or_default().entry(id).or_default() += 1;
+                }
+                history.push(id);
+            }
+        }
+
+        let transitions = counts
+            .into_iter()
+            .map(|(context, next)| {
+                let mut total = 0;
+                let cumulative = next
+                    .into_iter()
+                    .map(|(id, count)| {
+                        total += count;
+                        (id, total)
+                    })
+                    .collect();
+                (context, cumulative)
+            })
+            .collect();
+        NGramModel {
+            order,
+            backoff_rate: options.backoff_rate,
+            vocabulary,
+            transitions,
+        }
+    }
+
+    /// True if the corpus had no non-empty inputs.
+    pub fn is_empty(&self) -> bool {
+        self.vocabulary.is_empty()
+    }
+
+    /// Samples an input within the size limits. Samples that end short of the minimum are
+    /// followed by another one.
+    pub fn generate(&self, rng: &mut StdRng, limits: &SizeLimits) -> Vec<u8> {
+        let max_len = limits.max.max(limits.min);
+        let mut out = Vec::new();
+        let mut history = vec![START; self.order - 1];
+        while out.len() < max_len {
+            let mut k = self.order - 1;
+            while k > 0 && rng.gen_bool(self.backoff_rate) {
+                k -= 1;
+            }
+            let next = (0..=k).rev().find_map(|k| {
+                let candidates = self.transitions.get(&history[history.len() - k..])?;
+                let total = candidates.last()?.1;
+                let pick = rng.gen_range(0..total);
+                let index = candidates.partition_point(|&(_, cumulative)| cumulative <= pick);
+                Some(candidates[index].0)
+            });
+            match next {
+                Some(id) if id != END => {
+                    out.extend_from_slice(&self.vocabulary[id as usize]);
+                    history.push(id);
+                }
+                _ if out.len() < limits.min && !self.is_empty() => {
+                    history.extend(vec![START; self.order - 1]);
+                }
+                _ => break,
+            }
+        }
+        out.truncate(max_len);
+        out
+    }
+}
+
+fn tokenize(data: &[u8]) -> Vec<&[u8]> {
+    let class = |b: u8| {
+        if b.is_ascii_alphanumeric() || b == b'_' || b >= 0x80 {
+            0
+        } else if b.is_ascii_whitespace() {
+            1
+        } else {
+            2
+        }
+    };
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    for i in 1..=data.len() {
+        let boundary =
+            i == data.len() || class(data[i]) != class(data[start]) || class(data[start]) == 2;
+        if boundary {
+            tokens.push(&data[start..i]);
+            start = i;
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::SizeDistribution;
+    use rand::SeedableRng;
+
+    fn limits(min: usize, max: usize) -> SizeLimits {
+        SizeLimits {
+            min,
+            max,
+            distribution: SizeDistribution::Uniform,
+        }
+    }
+
+    #[test]
+    fn samples_respect_the_size_limits() {
+        let corpus = vec![b"GET /a".to_vec(), b"PUT /b".to_vec()];
+        let model = NGramModel::train(&corpus, &NGramOptions::default());
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..200 {
+            let sample = model.generate(&mut rng, &limits(20, 30));
+            assert!((20..=30).contains(&sample.len()), "{:?}", sample);
+        }
+    }
+
+    #[test]
+    fn unbacked_samples_reproduce_the_corpus() {
+        let corpus = vec![b"hello world".to_vec()];
+        let options = NGramOptions {
+            backoff_rate: 0.0,
+            ..NGramOptions::default()
+        };
+        let model = NGramModel::train(&corpus, &options);
+        let sample = model.generate(&mut StdRng::seed_from_u64(2), &limits(0, 100));
+        assert_eq!(sample, b"hello world");
+    }
+
+    #[test]
+    fn empty_inputs_are_ignored() {
+        assert!(NGramModel::train(&[Vec::new()], &NGramOptions::default()).is_empty());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::errors::FuzzerError;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Debug, sync::Arc};
+
+type Function<T> = Arc<dyn Fn(&[u8]) -> Result<T, FuzzerError> + Send + Sync>;
+type Check<T> = Arc<dyn Fn(&[u8], &T) -> Result<(), String> + Send + Sync>;
+
+/// A target that returns a value, with properties the engine checks on every output. An
+/// error from the function is a crash, as with other targets; properties are only checked on
+/// success.
+pub struct PropertyTarget<T> {
+    name: String,
+    func: Function<T>,
+    properties: Vec<(String, Check<T>)>,
+}
+
+impl<T: Debug + 'static> PropertyTarget<T> {
+    pub fn new<F>(name: &str, func: F) -> Self
+    where
+        F: Fn(&[u8]) -> Result<T, FuzzerError> + Send + Sync + 'static,
+    {
+        PropertyTarget {
+            name: name.to_string(),
+            func: Arc::new(func),
+            properties: Vec::new(),
+        }
+    }
+
+    /// Adds a property over the input and the output it produced.
+    pub fn property<P>(self, name: &str, property: P) -> Self
+    where
+        P: Fn(&[u8], &T) -> bool + Send + Sync + 'static,
+    {
+        self.check(name, move |input: &[u8], output: &T| {
+            if property(input, output) {
+                Ok(())
+            } else {
+                Err("property does not hold".to_string())
+            }
+        })
+    }
+
+    fn check<P>(mut self, name: &str, check: P) -> Self
+    where
+        P: Fn(&[u8], &T) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.properties.push((name.to_string(), Arc::new(check)));
+        self
+    }
+}
+
+impl<T: PartialEq + Debug + 'static> PropertyTarget<T> {
+    /// Running the function on `encode(output)` gives the output back. For a decoding
+    /// function this is `decode(encode(x)) == x`.
+    pub fn round_trip<E>(self, name: &str, encode: E) -> Self
+    where
+        E: Fn(&T) -> Vec<u8> + Send + Sync + 'static,
+    {
+        let func = Arc::clone(&self.func);
+        self.check(name, move |_: &[u8], output: &T| {
+            match func(&encode(output)) {
+                Ok(ref again) if again == output => Ok(()),
+                Ok(again) => Err(format!("re-encoded output gave {:?}", again)),
+                Err(e) => Err(format!("re-encoded output failed: {}", e)),
+            }
+        })
+    }
+}
+
+impl<T: AsRef<[u8]> + PartialEq + Debug + 'static> PropertyTarget<T> {
+    /// Running the function on its own output gives the same output: `f(f(x)) == f(x)`.
+    pub fn idempotent(self, name: &str) -> Self {
+        let func = Arc::clone(&self.func);
+        self.check(name, move |_: &[u8], output: &T| {
+            match func(output.as_ref()) {
+                Ok(ref again) if again == output => Ok(()),
+                Ok(again) => Err(format!("second application gave {:?}", again)),
+                Err(e) => Err(format!("second application failed: {}", e)),
+            }
+        })
+    }
+}
+
+impl<T: PartialOrd + Debug + 'static> PropertyTarget<T> {
+    /// Running the function on `grow(input)` gives an output at least as large. Grown inputs
+    /// the function rejects are not checked.
+    pub fn monotonic<G>(self, name: &str, grow: G) -> Self
+    where
+        G: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        let func = Arc::clone(&self.func);
+        self.check(name, move |input: &[u8], output: &T| {
+            match func(&grow(input)) {
+                Ok(ref grown) if grown < output => {
+                    Err(format!("grown input gave the smaller {:?}", grown))
+                }
+                Ok(grown) if grown.partial_cmp(output).is_none() => {
+                    Err(format!("grown input gave the incomparable {:?}", grown))
+                }
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+/// A property that did not hold for an input.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PropertyViolation {
+    pub target: String,
+    pub property: String,
+    pub input: Vec<u8>,
+    /// The function's output, formatted with `Debug`.
+    pub output: String,
+    pub detail: String,
+}
+
+/// A [`PropertyTarget`] with its output type erased, as stored by the engine.
+pub trait PropertyRunner: Send + Sync {
+    fn name(&self) -> &str;
+    /// Runs the function and checks its properties in order, stopping at the first one that
+    /// does not hold.
+    fn run(&self, input: &[u8]) -> Result<Option<PropertyViolation>, FuzzerError>;
+}
+
+impl<T: Debug + 'static> PropertyRunner for PropertyTarget<T> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, input: &[u8]) -> Result<Option<PropertyViolation>, FuzzerError> {
+        let output = (self.func)(input)?;
+        let violation = self.properties.iter().find_map(|(property, check)| {
+            let detail = check(input, &output).err()?;
+            Some(PropertyViolation {
+                target: self.name.clone(),
+                property: property.clone(),
+                input: input.to_vec(),
+                output: format!("{:?}", output),
+                detail,
+            })
+        });
+        Ok(violation)
+    }
+}
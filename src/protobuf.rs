@@ -0,0 +1,615 @@
+use crate::errors::FuzzerError;
+use prost::encoding::{encode_key, encode_varint, WireType};
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, FileDescriptorSet};
+use rand::{prelude::StdRng, Rng};
+use std::collections::HashMap;
+use std::fs;
+
+/// Largest field number the wire format allows.
+const MAX_FIELD_NUMBER: u32 = (1 << 29) - 1;
+
+const INTERESTING_VARINTS: [u64; 10] = [
+    0,
+    1,
+    0x7f,
+    0x80,
+    0x3fff,
+    0x4000,
+    0x7fff_ffff,
+    0xffff_ffff,
+    0x7fff_ffff_ffff_ffff,
+    u64::MAX,
+];
+
+const INTERESTING_DOUBLES: [f64; 8] = [
+    0.0,
+    -0.0,
+    1.0,
+    f64::MIN_POSITIVE,
+    f64::MAX,
+    f64::INFINITY,
+    f64::NEG_INFINITY,
+    f64::NAN,
+];
+
+#[derive(Debug, Clone)]
+pub struct ProtobufOptions {
+    pub max_depth: usize,
+    pub max_repeated: usize,
+    pub optional_field_rate: f64,
+    pub unknown_field_rate: f64,
+    pub wire_type_confusion_rate: f64,
+    pub max_bytes_length: usize,
+}
+
+impl Default for ProtobufOptions {
+    fn default() -> Self {
+        ProtobufOptions {
+            max_depth: 8,
+            max_repeated: 4,
+            optional_field_rate: 0.5,
+            unknown_field_rate: 0.05,
+            wire_type_confusion_rate: 0.02,
+            max_bytes_length: 64,
+        }
+    }
+}
+
+/// Message layouts resolved from a compiled `FileDescriptorSet`, rooted at one message type.
+#[derive(Debug, Clone)]
+pub struct ProtobufSchema {
+    messages: HashMap<String, MessageLayout>,
+    enums: HashMap<String, Vec<i32>>,
+    root: String,
+}
+
+#[derive(Debug, Clone)]
+struct MessageLayout {
+    fields: Vec<FieldLayout>,
+    oneof_count: usize,
+}
+
+#[derive(Debug, Clone)]
+struct FieldLayout {
+    number: u32,
+    kind: Type,
+    repeated: bool,
+    required: bool,
+    type_name: String,
+    oneof: Option<usize>,
+}
+
+impl ProtobufSchema {
+    pub fn load_from_file(filename: &str, message: &str) -> Result<Self, FuzzerError> {
+        let bytes = fs::read(filename).map_err(|e| {
+            FuzzerError::InputGenerationError(format!(
+                "Failed to read descriptor set {}: {}",
+                filename, e
+            ))
+        })?;
+        let descriptor_set = FileDescriptorSet::decode(bytes.as_slice()).map_err(|e| {
+            FuzzerError::InputGenerationError(format!("Invalid descriptor set {}: {}", filename, e))
+        })?;
+        Self::from_descriptor_set(&descriptor_set, message)
+    }
+
+    pub fn from_descriptor_set(
+        descriptor_set: &FileDescriptorSet,
+        message: &str,
+    ) -> Result<Self, FuzzerError> {
+        let mut schema = ProtobufSchema {
+            messages: HashMap::new(),
+            enums: HashMap::new(),
+            root: message.trim_start_matches('.').to_string(),
+        };
+
+        for file in &descriptor_set.file {
+            let package = file.package();
+            for enum_type in &file.enum_type {
+                let name = qualify(package, enum_type.name());
+                schema.enums.insert(
+                    name,
+                    enum_type.value.iter().map(|value| value.number()).collect(),
+                );
+            }
+            for message_type in &file.message_type {
+                schema.register_message(package, message_type)?;
+            }
+        }
+
+        if !schema.messages.contains_key(&schema.root) {
+            return Err(FuzzerError::InputGenerationError(format!(
+                "Message {} not found in descriptor set",
+                schema.root
+            )));
+        }
+        Ok(schema)
+    }
+
+    fn register_message(
+        &mut self,
+        scope: &str,
+        descriptor: &DescriptorProto,
+    ) -> Result<(), FuzzerError> {
+        let name = qualify(scope, descriptor.name());
+
+        for enum_type in &descriptor.enum_type {
+            self.enums.insert(
+                qualify(&name, enum_type.name()),
+                enum_type.value.iter().map(|value| value.number()).collect(),
+            );
+        }
+        for nested in &descriptor.nested_type {
+            self.register_message(&name, nested)?;
+        }
+
+        if let Some(field) = descriptor
+            .field
+            .iter()
+            .find(|field| !(1..=MAX_FIELD_NUMBER as i32).contains(&field.number()))
+        {
+            return Err(FuzzerError::InputGenerationError(format!(
+                "Field {}.{} has invalid number {}",
+                name,
+                field.name(),
+                field.number()
+            )));
+        }
+        let fields = descriptor
+            .field
+            .iter()
+            .map(|field| FieldLayout {
+                number: field.number() as u32,
+                kind: field.r#type(),
+                repeated: field.label() == Label::Repeated,
+                required: field.label() == Label::Required,
+                type_name: field.type_name().trim_start_matches('.').to_string(),
+                // Synthetic oneofs wrapping proto3 `optional` fields behave like plain optionals.
+                oneof: field
+                    .oneof_index
+                    .filter(|_| !field.proto3_optional())
+                    .map(|index| index as usize),
+            })
+            .collect();
+
+        self.messages.insert(
+            name,
+            MessageLayout {
+                fields,
+                oneof_count: descriptor.oneof_decl.len(),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    pub fn generate(&self, options: &ProtobufOptions, rng: &mut StdRng) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode_message(&self.root, options, rng, 0, &mut buf);
+        buf
+    }
+
+    fn encode_message(
+        &self,
+        name: &str,
+        options: &ProtobufOptions,
+        rng: &mut StdRng,
+        depth: usize,
+        buf: &mut Vec<u8>,
+    ) {
+        let Some(layout) = self.messages.get(name) else {
+            return;
+        };
+
+        // One member per oneof, occasionally two so that last-one-wins handling is exercised.
+        let mut oneof_choices = Vec::with_capacity(layout.oneof_count);
+        for index in 0..layout.oneof_count {
+            let members: Vec<u32> = layout
+                .fields
+                .iter()
+                .filter(|field| field.oneof == Some(index))
+                .map(|field| field.number)
+                .collect();
+            let mut chosen = Vec::new();
+            if !members.is_empty() && rng.gen_bool(0.9) {
+                chosen.push(members[rng.gen_range(0..members.len())]);
+                if members.len() > 1 && rng.gen_bool(0.1) {
+                    chosen.push(members[rng.gen_range(0..members.len())]);
+                }
+            }
+            oneof_choices.push(chosen);
+        }
+
+        for field in &layout.fields {
+            let present = match field.oneof {
+                Some(index) => oneof_choices[index].contains(&field.number),
+                None if field.required => true,
+                None if field.repeated => true,
+                None => rng.gen_bool(options.optional_field_rate),
+            };
+            if !present {
+                continue;
+            }
+            let nested = matches!(field.kind, Type::Message | Type::Group);
+            if nested && depth >= options.max_depth && !field.required {
+                continue;
+            }
+
+            if field.repeated {
+                let count = rng.gen_range(0..=options.max_repeated);
+                if is_packable(field.kind) && count > 0 && rng.gen_bool(0.5) {
+                    self.encode_packed(field, count, rng, buf);
+                } else {
+                    for _ in 0..count {
+                        self.encode_field(field, options, rng, depth, buf);
+                    }
+                }
+            } else {
+                self.encode_field(field, options, rng, depth, buf);
+            }
+        }
+
+        if rng.gen_bool(options.unknown_field_rate) {
+            self.encode_unknown_field(layout, options, rng, buf);
+        }
+    }
+
+    fn encode_field(
+        &self,
+        field: &FieldLayout,
+        options: &ProtobufOptions,
+        rng: &mut StdRng,
+        depth: usize,
+        buf: &mut Vec<u8>,
+    ) {
+        if rng.gen_bool(options.wire_type_confusion_rate) {
+            let wire_type = confused_wire_type(wire_type_of(field.kind), rng);
+            encode_key(field.number, wire_type, buf);
+            encode_raw_value(wire_type, options, rng, buf);
+            return;
+        }
+
+        encode_key(field.number, wire_type_of(field.kind), buf);
+        // Required messages are still present past the depth limit, but empty.
+        let recurse = depth < options.max_depth;
+        match field.kind {
+            Type::Message => {
+                let mut nested = Vec::new();
+                if recurse {
+                    self.encode_message(&field.type_name, options, rng, depth + 1, &mut nested);
+                }
+                encode_varint(nested.len() as u64, buf);
+                buf.extend_from_slice(&nested);
+            }
+            Type::Group => {
+                if recurse {
+                    self.encode_message(&field.type_name, options, rng, depth + 1, buf);
+                }
+                encode_key(field.number, WireType::EndGroup, buf);
+            }
+            Type::String => {
+                let value = random_string(options.max_bytes_length, rng);
+                encode_varint(value.len() as u64, buf);
+                buf.extend_from_slice(&value);
+            }
+            Type::Bytes => {
+                let len = rng.gen_range(0..=options.max_bytes_length);
+                encode_varint(len as u64, buf);
+                buf.extend((0..len).map(|_| rng.gen::<u8>()));
+            }
+            _ => self.encode_scalar(field, rng, buf),
+        }
+    }
+
+    fn encode_packed(
+        &self,
+        field: &FieldLayout,
+        count: usize,
+        rng: &mut StdRng,
+        buf: &mut Vec<u8>,
+    ) {
+        let mut packed = Vec::new();
+        for _ in 0..count {
+            self.encode_scalar(field, rng, &mut packed);
+        }
+        encode_key(field.number, WireType::LengthDelimited, buf);
+        encode_varint(packed.len() as u64, buf);
+        buf.extend_from_slice(&packed);
+    }
+
+    fn encode_scalar(&self, field: &FieldLayout, rng: &mut StdRng, buf: &mut Vec<u8>) {
+        match field.kind {
+            Type::Double => {
+                let value = if rng.gen_bool(0.3) {
+                    INTERESTING_DOUBLES[rng.gen_range(0..INTERESTING_DOUBLES.len())]
+                } else {
+                    rng.gen::<f64>() * rng.gen_range(-1e9..1e9)
+                };
+                buf.extend_from_slice(&value.to_le_bytes());
+            }
+            Type::Float => {
+                let value = if rng.gen_bool(0.3) {
+                    INTERESTING_DOUBLES[rng.gen_range(0..INTERESTING_DOUBLES.len())] as f32
+                } else {
+                    rng.gen::<f32>() * rng.gen_range(-1e6..1e6)
+                };
+                buf.extend_from_slice(&value.to_le_bytes());
+            }
+            Type::Fixed64 | Type::Sfixed64 => {
+                buf.extend_from_slice(&random_varint_value(rng).to_le_bytes());
+            }
+            Type::Fixed32 | Type::Sfixed32 => {
+                buf.extend_from_slice(&(random_varint_value(rng) as u32).to_le_bytes());
+            }
+            Type::Sint32 | Type::Sint64 => {
+                let value = random_varint_value(rng) as i64;
+                encode_varint(((value << 1) ^ (value >> 63)) as u64, buf);
+            }
+            Type::Int32 => {
+                // Negative int32 values are sign-extended to ten bytes on the wire.
+                encode_varint(random_varint_value(rng) as i32 as i64 as u64, buf);
+            }
+            Type::Uint32 => encode_varint(random_varint_value(rng) as u32 as u64, buf),
+            Type::Bool => {
+                let value = if rng.gen_bool(0.9) {
+                    rng.gen_range(0..=1)
+                } else {
+                    random_varint_value(rng)
+                };
+                encode_varint(value, buf);
+            }
+            Type::Enum => {
+                let values = self.enums.get(&field.type_name);
+                let value = match values {
+                    Some(values) if !values.is_empty() && rng.gen_bool(0.9) => {
+                        values[rng.gen_range(0..values.len())]
+                    }
+                    _ => random_varint_value(rng) as i32,
+                };
+                encode_varint(value as i64 as u64, buf);
+            }
+            _ => encode_varint(random_varint_value(rng), buf),
+        }
+    }
+
+    fn encode_unknown_field(
+        &self,
+        layout: &MessageLayout,
+        options: &ProtobufOptions,
+        rng: &mut StdRng,
+        buf: &mut Vec<u8>,
+    ) {
+        let max_known = layout
+            .fields
+            .iter()
+            .map(|field| field.number)
+            .max()
+            .unwrap_or(0);
+        if max_known >= MAX_FIELD_NUMBER {
+            return;
+        }
+        let number =
+            rng.gen_range(max_known + 1..=max_known.saturating_add(1000).min(MAX_FIELD_NUMBER));
+        let wire_type = match rng.gen_range(0..4) {
+            0 => WireType::Varint,
+            1 => WireType::SixtyFourBit,
+            2 => WireType::ThirtyTwoBit,
+            _ => WireType::LengthDelimited,
+        };
+        encode_key(number, wire_type, buf);
+        encode_raw_value(wire_type, options, rng, buf);
+    }
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+const fn is_packable(kind: Type) -> bool {
+    !matches!(
+        kind,
+        Type::String | Type::Bytes | Type::Message | Type::Group
+    )
+}
+
+const fn wire_type_of(kind: Type) -> WireType {
+    match kind {
+        Type::Double | Type::Fixed64 | Type::Sfixed64 => WireType::SixtyFourBit,
+        Type::Float | Type::Fixed32 | Type::Sfixed32 => WireType::ThirtyTwoBit,
+        Type::String | Type::Bytes | Type::Message => WireType::LengthDelimited,
+        Type::Group => WireType::StartGroup,
+        _ => WireType::Varint,
+    }
+}
+
+fn confused_wire_type(expected: WireType, rng: &mut StdRng) -> WireType {
+    let candidates = [
+        WireType::Varint,
+        WireType::SixtyFourBit,
+        WireType::LengthDelimited,
+        WireType::ThirtyTwoBit,
+    ];
+    loop {
+        let candidate = candidates[rng.gen_range(0..candidates.len())];
+        if candidate != expected {
+            return candidate;
+        }
+    }
+}
+
+fn encode_raw_value(
+    wire_type: WireType,
+    options: &ProtobufOptions,
+    rng: &mut StdRng,
+    buf: &mut Vec<u8>,
+) {
+    match wire_type {
+        WireType::Varint => encode_varint(random_varint_value(rng), buf),
+        WireType::SixtyFourBit => buf.extend_from_slice(&rng.gen::<u64>().to_le_bytes()),
+        WireType::ThirtyTwoBit => buf.extend_from_slice(&rng.gen::<u32>().to_le_bytes()),
+        WireType::LengthDelimited => {
+            let len = rng.gen_range(0..=options.max_bytes_length);
+            encode_varint(len as u64, buf);
+            buf.extend((0..len).map(|_| rng.gen::<u8>()));
+        }
+        WireType::StartGroup | WireType::EndGroup => {}
+    }
+}
+
+fn random_varint_value(rng: &mut StdRng) -> u64 {
+    if rng.gen_bool(0.3) {
+        INTERESTING_VARINTS[rng.gen_range(0..INTERESTING_VARINTS.len())]
+    } else {
+        let bits = rng.gen_range(1..=64);
+        rng.gen::<u64>() >> (64 - bits)
+    }
+}
+
+/// A string of at most `max_len` bytes.
+fn random_string(max_len: usize, rng: &mut StdRng) -> Vec<u8> {
+    let len = rng.gen_range(0..=max_len);
+    if rng.gen_bool(0.05) {
+        // Invalid UTF-8 in a string field is a classic decoder edge case.
+        return (0..len).map(|_| rng.gen::<u8>()).collect();
+    }
+    let mut value = String::with_capacity(len);
+    while value.len() < len {
+        let c = rng.gen::<char>();
+        if value.len() + c.len_utf8() > len {
+            // Fill the remainder with ASCII rather than overshoot.
+            value.extend((value.len()..len).map(|_| rng.gen_range(b' '..=b'~') as char));
+            break;
+        }
+        value.push(c);
+    }
+    value.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::encoding::{decode_key, decode_varint};
+    use prost_types::{FieldDescriptorProto, FileDescriptorProto};
+    use rand::SeedableRng;
+
+    fn field(name: &str, number: i32, kind: Type, label: Label) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            r#type: Some(kind as i32),
+            label: Some(label as i32),
+            type_name: matches!(kind, Type::Message | Type::Group).then(|| ".test.Node".into()),
+            ..Default::default()
+        }
+    }
+
+    fn schema(fields: Vec<FieldDescriptorProto>) -> Result<ProtobufSchema, FuzzerError> {
+        let set = FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                package: Some("test".to_string()),
+                message_type: vec![DescriptorProto {
+                    name: Some("Node".to_string()),
+                    field: fields,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        ProtobufSchema::from_descriptor_set(&set, "test.Node")
+    }
+
+    fn well_formed() -> ProtobufOptions {
+        ProtobufOptions {
+            unknown_field_rate: 0.0,
+            wire_type_confusion_rate: 0.0,
+            ..Default::default()
+        }
+    }
+
+    /// Walks a message, returning its nesting depth. Panics on malformed wire data.
+    fn parse(mut buf: &[u8], group: Option<u32>) -> (usize, usize) {
+        let start = buf.len();
+        let mut depth = 0;
+        while !buf.is_empty() {
+            let (number, wire_type) = decode_key(&mut buf).unwrap();
+            match wire_type {
+                WireType::Varint => {
+                    decode_varint(&mut buf).unwrap();
+                }
+                WireType::SixtyFourBit => buf = &buf[8..],
+                WireType::ThirtyTwoBit => buf = &buf[4..],
+                WireType::LengthDelimited => {
+                    let len = decode_varint(&mut buf).unwrap() as usize;
+                    let (value, rest) = buf.split_at(len);
+                    if number == 1 {
+                        depth = depth.max(parse(value, None).0 + 1);
+                    }
+                    buf = rest;
+                }
+                WireType::StartGroup => {
+                    let (nested, used) = parse(buf, Some(number));
+                    depth = depth.max(nested + 1);
+                    buf = &buf[used..];
+                }
+                WireType::EndGroup => {
+                    assert_eq!(group, Some(number));
+                    return (depth, start - buf.len());
+                }
+            }
+        }
+        assert_eq!(group, None, "unterminated group");
+        (depth, start)
+    }
+
+    #[test]
+    fn generated_messages_are_well_formed() {
+        let schema = schema(vec![
+            field("child", 1, Type::Message, Label::Optional),
+            field("values", 2, Type::Sint64, Label::Repeated),
+            field("name", 3, Type::String, Label::Optional),
+            field("group", 4, Type::Group, Label::Optional),
+            field("data", 5, Type::Bytes, Label::Optional),
+        ])
+        .unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..200 {
+            let (depth, _) = parse(&schema.generate(&well_formed(), &mut rng), None);
+            assert!(depth <= well_formed().max_depth);
+        }
+    }
+
+    #[test]
+    fn required_recursion_stops_at_max_depth() {
+        let schema = schema(vec![field("child", 1, Type::Message, Label::Required)]).unwrap();
+        let options = ProtobufOptions {
+            max_depth: 3,
+            ..well_formed()
+        };
+        let message = schema.generate(&options, &mut StdRng::seed_from_u64(2));
+        assert_eq!(parse(&message, None).0, 4);
+    }
+
+    #[test]
+    fn invalid_field_numbers_are_rejected() {
+        for number in [0, -1, MAX_FIELD_NUMBER as i32 + 1] {
+            assert!(schema(vec![field("x", number, Type::Int32, Label::Optional)]).is_err());
+        }
+    }
+
+    #[test]
+    fn strings_are_bounded_in_bytes() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..500 {
+            assert!(random_string(5, &mut rng).len() <= 5);
+        }
+    }
+}
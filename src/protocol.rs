@@ -0,0 +1,482 @@
+use crate::errors::FuzzerError;
+use crate::network::NetworkTarget;
+use crate::utils::error_signature;
+use rand::{prelude::StdRng, Rng};
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, future::Future, sync::Arc};
+
+/// The state of every session before its first message.
+pub const INITIAL_STATE: &str = "init";
+
+/// Upper bound on sessions spent minimizing one crash.
+const MAX_MINIMIZATION_RUNS: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct ProtocolOptions {
+    pub max_messages: usize,
+    pub max_message_size: usize,
+    /// Sessions kept for mutation.
+    pub corpus_size: usize,
+    /// Shrink crashing sessions before recording them.
+    pub minimize: bool,
+}
+
+impl Default for ProtocolOptions {
+    fn default() -> Self {
+        ProtocolOptions {
+            max_messages: 32,
+            max_message_size: 1024,
+            corpus_size: 256,
+            minimize: true,
+        }
+    }
+}
+
+type Extractor = Arc<dyn Fn(&[u8]) -> Option<String> + Send + Sync>;
+
+/// Extracts a response code from a server response. The code is the state the server is in
+/// after the message it answered.
+#[derive(Clone)]
+pub enum StateExtractor {
+    /// The first capture group of the first match, or the whole match if the pattern has no
+    /// groups.
+    Regex(Regex),
+    Custom(Extractor),
+}
+
+impl StateExtractor {
+    pub fn regex(pattern: &str) -> Result<Self, FuzzerError> {
+        Regex::new(pattern).map(StateExtractor::Regex).map_err(|e| {
+            FuzzerError::CustomError(format!("Invalid state pattern {}: {}", pattern, e))
+        })
+    }
+
+    pub fn custom<F>(extract: F) -> Self
+    where
+        F: Fn(&[u8]) -> Option<String> + Send + Sync + 'static,
+    {
+        StateExtractor::Custom(Arc::new(extract))
+    }
+
+    pub fn extract(&self, response: &[u8]) -> Option<String> {
+        match self {
+            StateExtractor::Regex(regex) => {
+                let captures = regex.captures(response)?;
+                let code = captures.get(1).or_else(|| captures.get(0))?;
+                Some(String::from_utf8_lossy(code.as_bytes()).into_owned())
+            }
+            StateExtractor::Custom(extract) => extract(response),
+        }
+    }
+}
+
+impl fmt::Debug for StateExtractor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateExtractor::Regex(regex) => f.debug_tuple("Regex").field(&regex.as_str()).finish(),
+            StateExtractor::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// A network server fuzzed with sessions of messages, whose protocol states are inferred
+/// from its responses.
+#[derive(Debug)]
+pub struct ProtocolTarget {
+    name: String,
+    network: NetworkTarget,
+    extractor: StateExtractor,
+    seeds: Vec<Vec<Vec<u8>>>,
+}
+
+impl ProtocolTarget {
+    pub fn new(name: &str, network: NetworkTarget, extractor: StateExtractor) -> Self {
+        ProtocolTarget {
+            name: name.to_string(),
+            network,
+            extractor,
+            seeds: Vec::new(),
+        }
+    }
+
+    /// Adds a recorded session, such as a valid login, to start fuzzing from.
+    pub fn seed(mut self, messages: Vec<Vec<u8>>) -> Self {
+        self.seeds.push(messages);
+        self
+    }
+
+    /// Adds a session given as one input, split into messages by the network target's
+    /// framing.
+    pub fn seed_input(self, input: &[u8]) -> Self {
+        let messages = self.network.split(input);
+        self.seed(messages)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub const fn network(&self) -> &NetworkTarget {
+        &self.network
+    }
+
+    pub fn seeds(&self) -> &[Vec<Vec<u8>>] {
+        &self.seeds
+    }
+
+    /// Runs a session and returns the states it went through, starting with
+    /// [`INITIAL_STATE`]. A response without a code leaves the state unchanged.
+    pub async fn run(&self, messages: &[Vec<u8>]) -> Result<Vec<String>, FuzzerError> {
+        let responses = self.network.send_messages(messages).await?;
+        let mut states = vec![INITIAL_STATE.to_string()];
+        for response in &responses {
+            let state = self
+                .extractor
+                .extract(response)
+                .unwrap_or_else(|| states[states.len() - 1].clone());
+            states.push(state);
+        }
+        Ok(states)
+    }
+}
+
+/// A session kept for mutation, with the state after each of its messages.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub messages: Vec<Vec<u8>>,
+    /// [`INITIAL_STATE`] followed by the state after each message the server answered.
+    pub states: Vec<String>,
+}
+
+impl Session {
+    /// Number of messages that lead into `state`, if the session reaches it.
+    pub fn prefix_len(&self, state: &str) -> Option<usize> {
+        self.states.iter().position(|s| s == state)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateStats {
+    /// Sessions that went through the state.
+    pub visits: usize,
+    /// Times the state was chosen to fuzz from.
+    pub selections: usize,
+}
+
+/// The states and transitions seen so far.
+#[derive(Debug, Clone, Default)]
+pub struct StateMachine {
+    states: BTreeMap<String, StateStats>,
+    transitions: BTreeMap<(String, String), usize>,
+}
+
+impl StateMachine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn states(&self) -> impl Iterator<Item = (&str, &StateStats)> {
+        self.states
+            .iter()
+            .map(|(state, stats)| (state.as_str(), stats))
+    }
+
+    /// Transitions with the number of times sessions took them.
+    pub fn transitions(&self) -> impl Iterator<Item = (&str, &str, usize)> {
+        self.transitions
+            .iter()
+            .map(|((from, to), count)| (from.as_str(), to.as_str(), *count))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /// Records the states a session went through. Returns true if it reached a state or took
+    /// a transition not seen before.
+    pub fn record(&mut self, states: &[String]) -> bool {
+        let mut new = false;
+        let mut seen: Vec<&String> = Vec::new();
+        for state in states {
+            if !seen.contains(&state) {
+                seen.push(state);
+                let stats = self.states.entry(state.clone()).or_insert_with(|| {
+                    new = true;
+                    StateStats::default()
+                });
+                stats.visits += 1;
+            }
+        }
+        for pair in states.windows(2) {
+            let count = self
+                .transitions
+                .entry((pair[0].clone(), pair[1].clone()))
+                .or_insert_with(|| {
+                    new = true;
+                    0
+                });
+            *count += 1;
+        }
+        new
+    }
+
+    /// Chooses a state accepted by `reachable`, favoring states few sessions went through.
+    pub fn select(&mut self, rng: &mut StdRng, reachable: impl Fn(&str) -> bool) -> Option<String> {
+        let candidates: Vec<(&String, f64)> = self
+            .states
+            .iter()
+            .filter(|(state, _)| reachable(state))
+            .map(|(state, stats)| (state, 1.0 / (1 + stats.visits + stats.selections) as f64))
+            .collect();
+        let total: f64 = candidates.iter().map(|(_, weight)| weight).sum();
+        let mut pick = rng.gen_range(0.0..total.max(f64::MIN_POSITIVE));
+        let state = candidates
+            .iter()
+            .find(|(_, weight)| {
+                pick -= weight;
+                pick < 0.0
+            })
+            .or(candidates.last())?
+            .0
+            .clone();
+        if let Some(stats) = self.states.get_mut(&state) {
+            stats.selections += 1;
+        }
+        Some(state)
+    }
+
+    /// The machine in Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph protocol {\n");
+        for (state, stats) in &self.states {
+            dot.push_str(&format!(
+                "    {:?} [label=\"{} ({})\"];\n",
+                state,
+                state.escape_debug(),
+                stats.visits
+            ));
+        }
+        for ((from, to), count) in &self.transitions {
+            dot.push_str(&format!(
+                "    {:?} -> {:?} [label=\"{}\"];\n",
+                from, to, count
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// A session that crashed the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolCrash {
+    pub target: String,
+    pub error: String,
+    pub messages: Vec<Vec<u8>>,
+}
+
+/// Cuts a session down to `max_messages` messages of at most `max_message_size` bytes.
+pub fn truncate(messages: &mut Vec<Vec<u8>>, options: &ProtocolOptions) {
+    messages.truncate(options.max_messages);
+    for message in messages {
+        message.truncate(options.max_message_size);
+    }
+}
+
+/// Shrinks a crashing session while it keeps crashing with the same error: first by removing
+/// messages, then by shortening them. `run` returns `None` for a session that did not finish,
+/// which ends minimization.
+pub async fn minimize<F, Fut>(messages: &[Vec<u8>], error: &str, mut run: F) -> Vec<Vec<u8>>
+where
+    F: FnMut(Vec<Vec<u8>>) -> Fut,
+    Fut: Future<Output = Option<Result<(), String>>>,
+{
+    let mut messages = messages.to_vec();
+    let error = error_signature(error);
+    let mut runs = 0;
+
+    let mut chunk = messages.len() / 2;
+    while chunk > 0 {
+        let mut start = 0;
+        while start < messages.len() && messages.len() > 1 {
+            let mut candidate = messages.clone();
+            candidate.drain(start..(start + chunk).min(messages.len()));
+            if crashes_with(&mut run, &mut runs, candidate.clone(), &error).await {
+                messages = candidate;
+            } else {
+                start += chunk;
+            }
+        }
+        chunk /= 2;
+    }
+
+    for index in 0..messages.len() {
+        let mut len = messages[index].len() / 2;
+        loop {
+            let mut candidate = messages.clone();
+            candidate[index].truncate(len);
+            if crashes_with(&mut run, &mut runs, candidate.clone(), &error).await {
+                messages = candidate;
+                if len == 0 {
+                    break;
+                }
+                len /= 2;
+            } else {
+                break;
+            }
+        }
+    }
+    messages
+}
+
+/// Runs a candidate of [`minimize`] if runs are left, and tells whether it crashed with an
+/// error of the given signature. A session that did not finish uses up the remaining runs.
+async fn crashes_with<F, Fut>(
+    run: &mut F,
+    runs: &mut usize,
+    candidate: Vec<Vec<u8>>,
+    signature: &str,
+) -> bool
+where
+    F: FnMut(Vec<Vec<u8>>) -> Fut,
+    Fut: Future<Output = Option<Result<(), String>>>,
+{
+    if *runs >= MAX_MINIMIZATION_RUNS {
+        return false;
+    }
+    *runs += 1;
+    match run(candidate).await {
+        Some(Err(e)) => error_signature(&e) == signature,
+        Some(Ok(())) => false,
+        None => {
+            *runs = MAX_MINIMIZATION_RUNS;
+            false
+        }
+    }
+}
+
+/// Applies one change to the messages after the first `prefix_len`, which lead into the state
+/// being fuzzed and are kept as they are. `mutate_message` mutates the bytes of one message;
+/// `donor` is another session's messages to splice from.
+pub fn mutate<F>(
+    messages: &mut Vec<Vec<u8>>,
+    prefix_len: usize,
+    donor: &[Vec<u8>],
+    options: &ProtocolOptions,
+    rng: &mut StdRng,
+    mut mutate_message: F,
+) where
+    F: FnMut(&[u8]) -> Vec<u8>,
+{
+    let prefix_len = prefix_len.min(messages.len());
+    let room = messages.len() < options.max_messages;
+    if messages.len() == prefix_len {
+        if room {
+            let message = random_message(donor, options, rng);
+            messages.push(message);
+        }
+        return;
+    }
+    let index = rng.gen_range(prefix_len..messages.len());
+    match rng.gen_range(0..6) {
+        0 if room => {
+            let at = rng.gen_range(prefix_len..=messages.len());
+            let message = random_message(donor, options, rng);
+            messages.insert(at, message);
+        }
+        1 => {
+            messages.remove(index);
+        }
+        2 if room => {
+            // Repeated messages find state confusion, such as a second login.
+            messages.insert(index, messages[index].clone());
+        }
+        3 => {
+            let other = rng.gen_range(prefix_len..messages.len());
+            messages.swap(index, other);
+        }
+        4 if !donor.is_empty() => {
+            // Continue with the tail of another session.
+            let from = rng.gen_range(0..donor.len());
+            messages.truncate(index);
+            let space = options.max_messages.saturating_sub(messages.len());
+            messages.extend(donor[from..].iter().take(space).cloned());
+        }
+        _ => {
+            let mut message = mutate_message(&messages[index]);
+            message.truncate(options.max_message_size);
+            messages[index] = message;
+        }
+    }
+}
+
+fn random_message(donor: &[Vec<u8>], options: &ProtocolOptions, rng: &mut StdRng) -> Vec<u8> {
+    if !donor.is_empty() && rng.gen_bool(0.75) {
+        return donor[rng.gen_range(0..donor.len())].clone();
+    }
+    let len = rng.gen_range(1..=options.max_message_size.clamp(1, 64));
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Crashes on a session with a `QUIT` after a `LOGIN`, naming a different connection
+    /// every time.
+    fn run(messages: &[Vec<u8>], runs: &mut usize) -> Option<Result<(), String>> {
+        *runs += 1;
+        let login = messages.iter().position(|m| m.starts_with(b"LOGIN"));
+        let quit = messages.iter().rposition(|m| m.starts_with(b"QUIT"));
+        match (login, quit) {
+            (Some(login), Some(quit)) if login < quit => {
+                Some(Err(format!("connection {} reset", *runs)))
+            }
+            _ => Some(Ok(())),
+        }
+    }
+
+    #[tokio::test]
+    async fn minimizes_crashing_sessions() {
+        let messages: Vec<Vec<u8>> = vec![
+            b"HELO".to_vec(),
+            b"LOGIN admin".to_vec(),
+            b"NOOP".to_vec(),
+            b"QUIT now".to_vec(),
+            b"NOOP".to_vec(),
+        ];
+        let mut runs = 0;
+        let minimal = minimize(&messages, "connection 0 reset", |candidate| {
+            let result = run(&candidate, &mut runs);
+            async move { result }
+        })
+        .await;
+        assert_eq!(minimal, [b"LOGIN".to_vec(), b"QUIT".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn unfinished_sessions_stop_minimization() {
+        let messages = vec![b"LOGIN".to_vec(), b"QUIT".to_vec(), b"NOOP".to_vec()];
+        let mut attempts = 0;
+        let minimal = minimize(&messages, "connection 0 reset", |_| {
+            attempts += 1;
+            async { None }
+        })
+        .await;
+        assert_eq!(attempts, 1);
+        assert_eq!(minimal, messages);
+    }
+
+    #[test]
+    fn truncates_sessions_to_the_limits() {
+        let options = ProtocolOptions {
+            max_messages: 2,
+            max_message_size: 3,
+            ..Default::default()
+        };
+        let mut messages = vec![b"abcdef".to_vec(), b"ab".to_vec(), b"abcd".to_vec()];
+        truncate(&mut messages, &options);
+        assert_eq!(messages, [b"abc".to_vec(), b"ab".to_vec()]);
+    }
+}
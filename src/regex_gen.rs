@@ -0,0 +1,245 @@
+use crate::errors::FuzzerError;
+use rand::{prelude::StdRng, Rng};
+use regex::bytes::Regex;
+use regex_syntax::hir::{Class, Hir, HirKind};
+use regex_syntax::Parser;
+
+const NEAR_MISS_ATTEMPTS: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct RegexOptions {
+    /// Extra repetitions allowed beyond the minimum of an unbounded repetition such as `*` or `+`.
+    pub max_repeat: u32,
+    /// Probability of generating a string that just fails to match instead of a match.
+    pub near_miss_rate: f64,
+}
+
+impl Default for RegexOptions {
+    fn default() -> Self {
+        RegexOptions {
+            max_repeat: 8,
+            near_miss_rate: 0.0,
+        }
+    }
+}
+
+/// Produces strings matching a regular expression by walking its `regex-syntax` HIR.
+#[derive(Debug, Clone)]
+pub struct RegexGenerator {
+    pattern: String,
+    hir: Hir,
+    max_repeat: u32,
+    /// The pattern anchored at both ends, used to check near misses.
+    matcher: Regex,
+    sites: usize,
+}
+
+impl RegexGenerator {
+    pub fn new(pattern: &str, max_repeat: u32) -> Result<Self, FuzzerError> {
+        let hir = Parser::new().parse(pattern).map_err(|e| {
+            FuzzerError::InputGenerationError(format!("Invalid regex {}: {}", pattern, e))
+        })?;
+        let matcher = Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| {
+            FuzzerError::InputGenerationError(format!("Invalid regex {}: {}", pattern, e))
+        })?;
+        Ok(RegexGenerator {
+            pattern: pattern.to_string(),
+            sites: count_sites(&hir),
+            hir,
+            max_repeat,
+            matcher,
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn generate(&self, rng: &mut StdRng) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.walk(&self.hir, rng, &mut out, &mut None);
+        out
+    }
+
+    /// Generates a string that does not match the whole pattern but differs from a match in
+    /// a single place: one literal byte, one class member or one repetition count.
+    pub fn generate_near_miss(&self, rng: &mut StdRng) -> Vec<u8> {
+        let mut out = Vec::new();
+        for _ in 0..NEAR_MISS_ATTEMPTS {
+            out.clear();
+            let mut site = Some(rng.gen_range(0..self.sites.max(1)));
+            self.walk(&self.hir, rng, &mut out, &mut site);
+            if !self.matcher.is_match(&out) {
+                return out;
+            }
+        }
+        // Patterns such as `.*` have few or no near misses; a trailing byte may still miss.
+        out.push(b'\n');
+        out
+    }
+
+    pub fn is_match(&self, data: &[u8]) -> bool {
+        self.matcher.is_match(data)
+    }
+
+    pub fn generate_string(&self, rng: &mut StdRng) -> String {
+        String::from_utf8_lossy(&self.generate(rng)).into_owned()
+    }
+
+    /// `site` counts down the violation sites still to pass; the site where it reaches zero
+    /// is generated wrongly.
+    fn walk(&self, hir: &Hir, rng: &mut StdRng, out: &mut Vec<u8>, site: &mut Option<usize>) {
+        match hir.kind() {
+            HirKind::Empty | HirKind::Look(_) => {}
+            HirKind::Literal(literal) => {
+                if at_site(site) {
+                    push_wrong_literal(&literal.0, rng, out);
+                } else {
+                    out.extend_from_slice(&literal.0);
+                }
+            }
+            HirKind::Class(class) => {
+                if at_site(site) {
+                    push_class_outsider(class, rng, out);
+                } else {
+                    push_class_member(class, rng, out);
+                }
+            }
+            HirKind::Repetition(repetition) => {
+                // Unbounded repetitions are capped so that `.*` stays reasonably short.
+                let max = repetition
+                    .max
+                    .unwrap_or(repetition.min.saturating_add(self.max_repeat))
+                    .max(repetition.min);
+                let count = if at_site(site) {
+                    match repetition.max {
+                        Some(max) if repetition.min == 0 || rng.gen_bool(0.5) => max + 1,
+                        _ => repetition.min.saturating_sub(1),
+                    }
+                } else {
+                    rng.gen_range(repetition.min..=max)
+                };
+                for _ in 0..count {
+                    self.walk(&repetition.sub, rng, out, site);
+                }
+            }
+            HirKind::Capture(capture) => self.walk(&capture.sub, rng, out, site),
+            HirKind::Concat(subs) => {
+                for sub in subs {
+                    self.walk(sub, rng, out, site);
+                }
+            }
+            HirKind::Alternation(subs) => {
+                let sub = &subs[rng.gen_range(0..subs.len())];
+                self.walk(sub, rng, out, site);
+            }
+        }
+    }
+}
+
+fn at_site(site: &mut Option<usize>) -> bool {
+    match site {
+        Some(0) => {
+            *site = None;
+            true
+        }
+        Some(remaining) => {
+            *remaining -= 1;
+            false
+        }
+        None => false,
+    }
+}
+
+fn count_sites(hir: &Hir) -> usize {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => 0,
+        HirKind::Literal(_) | HirKind::Class(_) => 1,
+        HirKind::Repetition(repetition) => 1 + count_sites(&repetition.sub),
+        HirKind::Capture(capture) => count_sites(&capture.sub),
+        HirKind::Concat(subs) | HirKind::Alternation(subs) => subs.iter().map(count_sites).sum(),
+    }
+}
+
+fn push_wrong_literal(literal: &[u8], rng: &mut StdRng, out: &mut Vec<u8>) {
+    let mut bytes = literal.to_vec();
+    let index = rng.gen_range(0..bytes.len());
+    match rng.gen_range(0..3) {
+        0 => {
+            bytes.remove(index);
+        }
+        1 => bytes.insert(index, bytes[index]),
+        _ => {
+            bytes[index] = match bytes[index] {
+                b if b.is_ascii_alphabetic() => b ^ 0x20,
+                b => b.wrapping_add(1),
+            }
+        }
+    }
+    out.extend_from_slice(&bytes);
+}
+
+/// Pushes a character just outside one of the class ranges, or nothing at all.
+fn push_class_outsider(class: &Class, rng: &mut StdRng, out: &mut Vec<u8>) {
+    match class {
+        Class::Unicode(class) => {
+            let ranges = class.ranges();
+            if ranges.is_empty() || rng.gen_bool(0.2) {
+                return;
+            }
+            let range = ranges[rng.gen_range(0..ranges.len())];
+            let candidates = [
+                (range.start() as u32).checked_sub(1),
+                (range.end() as u32).checked_add(1),
+            ];
+            let outside = candidates
+                .into_iter()
+                .flatten()
+                .filter_map(char::from_u32)
+                .find(|c| !ranges.iter().any(|r| r.start() <= *c && *c <= r.end()));
+            if let Some(c) = outside {
+                let mut buf = [0; 4];
+                out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+        Class::Bytes(class) => {
+            let ranges = class.ranges();
+            if ranges.is_empty() || rng.gen_bool(0.2) {
+                return;
+            }
+            let range = ranges[rng.gen_range(0..ranges.len())];
+            let outside = [range.start().checked_sub(1), range.end().checked_add(1)]
+                .into_iter()
+                .flatten()
+                .find(|b| !ranges.iter().any(|r| r.start() <= *b && *b <= r.end()));
+            if let Some(b) = outside {
+                out.push(b);
+            }
+        }
+    }
+}
+
+fn push_class_member(class: &Class, rng: &mut StdRng, out: &mut Vec<u8>) {
+    match class {
+        Class::Unicode(class) => {
+            let ranges = class.ranges();
+            if ranges.is_empty() {
+                return;
+            }
+            let range = ranges[rng.gen_range(0..ranges.len())];
+            let code = rng.gen_range(range.start() as u32..=range.end() as u32);
+            // Ranges spanning the surrogate block contain unrepresentable code points.
+            let c = char::from_u32(code).unwrap_or(range.start());
+            let mut buf = [0; 4];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+        Class::Bytes(class) => {
+            let ranges = class.ranges();
+            if ranges.is_empty() {
+                return;
+            }
+            let range = ranges[rng.gen_range(0..ranges.len())];
+            out.push(rng.gen_range(range.start()..=range.end()));
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use crate::template::BinaryTemplate;
+use serde::{Deserialize, Serialize};
+use std::{fmt, ops::Range, sync::Arc};
+
+/// What byte-level mutation may do to a region of an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegionKind {
+    /// Never changed.
+    Frozen,
+    /// Only replaced as a whole by a dictionary word.
+    DictionaryOnly,
+    /// An integer of up to 8 bytes, changed by arithmetic, bit flips and boundary values.
+    Numeric,
+    /// Any operator, including ones that change the input's length.
+    Free,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Region {
+    pub range: Range<usize>,
+    pub kind: RegionKind,
+}
+
+/// Marks ranges of an input with the operators allowed to touch them. Bytes outside every
+/// region are free.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegionMap {
+    /// Sorted and non-overlapping.
+    regions: Vec<Region>,
+}
+
+impl RegionMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks `range`, replacing whatever the overlapping parts were marked before.
+    pub fn mark(&mut self, range: Range<usize>, kind: RegionKind) {
+        if range.is_empty() {
+            return;
+        }
+        let mut regions = Vec::with_capacity(self.regions.len() + 2);
+        for region in self.regions.drain(..) {
+            if region.range.start < range.start {
+                regions.push(Region {
+                    range: region.range.start..region.range.end.min(range.start),
+                    kind: region.kind,
+                });
+            }
+            if region.range.end > range.end {
+                regions.push(Region {
+                    range: region.range.start.max(range.end)..region.range.end,
+                    kind: region.kind,
+                });
+            }
+        }
+        regions.push(Region { range, kind });
+        regions.sort_by_key(|region| region.range.start);
+        self.regions = regions;
+    }
+
+    pub fn with(mut self, range: Range<usize>, kind: RegionKind) -> Self {
+        self.mark(range, kind);
+        self
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    pub fn region_at(&self, index: usize) -> Option<&Region> {
+        self.regions
+            .iter()
+            .find(|region| region.range.contains(&index))
+    }
+
+    pub fn kind_at(&self, index: usize) -> RegionKind {
+        self.region_at(index)
+            .map_or(RegionKind::Free, |region| region.kind)
+    }
+
+    /// Indices below `len` whose kind is accepted by `allowed`.
+    pub fn positions(&self, len: usize, allowed: impl Fn(RegionKind) -> bool) -> Vec<usize> {
+        (0..len).filter(|&i| allowed(self.kind_at(i))).collect()
+    }
+
+    /// End of the run of free bytes starting at `index`, capped at `len`.
+    pub fn free_run_end(&self, index: usize, len: usize) -> usize {
+        self.regions
+            .iter()
+            .filter(|region| region.kind != RegionKind::Free && region.range.start >= index)
+            .map(|region| region.range.start)
+            .min()
+            .unwrap_or(len)
+            .min(len)
+    }
+
+    /// Updates the map after `range` of the input was replaced by `len` bytes. Inserted bytes
+    /// belong to the region the edit started in, if it extends past the edit.
+    pub fn splice(&mut self, range: Range<usize>, len: usize) {
+        let removed = range.end - range.start;
+        let moved = |x: usize| {
+            if x <= range.start {
+                x
+            } else if x >= range.end {
+                x - removed + len
+            } else {
+                range.start
+            }
+        };
+        for region in &mut self.regions {
+            region.range = moved(region.range.start)..moved(region.range.end);
+        }
+        self.regions.retain(|region| !region.range.is_empty());
+    }
+}
+
+/// Computes the region map of an input that has none attached.
+pub trait RegionProvider: Send + Sync {
+    fn regions(&self, data: &[u8]) -> RegionMap;
+}
+
+impl<F> RegionProvider for F
+where
+    F: Fn(&[u8]) -> RegionMap + Send + Sync,
+{
+    fn regions(&self, data: &[u8]) -> RegionMap {
+        self(data)
+    }
+}
+
+/// Where region maps come from for inputs without one attached.
+#[derive(Clone)]
+pub enum RegionSource {
+    /// Magic values, lengths, counts and checksums are frozen, integers numeric and blobs
+    /// free. Inputs the template cannot decode are unrestricted.
+    Template(BinaryTemplate),
+    Provider(Arc<dyn RegionProvider>),
+}
+
+impl RegionSource {
+    pub fn provider<P>(provider: P) -> Self
+    where
+        P: RegionProvider + 'static,
+    {
+        RegionSource::Provider(Arc::new(provider))
+    }
+
+    pub fn regions(&self, data: &[u8]) -> RegionMap {
+        match self {
+            RegionSource::Template(template) => template.regions(data).unwrap_or_default(),
+            RegionSource::Provider(provider) => provider.regions(data),
+        }
+    }
+}
+
+impl fmt::Debug for RegionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegionSource::Template(template) => {
+                f.debug_tuple("Template").field(&template.name).finish()
+            }
+            RegionSource::Provider(_) => f.write_str("Provider"),
+        }
+    }
+}
@@ -21,7 +21,7 @@ impl CrashReproducer {
     }
 
     pub async fn reproduce(&self, crash_input: &[u8]) -> Result<(), FuzzerError> {
-        match self.target.execute(crash_input).await {
+        match self.execute(crash_input).await? {
             Ok(_) => Err(FuzzerError::ReproductionFailed(
                 "No crash occurred".to_string(),
             )),
@@ -37,7 +37,7 @@ impl CrashReproducer {
         crash_input: &[u8],
         log_file: &str,
     ) -> Result<(), FuzzerError> {
-        match self.target.execute(crash_input).await {
+        match self.execute(crash_input).await? {
             Ok(_) => Err(FuzzerError::ReproductionFailed(
                 "No crash occurred".to_string(),
             )),
@@ -52,4 +52,16 @@ impl CrashReproducer {
             }
         }
     }
+
+    /// Runs the input between the target's setup and teardown hooks. Hook failures are
+    /// returned as errors rather than as the target's outcome.
+    async fn execute(&self, crash_input: &[u8]) -> Result<Result<(), FuzzerError>, FuzzerError> {
+        self.target.setup()?;
+        let result = self.target.execute(crash_input).await;
+        self.target.teardown()?;
+        match result {
+            Err(e @ FuzzerError::HookError(_)) => Err(e),
+            result => Ok(result),
+        }
+    }
 }
@@ -0,0 +1,84 @@
+use rand::{prelude::StdRng, Rng};
+
+/// Weight of the latest execution in a target's coverage yield.
+const YIELD_RATE: f64 = 0.1;
+/// Yield credited to every target, so ones that stopped finding coverage still get some time.
+const YIELD_FLOOR: f64 = 0.05;
+
+/// How the iterations of a run are shared among targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Schedule {
+    /// Every target runs in every iteration.
+    #[default]
+    EveryTarget,
+    /// One target runs per iteration, chosen in proportion to its weight.
+    Weighted,
+    /// One target runs per iteration, chosen in proportion to its weight times the new
+    /// coverage its recent executions found.
+    CoverageYield,
+}
+
+/// Chooses the target to run in each iteration.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    schedule: Schedule,
+    weights: Vec<f64>,
+    /// Moving average of the blocks each execution of a target newly covered.
+    yields: Vec<f64>,
+}
+
+impl Scheduler {
+    pub fn new(schedule: Schedule, weights: Vec<f64>) -> Self {
+        let weights: Vec<f64> = weights
+            .into_iter()
+            .map(|w| if w.is_finite() { w.max(0.0) } else { 0.0 })
+            .collect();
+        Scheduler {
+            schedule,
+            yields: vec![0.0; weights.len()],
+            weights,
+        }
+    }
+
+    /// Indices of the targets to run in the next iteration.
+    pub fn next(&self, rng: &mut StdRng) -> Vec<usize> {
+        if self.weights.is_empty() {
+            return Vec::new();
+        }
+        if self.schedule == Schedule::EveryTarget {
+            return (0..self.weights.len()).collect();
+        }
+        let shares: Vec<f64> = (0..self.weights.len()).map(|i| self.share(i)).collect();
+        let total: f64 = shares.iter().sum();
+        if total <= 0.0 {
+            return vec![rng.gen_range(0..shares.len())];
+        }
+        let mut pick = rng.gen_range(0.0..total);
+        let index = shares
+            .iter()
+            .position(|share| {
+                pick -= share;
+                pick < 0.0
+            })
+            .unwrap_or(shares.len() - 1);
+        vec![index]
+    }
+
+    /// Records how many blocks an execution of a target newly covered.
+    pub fn record_yield(&mut self, index: usize, new_blocks: usize) {
+        if let Some(y) = self.yields.get_mut(index) {
+            *y += YIELD_RATE * (new_blocks as f64 - *y);
+        }
+    }
+
+    pub fn yields(&self) -> &[f64] {
+        &self.yields
+    }
+
+    fn share(&self, index: usize) -> f64 {
+        match self.schedule {
+            Schedule::CoverageYield => self.weights[index] * (self.yields[index] + YIELD_FLOOR),
+            _ => self.weights[index],
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use crate::errors::FuzzerError;
+use crate::structured;
+use crate::utils::{error_signature, panic_message};
+use log::debug;
+use rand::{prelude::StdRng, Rng};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+
+/// Upper bound on runs spent minimizing one failing sequence.
+const MAX_MINIMIZATION_RUNS: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct SequenceOptions {
+    pub max_calls: usize,
+    pub max_argument_size: usize,
+    /// Previously run sequences kept for mutation.
+    pub pool_size: usize,
+    /// Probability of generating a fresh sequence instead of mutating one from the pool.
+    pub generation_rate: f64,
+    /// Shrink failing sequences before recording them.
+    pub minimize: bool,
+}
+
+impl Default for SequenceOptions {
+    fn default() -> Self {
+        SequenceOptions {
+            max_calls: 16,
+            max_argument_size: 64,
+            pool_size: 64,
+            generation_rate: 0.2,
+            minimize: true,
+        }
+    }
+}
+
+/// One call in a sequence: an operation, by its index in the target, and the bytes its
+/// argument is decoded from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Call {
+    pub operation: usize,
+    pub argument: Vec<u8>,
+}
+
+/// Why a sequence failed: the index of the failing call and its error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceFailure {
+    pub call: usize,
+    pub error: String,
+}
+
+type Operation<S> = Arc<dyn Fn(&mut S, &[u8]) -> Result<(), FuzzerError> + Send + Sync>;
+
+/// A stateful component driven by sequences of operations. Every sequence runs against fresh
+/// state built by the constructor.
+pub struct SequenceTarget<S> {
+    name: String,
+    init: Arc<dyn Fn() -> S + Send + Sync>,
+    operations: Vec<(String, Operation<S>)>,
+}
+
+impl<S: 'static> SequenceTarget<S> {
+    pub fn new<F>(name: &str, init: F) -> Self
+    where
+        F: Fn() -> S + Send + Sync + 'static,
+    {
+        SequenceTarget {
+            name: name.to_string(),
+            init: Arc::new(init),
+            operations: Vec::new(),
+        }
+    }
+
+    /// Adds an operation that takes its argument as raw bytes.
+    pub fn operation<F>(mut self, name: &str, func: F) -> Self
+    where
+        F: Fn(&mut S, &[u8]) -> Result<(), FuzzerError> + Send + Sync + 'static,
+    {
+        self.operations.push((name.to_string(), Arc::new(func)));
+        self
+    }
+
+    /// Adds an operation over a typed argument, decoded with
+    /// [`structured::from_fuzz_bytes`]. Calls whose argument does not decode are skipped.
+    pub fn typed_operation<A, F>(self, name: &str, func: F) -> Self
+    where
+        A: DeserializeOwned + 'static,
+        F: Fn(&mut S, A) -> Result<(), FuzzerError> + Send + Sync + 'static,
+    {
+        self.operation(
+            name,
+            move |state: &mut S, argument: &[u8]| match structured::from_fuzz_bytes::<A>(argument) {
+                Ok(argument) => func(state, argument),
+                Err(e) => {
+                    debug!("Argument rejected by operation: {}", e);
+                    Ok(())
+                }
+            },
+        )
+    }
+}
+
+/// A [`SequenceTarget`] with its state type erased, as stored by the engine.
+pub trait SequenceRunner: Send + Sync {
+    fn name(&self) -> &str;
+    fn operation_names(&self) -> Vec<&str>;
+    /// Runs the calls in order against fresh state, stopping at the first call that fails or
+    /// panics.
+    fn run(&self, calls: &[Call]) -> Result<(), SequenceFailure>;
+}
+
+impl<S: 'static> SequenceRunner for SequenceTarget<S> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn operation_names(&self) -> Vec<&str> {
+        self.operations
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    fn run(&self, calls: &[Call]) -> Result<(), SequenceFailure> {
+        let mut state = (self.init)();
+        for (index, call) in calls.iter().enumerate() {
+            let Some((_, operation)) = self.operations.get(call.operation) else {
+                continue;
+            };
+            let result =
+                panic::catch_unwind(AssertUnwindSafe(|| operation(&mut state, &call.argument)));
+            let error = match result {
+                Ok(Ok(())) => continue,
+                Ok(Err(e)) => e.to_string(),
+                Err(payload) => format!("panicked: {}", panic_message(payload.as_ref())),
+            };
+            return Err(SequenceFailure { call: index, error });
+        }
+        Ok(())
+    }
+}
+
+/// A failing sequence as recorded by the engine, with operations by name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SequenceCrash {
+    pub target: String,
+    pub error: String,
+    pub calls: Vec<NamedCall>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamedCall {
+    pub operation: String,
+    pub argument: Vec<u8>,
+}
+
+impl SequenceCrash {
+    pub fn new(target: &dyn SequenceRunner, error: &str, calls: &[Call]) -> Self {
+        let names = target.operation_names();
+        SequenceCrash {
+            target: target.name().to_string(),
+            error: error.to_string(),
+            calls: calls
+                .iter()
+                .map(|call| NamedCall {
+                    operation: names.get(call.operation).unwrap_or(&"?").to_string(),
+                    argument: call.argument.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+pub fn generate(operations: usize, options: &SequenceOptions, rng: &mut StdRng) -> Vec<Call> {
+    let len = rng.gen_range(1..=options.max_calls.max(1));
+    (0..len)
+        .map(|_| random_call(operations, options, rng))
+        .collect()
+}
+
+/// Applies one sequence-level change. `mutate_argument` mutates the bytes of one argument.
+pub fn mutate<F>(
+    operations: usize,
+    options: &SequenceOptions,
+    rng: &mut StdRng,
+    calls: &mut Vec<Call>,
+    mut mutate_argument: F,
+) where
+    F: FnMut(&[u8]) -> Vec<u8>,
+{
+    if calls.is_empty() {
+        calls.push(random_call(operations, options, rng));
+        return;
+    }
+    let index = rng.gen_range(0..calls.len());
+    match rng.gen_range(0..6) {
+        0 if calls.len() < options.max_calls => {
+            let at = rng.gen_range(0..=calls.len());
+            calls.insert(at, random_call(operations, options, rng));
+        }
+        1 if calls.len() > 1 => {
+            calls.remove(index);
+        }
+        2 => {
+            let other = rng.gen_range(0..calls.len());
+            calls.swap(index, other);
+        }
+        3 if calls.len() < options.max_calls => {
+            // Repeated calls find double frees and stale handles.
+            let at = rng.gen_range(0..=calls.len());
+            calls.insert(at, calls[index].clone());
+        }
+        4 => calls[index].operation = rng.gen_range(0..operations),
+        _ => {
+            let argument = mutate_argument(&calls[index].argument);
+            calls[index].argument = argument;
+            calls[index].argument.truncate(options.max_argument_size);
+        }
+    }
+}
+
+/// Shrinks a failing sequence while it keeps failing in the same operation with the same
+/// error: first by removing calls, then by shortening arguments. `run` returns `None` for a
+/// run that did not finish, which ends minimization.
+pub fn minimize<F>(calls: &[Call], failure: &SequenceFailure, mut run: F) -> Vec<Call>
+where
+    F: FnMut(&[Call]) -> Option<Result<(), SequenceFailure>>,
+{
+    // Calls after the failing one never ran.
+    let mut calls = calls[..=failure.call.min(calls.len().saturating_sub(1))].to_vec();
+    let operation = calls.last().map(|call| call.operation);
+    let error = error_signature(&failure.error);
+    let mut runs = 0;
+    let mut stopped = false;
+    let mut still_fails = |candidate: &[Call]| {
+        runs += 1;
+        if stopped || runs > MAX_MINIMIZATION_RUNS {
+            return false;
+        }
+        match run(candidate) {
+            Some(Err(f)) => {
+                candidate.get(f.call).map(|call| call.operation) == operation
+                    && error_signature(&f.error) == error
+            }
+            Some(Ok(())) => false,
+            None => {
+                stopped = true;
+                false
+            }
+        }
+    };
+
+    let mut chunk = calls.len() / 2;
+    while chunk > 0 {
+        let mut start = 0;
+        while start < calls.len() && calls.len() > 1 {
+            let mut candidate = calls.clone();
+            candidate.drain(start..(start + chunk).min(calls.len()));
+            if !candidate.is_empty() && still_fails(&candidate) {
+                calls = candidate;
+            } else {
+                start += chunk;
+            }
+        }
+        chunk /= 2;
+    }
+
+    for index in 0..calls.len() {
+        let mut len = calls[index].argument.len() / 2;
+        loop {
+            let mut candidate = calls.clone();
+            candidate[index].argument.truncate(len);
+            if still_fails(&candidate) {
+                calls = candidate;
+                if len == 0 {
+                    break;
+                }
+                len /= 2;
+            } else {
+                break;
+            }
+        }
+    }
+    calls
+}
+
+fn random_call(operations: usize, options: &SequenceOptions, rng: &mut StdRng) -> Call {
+    let len = rng.gen_range(0..=options.max_argument_size);
+    Call {
+        operation: rng.gen_range(0..operations),
+        argument: (0..len).map(|_| rng.gen()).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(operation: usize, argument: &[u8]) -> Call {
+        Call {
+            operation,
+            argument: argument.to_vec(),
+        }
+    }
+
+    /// Fails at the first call of operation 1 after an operation 0, with an error naming a
+    /// different address every time.
+    fn run(calls: &[Call], runs: &mut u64) -> Result<(), SequenceFailure> {
+        *runs += 1;
+        let mut opened = false;
+        for (index, call) in calls.iter().enumerate() {
+            match call.operation {
+                0 => opened = true,
+                1 if opened => {
+                    return Err(SequenceFailure {
+                        call: index,
+                        error: format!("use after free at 0x{:x}", 0x7f00_0000 + *runs),
+                    })
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn minimizes_failures_with_varying_errors() {
+        let calls = vec![
+            call(2, b"abc"),
+            call(0, b"open this"),
+            call(2, b""),
+            call(1, b"close that"),
+        ];
+        let mut runs = 0;
+        let failure = run(&calls, &mut runs).unwrap_err();
+        let minimal = minimize(&calls, &failure, |calls| Some(run(calls, &mut runs)));
+        assert_eq!(minimal, [call(0, b""), call(1, b"")]);
+    }
+
+    #[test]
+    fn unfinished_runs_stop_minimization() {
+        let calls = vec![call(2, b"abc"), call(0, b"x"), call(1, b"y")];
+        let mut runs = 0;
+        let failure = run(&calls, &mut runs).unwrap_err();
+        let mut attempts = 0;
+        let minimal = minimize(&calls, &failure, |_| {
+            attempts += 1;
+            None
+        });
+        assert_eq!(attempts, 1);
+        assert_eq!(minimal, calls);
+    }
+
+    #[test]
+    fn error_signatures_mask_numbers() {
+        assert_eq!(
+            error_signature("slot 12 freed at 0x7ffd3a"),
+            error_signature("slot 7 freed at 0x55aa01")
+        );
+        assert_ne!(
+            error_signature("slot freed"),
+            error_signature("slot leaked")
+        );
+    }
+}
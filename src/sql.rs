@@ -0,0 +1,493 @@
+use rand::{prelude::StdRng, Rng};
+
+const TABLES: [&str; 6] = ["users", "orders", "items", "t", "accounts", "log"];
+const COLUMNS: [&str; 8] = [
+    "id",
+    "name",
+    "price",
+    "created_at",
+    "a",
+    "b",
+    "status",
+    "data",
+];
+const TYPES: [&str; 9] = [
+    "INTEGER",
+    "BIGINT",
+    "TEXT",
+    "VARCHAR(255)",
+    "DECIMAL(10, 2)",
+    "BOOLEAN",
+    "TIMESTAMP",
+    "BLOB",
+    "REAL",
+];
+const FUNCTIONS: [&str; 8] = [
+    "COUNT", "SUM", "MAX", "LOWER", "SUBSTR", "COALESCE", "LENGTH", "ABS",
+];
+const BINARY_OPERATORS: [&str; 15] = [
+    "+", "-", "*", "/", "%", "||", "=", "<>", "!=", "<", ">=", "AND", "OR", "LIKE", "IS NOT",
+];
+const JOINS: [&str; 6] = [
+    "JOIN",
+    "INNER JOIN",
+    "LEFT JOIN",
+    "LEFT OUTER JOIN",
+    "CROSS JOIN",
+    "NATURAL JOIN",
+];
+
+#[derive(Debug, Clone)]
+pub struct SqlOptions {
+    /// Maximum nesting of expressions and subqueries.
+    pub max_depth: usize,
+    pub max_items: usize,
+    pub max_statements: usize,
+    pub comment_rate: f64,
+    pub odd_quoting_rate: f64,
+    /// Keywords of the target dialect. They are used as binary operators, as trailing clauses
+    /// and as unquoted identifiers.
+    pub dialect_keywords: Vec<String>,
+}
+
+impl Default for SqlOptions {
+    fn default() -> Self {
+        SqlOptions {
+            max_depth: 8,
+            max_items: 4,
+            max_statements: 3,
+            comment_rate: 0.05,
+            odd_quoting_rate: 0.1,
+            dialect_keywords: ["LIMIT", "OFFSET", "RETURNING", "ILIKE", "GLOB", "COLLATE"]
+                .iter()
+                .map(|k| k.to_string())
+                .collect(),
+        }
+    }
+}
+
+pub struct SqlGenerator<'a> {
+    options: &'a SqlOptions,
+    rng: &'a mut StdRng,
+}
+
+impl<'a> SqlGenerator<'a> {
+    pub fn new(options: &'a SqlOptions, rng: &'a mut StdRng) -> Self {
+        SqlGenerator { options, rng }
+    }
+
+    pub fn generate(mut self) -> Vec<u8> {
+        let count = self.rng.gen_range(1..=self.options.max_statements.max(1));
+        let mut out = String::new();
+        for i in 0..count {
+            if i > 0 {
+                out.push_str(";\n");
+            }
+            out.push_str(&self.statement());
+        }
+        if self.rng.gen_bool(0.7) {
+            out.push(';');
+        }
+        out.into_bytes()
+    }
+
+    fn statement(&mut self) -> String {
+        match self.rng.gen_range(0..10) {
+            0..=3 => self.select(0),
+            4 => self.insert(),
+            5 => self.update(),
+            6 => self.delete(),
+            _ => self.ddl(),
+        }
+    }
+
+    fn select(&mut self, depth: usize) -> String {
+        let mut sql = String::new();
+        if depth < self.options.max_depth && self.rng.gen_bool(0.15) {
+            sql.push_str("WITH ");
+            if self.rng.gen_bool(0.3) {
+                sql.push_str("RECURSIVE ");
+            }
+            for i in 0..self.rng.gen_range(1..=2) {
+                if i > 0 {
+                    sql.push_str(", ");
+                }
+                let name = self.identifier();
+                let body = self.select(depth + 1);
+                sql.push_str(&format!("{} AS ({})", name, body));
+            }
+            sql.push(' ');
+        }
+
+        sql.push_str("SELECT");
+        if self.rng.gen_bool(0.1) {
+            sql.push_str(" DISTINCT");
+        }
+        sql.push_str(&self.gap());
+        if self.rng.gen_bool(0.15) {
+            sql.push('*');
+        } else {
+            let columns = self.items(|gen| {
+                let expr = gen.expr(depth + 1);
+                if gen.rng.gen_bool(0.3) {
+                    format!("{} AS {}", expr, gen.identifier())
+                } else {
+                    expr
+                }
+            });
+            sql.push_str(&columns);
+        }
+
+        if self.rng.gen_bool(0.9) {
+            sql.push_str(&format!(" FROM{}{}", self.gap(), self.table_ref(depth)));
+            for _ in 0..self.rng.gen_range(0..=self.options.max_items / 2) {
+                let join = JOINS[self.rng.gen_range(0..JOINS.len())];
+                let table = self.table_ref(depth);
+                sql.push_str(&format!(" {} {}", join, table));
+                if !join.starts_with("CROSS") && !join.starts_with("NATURAL") {
+                    let condition = self.expr(depth + 1);
+                    sql.push_str(&format!(" ON {}", condition));
+                }
+            }
+        }
+        if self.rng.gen_bool(0.6) {
+            let condition = self.expr(depth + 1);
+            sql.push_str(&format!(" WHERE{}{}", self.gap(), condition));
+        }
+        if self.rng.gen_bool(0.2) {
+            let columns = self.items(|gen| gen.column());
+            sql.push_str(&format!(" GROUP BY {}", columns));
+            if self.rng.gen_bool(0.5) {
+                let condition = self.expr(depth + 1);
+                sql.push_str(&format!(" HAVING {}", condition));
+            }
+        }
+        if self.rng.gen_bool(0.3) {
+            let columns = self.items(|gen| {
+                let direction = ["", " ASC", " DESC", " DESC NULLS LAST"][gen.rng.gen_range(0..4)];
+                format!("{}{}", gen.column(), direction)
+            });
+            sql.push_str(&format!(" ORDER BY {}", columns));
+        }
+        self.dialect_clause(&mut sql, depth);
+        if depth < self.options.max_depth && self.rng.gen_bool(0.1) {
+            let operator = ["UNION", "UNION ALL", "INTERSECT", "EXCEPT"][self.rng.gen_range(0..4)];
+            let other = self.select(depth + 1);
+            sql.push_str(&format!(" {} {}", operator, other));
+        }
+        sql
+    }
+
+    fn insert(&mut self) -> String {
+        let table = self.table_name();
+        let columns = self.items(|gen| gen.column());
+        let count = columns.split(',').count();
+        let source = if self.rng.gen_bool(0.2) {
+            self.select(1)
+        } else {
+            let rows = self.rng.gen_range(1..=self.options.max_items.max(1));
+            let rows: Vec<String> = (0..rows)
+                .map(|_| {
+                    let values: Vec<String> = (0..count).map(|_| self.expr(1)).collect();
+                    format!("({})", values.join(", "))
+                })
+                .collect();
+            format!("VALUES {}", rows.join(", "))
+        };
+        let mut sql = format!("INSERT INTO {} ({}) {}", table, columns, source);
+        self.dialect_clause(&mut sql, 1);
+        sql
+    }
+
+    fn update(&mut self) -> String {
+        let table = self.table_name();
+        let assignments = self.items(|gen| format!("{} = {}", gen.column(), gen.expr(1)));
+        let mut sql = format!("UPDATE {} SET {}", table, assignments);
+        if self.rng.gen_bool(0.8) {
+            let condition = self.expr(1);
+            sql.push_str(&format!(" WHERE {}", condition));
+        }
+        self.dialect_clause(&mut sql, 1);
+        sql
+    }
+
+    fn delete(&mut self) -> String {
+        let table = self.table_name();
+        let mut sql = format!("DELETE FROM {}", table);
+        if self.rng.gen_bool(0.8) {
+            let condition = self.expr(1);
+            sql.push_str(&format!(" WHERE {}", condition));
+        }
+        sql
+    }
+
+    fn ddl(&mut self) -> String {
+        let table = self.table_name();
+        match self.rng.gen_range(0..4) {
+            0 => {
+                let columns = self.items(|gen| {
+                    let mut column = format!(
+                        "{} {}",
+                        gen.column(),
+                        TYPES[gen.rng.gen_range(0..TYPES.len())]
+                    );
+                    match gen.rng.gen_range(0..5) {
+                        0 => column.push_str(" PRIMARY KEY"),
+                        1 => column.push_str(" NOT NULL"),
+                        2 => column.push_str(&format!(" DEFAULT {}", gen.literal())),
+                        3 => column.push_str(&format!(" CHECK ({})", gen.expr(2))),
+                        _ => {}
+                    }
+                    column
+                });
+                let exists = if self.rng.gen_bool(0.3) {
+                    "IF NOT EXISTS "
+                } else {
+                    ""
+                };
+                format!("CREATE TABLE {}{} ({})", exists, table, columns)
+            }
+            1 => format!(
+                "DROP TABLE {}{}",
+                if self.rng.gen_bool(0.5) {
+                    "IF EXISTS "
+                } else {
+                    ""
+                },
+                table
+            ),
+            2 => {
+                let column = self.column();
+                let ty = TYPES[self.rng.gen_range(0..TYPES.len())];
+                format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, ty)
+            }
+            _ => {
+                let index = self.identifier();
+                let columns = self.items(|gen| gen.column());
+                let unique = if self.rng.gen_bool(0.3) {
+                    "UNIQUE "
+                } else {
+                    ""
+                };
+                format!(
+                    "CREATE {}INDEX {} ON {} ({})",
+                    unique, index, table, columns
+                )
+            }
+        }
+    }
+
+    fn expr(&mut self, depth: usize) -> String {
+        if depth >= self.options.max_depth || self.rng.gen_bool(0.4) {
+            return if self.rng.gen_bool(0.5) {
+                self.literal()
+            } else {
+                self.column()
+            };
+        }
+        match self.rng.gen_range(0..10) {
+            0..=2 => {
+                let operator =
+                    if !self.options.dialect_keywords.is_empty() && self.rng.gen_bool(0.1) {
+                        let index = self.rng.gen_range(0..self.options.dialect_keywords.len());
+                        self.options.dialect_keywords[index].clone()
+                    } else {
+                        BINARY_OPERATORS[self.rng.gen_range(0..BINARY_OPERATORS.len())].to_string()
+                    };
+                let left = self.expr(depth + 1);
+                let right = self.expr(depth + 1);
+                format!("{} {}{}{}", left, operator, self.gap(), right)
+            }
+            3 => {
+                let inner = self.expr(depth + 1);
+                format!("({})", inner)
+            }
+            4 => {
+                let operand = self.expr(depth + 1);
+                let prefix = ["NOT ", "-", "~", "NOT NOT "][self.rng.gen_range(0..4)];
+                format!("{}{}", prefix, operand)
+            }
+            5 => {
+                let function = FUNCTIONS[self.rng.gen_range(0..FUNCTIONS.len())];
+                if function == "COUNT" && self.rng.gen_bool(0.5) {
+                    return "COUNT(*)".to_string();
+                }
+                let args = self.items(|gen| gen.expr(depth + 1));
+                format!("{}({})", function, args)
+            }
+            6 => {
+                let operand = self.expr(depth + 1);
+                let ty = TYPES[self.rng.gen_range(0..TYPES.len())];
+                format!("CAST({} AS {})", operand, ty)
+            }
+            7 => {
+                let mut case = "CASE".to_string();
+                for _ in 0..self.rng.gen_range(1..=2) {
+                    let condition = self.expr(depth + 1);
+                    let result = self.expr(depth + 1);
+                    case.push_str(&format!(" WHEN {} THEN {}", condition, result));
+                }
+                if self.rng.gen_bool(0.5) {
+                    let otherwise = self.expr(depth + 1);
+                    case.push_str(&format!(" ELSE {}", otherwise));
+                }
+                case.push_str(" END");
+                case
+            }
+            8 => {
+                let subquery = self.select(depth + 1);
+                match self.rng.gen_range(0..3) {
+                    0 => format!("EXISTS ({})", subquery),
+                    1 => format!("{} IN ({})", self.column(), subquery),
+                    _ => format!("({})", subquery),
+                }
+            }
+            _ => {
+                let operand = self.expr(depth + 1);
+                match self.rng.gen_range(0..3) {
+                    0 => format!("{} IS NULL", operand),
+                    1 => {
+                        let low = self.literal();
+                        let high = self.literal();
+                        format!("{} BETWEEN {} AND {}", operand, low, high)
+                    }
+                    _ => {
+                        let values = self.items(|gen| gen.literal());
+                        format!("{} IN ({})", operand, values)
+                    }
+                }
+            }
+        }
+    }
+
+    fn literal(&mut self) -> String {
+        match self.rng.gen_range(0..10) {
+            0 => "NULL".to_string(),
+            1 => ["TRUE", "FALSE"][self.rng.gen_range(0..2)].to_string(),
+            2 => [
+                "0",
+                "-1",
+                "2147483648",
+                "-9223372036854775809",
+                "1e308",
+                "0x7FFFFFFF",
+                ".5",
+                "1.",
+            ][self.rng.gen_range(0..8)]
+            .to_string(),
+            3 => format!("{:.3}", self.rng.gen_range(-1000.0..1000.0)),
+            4 if self.rng.gen_bool(self.options.odd_quoting_rate) => [
+                "'it''s'",
+                "'back\\'slash'",
+                "E'\\x41\\n'",
+                "N'nvarchar'",
+                "X'DEADBEEF'",
+                "$$dollar ' quoted$$",
+                "$tag$nested $$ dollar$tag$",
+                "''",
+                "'unterminated",
+            ][self.rng.gen_range(0..9)]
+            .to_string(),
+            4..=6 => {
+                let len = self.rng.gen_range(0..12);
+                let text: String = (0..len)
+                    .map(|_| self.rng.gen_range(b'a'..=b'z') as char)
+                    .collect();
+                format!("'{}'", text)
+            }
+            _ => self.rng.gen_range(0..1000).to_string(),
+        }
+    }
+
+    fn column(&mut self) -> String {
+        let column = if self.rng.gen_bool(0.8) {
+            COLUMNS[self.rng.gen_range(0..COLUMNS.len())].to_string()
+        } else {
+            self.identifier()
+        };
+        if self.rng.gen_bool(0.2) {
+            format!("{}.{}", TABLES[self.rng.gen_range(0..TABLES.len())], column)
+        } else {
+            column
+        }
+    }
+
+    fn table_name(&mut self) -> String {
+        if self.rng.gen_bool(0.8) {
+            let table = TABLES[self.rng.gen_range(0..TABLES.len())];
+            if self.rng.gen_bool(0.1) {
+                format!("main.{}", table)
+            } else {
+                table.to_string()
+            }
+        } else {
+            self.identifier()
+        }
+    }
+
+    fn table_ref(&mut self, depth: usize) -> String {
+        if depth < self.options.max_depth && self.rng.gen_bool(0.15) {
+            let subquery = self.select(depth + 1);
+            format!("({}) AS {}", subquery, self.identifier())
+        } else {
+            self.table_name()
+        }
+    }
+
+    /// An identifier that is sometimes quoted in one of the dialect-specific styles, or is a
+    /// dialect keyword used unquoted.
+    fn identifier(&mut self) -> String {
+        if self.rng.gen_bool(self.options.odd_quoting_rate) {
+            match self.rng.gen_range(0..6) {
+                0 => "\"weird \"\"name\"\"\"".to_string(),
+                1 => "`back``tick`".to_string(),
+                2 => "[bracket]]name]".to_string(),
+                3 => "\"\"".to_string(),
+                4 => "\"\u{00E9}t\u{00E9}\"".to_string(),
+                _ => match self.options.dialect_keywords.len() {
+                    0 => "select".to_string(),
+                    n => self.options.dialect_keywords[self.rng.gen_range(0..n)].clone(),
+                },
+            }
+        } else {
+            let len = self.rng.gen_range(1..8);
+            let mut name: String = (0..len)
+                .map(|_| self.rng.gen_range(b'a'..=b'z') as char)
+                .collect();
+            if self.rng.gen_bool(0.2) {
+                name.push_str(&self.rng.gen_range(0..100).to_string());
+            }
+            name
+        }
+    }
+
+    fn dialect_clause(&mut self, sql: &mut String, depth: usize) {
+        if self.options.dialect_keywords.is_empty() || !self.rng.gen_bool(0.2) {
+            return;
+        }
+        let index = self.rng.gen_range(0..self.options.dialect_keywords.len());
+        let keyword = self.options.dialect_keywords[index].clone();
+        let operand = self.expr(depth + 1);
+        sql.push_str(&format!(" {} {}", keyword, operand));
+    }
+
+    fn items(&mut self, mut item: impl FnMut(&mut Self) -> String) -> String {
+        let count = self.rng.gen_range(1..=self.options.max_items.max(1));
+        let items: Vec<String> = (0..count).map(|_| item(self)).collect();
+        items.join(", ")
+    }
+
+    /// Whitespace between tokens, occasionally replaced by a comment.
+    fn gap(&mut self) -> String {
+        if !self.rng.gen_bool(self.options.comment_rate) {
+            return " ".to_string();
+        }
+        match self.rng.gen_range(0..5) {
+            0 => " -- comment\n".to_string(),
+            1 => " /* block */ ".to_string(),
+            2 => " /* nested /* block */ comment */ ".to_string(),
+            3 => "/**/".to_string(),
+            _ => " #hash comment\n".to_string(),
+        }
+    }
+}
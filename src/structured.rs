@@ -0,0 +1,418 @@
+use crate::errors::FuzzerError;
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use std::fmt::Display;
+
+impl de::Error for FuzzerError {
+    fn custom<T: Display>(msg: T) -> Self {
+        FuzzerError::StructuredInputError(msg.to_string())
+    }
+}
+
+/// Containers and enum variants nested deeper than this are rejected, so recursive types
+/// cannot exhaust the stack.
+pub const MAX_DEPTH: usize = 64;
+
+/// Builds any `Deserialize` type from fuzz bytes.
+///
+/// Once the bytes run out, numbers read as zero, collections as empty and enums as their last
+/// variant. Values nested deeper than [`MAX_DEPTH`] fail to decode, which only happens for
+/// recursive types. Types that rely on `deserialize_any` (untagged enums, `serde_json::Value`,
+/// flattening) are not supported because the byte stream is not self-describing.
+pub fn from_fuzz_bytes<T: DeserializeOwned>(data: &[u8]) -> Result<T, FuzzerError> {
+    let mut deserializer = FuzzDeserializer::new(data);
+    T::deserialize(&mut deserializer)
+}
+
+pub struct FuzzDeserializer<'de> {
+    data: &'de [u8],
+    depth: usize,
+}
+
+impl<'de> FuzzDeserializer<'de> {
+    pub const fn new(data: &'de [u8]) -> Self {
+        FuzzDeserializer { data, depth: 0 }
+    }
+
+    pub const fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> &'de [u8] {
+        let len = len.min(self.data.len());
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        head
+    }
+
+    fn read<const N: usize>(&mut self) -> [u8; N] {
+        let mut buf = [0; N];
+        let bytes = self.take(N);
+        buf[..bytes.len()].copy_from_slice(bytes);
+        buf
+    }
+
+    fn read_len(&mut self) -> usize {
+        let len = u16::from_le_bytes(self.read::<2>()) as usize;
+        len.min(self.data.len())
+    }
+
+    /// An element count, at most one per remaining byte.
+    fn read_count(&mut self) -> usize {
+        let count = self.read::<1>()[0] as usize;
+        count.min(self.data.len())
+    }
+
+    /// Runs `f` one level deeper, failing past [`MAX_DEPTH`].
+    fn nested<R>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<R, FuzzerError>,
+    ) -> Result<R, FuzzerError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(FuzzerError::StructuredInputError(format!(
+                "value nested deeper than {} levels",
+                MAX_DEPTH
+            )));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+}
+
+macro_rules! deserialize_number {
+    ($method:ident, $visit:ident, $ty:ty) => {
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FuzzerError> {
+            visitor.$visit(<$ty>::from_le_bytes(self.read()))
+        }
+    };
+}
+
+impl<'de> de::Deserializer<'de> for &mut FuzzDeserializer<'de> {
+    type Error = FuzzerError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, FuzzerError> {
+        Err(FuzzerError::StructuredInputError(
+            "self-describing types are not supported".to_string(),
+        ))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FuzzerError> {
+        visitor.visit_bool(self.read::<1>()[0] & 1 == 1)
+    }
+
+    deserialize_number!(deserialize_i8, visit_i8, i8);
+    deserialize_number!(deserialize_i16, visit_i16, i16);
+    deserialize_number!(deserialize_i32, visit_i32, i32);
+    deserialize_number!(deserialize_i64, visit_i64, i64);
+    deserialize_number!(deserialize_i128, visit_i128, i128);
+    deserialize_number!(deserialize_u8, visit_u8, u8);
+    deserialize_number!(deserialize_u16, visit_u16, u16);
+    deserialize_number!(deserialize_u32, visit_u32, u32);
+    deserialize_number!(deserialize_u64, visit_u64, u64);
+    deserialize_number!(deserialize_u128, visit_u128, u128);
+    deserialize_number!(deserialize_f32, visit_f32, f32);
+    deserialize_number!(deserialize_f64, visit_f64, f64);
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FuzzerError> {
+        let code = u32::from_le_bytes(self.read()) % 0x11_0000;
+        visitor.visit_char(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FuzzerError> {
+        let len = self.read_len();
+        let bytes = self.take(len);
+        match std::str::from_utf8(bytes) {
+            Ok(text) => visitor.visit_borrowed_str(text),
+            Err(_) => visitor.visit_string(String::from_utf8_lossy(bytes).into_owned()),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FuzzerError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FuzzerError> {
+        let len = self.read_len();
+        visitor.visit_borrowed_bytes(self.take(len))
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FuzzerError> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FuzzerError> {
+        if self.read::<1>()[0] & 1 == 1 {
+            self.nested(|deserializer| visitor.visit_some(deserializer))
+        } else {
+            visitor.visit_none()
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FuzzerError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, FuzzerError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, FuzzerError> {
+        self.nested(|deserializer| visitor.visit_newtype_struct(deserializer))
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FuzzerError> {
+        let len = self.read_count();
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, FuzzerError> {
+        self.nested(|deserializer| {
+            visitor.visit_seq(Elements {
+                deserializer,
+                remaining: len,
+            })
+        })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, FuzzerError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FuzzerError> {
+        let len = self.read_count();
+        self.nested(|deserializer| {
+            visitor.visit_map(Elements {
+                deserializer,
+                remaining: len,
+            })
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, FuzzerError> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, FuzzerError> {
+        if variants.is_empty() {
+            return Err(FuzzerError::StructuredInputError(
+                "enum has no variants".to_string(),
+            ));
+        }
+        // The last variant is the usual place for the leaf of a recursive enum.
+        let index = if self.data.is_empty() {
+            variants.len() as u32 - 1
+        } else {
+            u32::from_le_bytes(self.read()) % variants.len() as u32
+        };
+        self.nested(|deserializer| {
+            visitor.visit_enum(Variant {
+                deserializer,
+                index,
+            })
+        })
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FuzzerError> {
+        self.deserialize_u32(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FuzzerError> {
+        visitor.visit_unit()
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+struct Elements<'a, 'de> {
+    deserializer: &'a mut FuzzDeserializer<'de>,
+    remaining: usize,
+}
+
+impl<'de> SeqAccess<'de> for Elements<'_, 'de> {
+    type Error = FuzzerError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, FuzzerError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.deserializer).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'de> MapAccess<'de> for Elements<'_, 'de> {
+    type Error = FuzzerError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, FuzzerError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.deserializer).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, FuzzerError> {
+        seed.deserialize(&mut *self.deserializer)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+struct Variant<'a, 'de> {
+    deserializer: &'a mut FuzzDeserializer<'de>,
+    index: u32,
+}
+
+impl<'a, 'de> EnumAccess<'de> for Variant<'a, 'de> {
+    type Error = FuzzerError;
+    type Variant = &'a mut FuzzDeserializer<'de>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), FuzzerError> {
+        let deserializer: de::value::U32Deserializer<FuzzerError> = self.index.into_deserializer();
+        let value = seed.deserialize(deserializer)?;
+        Ok((value, self.deserializer))
+    }
+}
+
+impl<'de> VariantAccess<'de> for &mut FuzzDeserializer<'de> {
+    type Error = FuzzerError;
+
+    fn unit_variant(self) -> Result<(), FuzzerError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, FuzzerError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, FuzzerError> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, FuzzerError> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Expr {
+        Neg(Box<Expr>),
+        Add(Box<Expr>, Box<Expr>),
+        Lit(u8),
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Record {
+        id: u16,
+        name: String,
+        tags: Vec<u8>,
+        parent: Option<Box<Record>>,
+    }
+
+    #[test]
+    fn decodes_little_endian_fields() {
+        let record: Record = from_fuzz_bytes(&[1, 2, 2, 0, b'h', b'i', 2, 7, 8, 0]).unwrap();
+        assert_eq!(
+            record,
+            Record {
+                id: 0x0201,
+                name: "hi".to_string(),
+                tags: vec![7, 8],
+                parent: None,
+            }
+        );
+    }
+
+    #[test]
+    fn exhausted_input_picks_the_last_variant() {
+        assert_eq!(from_fuzz_bytes::<Expr>(&[]).unwrap(), Expr::Lit(0));
+        assert_eq!(
+            from_fuzz_bytes::<Expr>(&[0, 0, 0, 0]).unwrap(),
+            Expr::Neg(Box::new(Expr::Lit(0)))
+        );
+    }
+
+    #[test]
+    fn deep_recursion_is_rejected() {
+        // Variant 0 every time nests one level further per four bytes.
+        let input = vec![0; 4 * (MAX_DEPTH + 1)];
+        assert!(from_fuzz_bytes::<Expr>(&input).is_err());
+        // An empty record with a parent, over and over.
+        let input = [0, 0, 0, 0, 0, 1].repeat(MAX_DEPTH);
+        assert!(from_fuzz_bytes::<Record>(&input).is_err());
+        assert!(from_fuzz_bytes::<Record>(&input[..60]).is_ok());
+    }
+
+    #[test]
+    fn collection_lengths_are_capped_by_the_input() {
+        let tags: Vec<u8> = from_fuzz_bytes(&[200, 1, 2, 3]).unwrap();
+        assert_eq!(tags, [1, 2, 3]);
+    }
+}
@@ -1,12 +1,24 @@
+use crate::allocator::ThreadContext;
+use crate::coverage::CoverageTracker;
 use crate::errors::FuzzerError;
+use crate::mutator_options::MutatorOptions;
+use crate::structured;
+use crate::utils::{panic_message, InputFormat};
 use async_trait::async_trait;
+use log::debug;
+use serde::de::DeserializeOwned;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::task;
+
+type SyncFunction = Arc<dyn Fn(&[u8]) -> Result<(), FuzzerError> + Send + Sync>;
+type Hook = Arc<dyn Fn() -> Result<(), FuzzerError> + Send + Sync>;
 
 #[derive(Clone)]
 pub enum TargetFunctionType {
-    Sync(fn(&[u8]) -> Result<(), FuzzerError>),
+    Sync(SyncFunction),
     Async(
         Arc<
             dyn Fn(&[u8]) -> Pin<Box<dyn Future<Output = Result<(), FuzzerError>> + Send>>
@@ -16,17 +28,72 @@ pub enum TargetFunctionType {
     ),
 }
 
+/// Lifecycle hooks of a target. Errors from hooks are reported as [`FuzzerError::HookError`].
+#[derive(Clone, Default)]
+pub struct TargetHooks {
+    /// Runs once per `Fuzzer::run`, in any fuzz mode, before fuzzing starts.
+    pub setup: Option<Hook>,
+    /// Runs before every execution, to reset state a previous one may have left behind.
+    pub before_each: Option<Hook>,
+    /// Runs after every execution, whether it succeeded or not.
+    pub after_each: Option<Hook>,
+    /// Runs once after fuzzing stops, if `setup` succeeded.
+    pub teardown: Option<Hook>,
+}
+
+/// Per-target settings. Those left unset fall back to the fuzzer's configuration.
+#[derive(Debug, Clone)]
+pub struct TargetOptions {
+    pub timeout: Option<Duration>,
+    /// Inputs in another format than the fuzzer's, from a generator of the target's own.
+    pub input_format: Option<InputFormat>,
+    /// Inputs mutated by a mutator of the target's own.
+    pub mutator_options: Option<MutatorOptions>,
+    /// Share of the iterations relative to other targets, under a weighted schedule.
+    pub weight: f64,
+    /// Coverage recorded by the target, whose growth `Schedule::CoverageYield` rewards.
+    pub coverage: Option<CoverageTracker>,
+}
+
+impl Default for TargetOptions {
+    fn default() -> Self {
+        TargetOptions {
+            timeout: None,
+            input_format: None,
+            mutator_options: None,
+            weight: 1.0,
+            coverage: None,
+        }
+    }
+}
+
+impl TargetOptions {
+    /// True if the target needs inputs of its own rather than the shared ones.
+    pub const fn has_own_inputs(&self) -> bool {
+        self.input_format.is_some() || self.mutator_options.is_some()
+    }
+}
+
 #[derive(Clone)]
 pub struct TargetFunction {
     pub name: String,
     pub func_type: TargetFunctionType,
+    pub hooks: TargetHooks,
+    pub options: TargetOptions,
 }
 
 impl TargetFunction {
-    pub fn new_sync(name: &str, func: fn(&[u8]) -> Result<(), FuzzerError>) -> Self {
+    /// Wraps a function or a closure. State captured by a closure is shared by all
+    /// executions, so it is only built once.
+    pub fn new_sync<F>(name: &str, func: F) -> Self
+    where
+        F: Fn(&[u8]) -> Result<(), FuzzerError> + Send + Sync + 'static,
+    {
         TargetFunction {
             name: name.to_string(),
-            func_type: TargetFunctionType::Sync(func),
+            func_type: TargetFunctionType::Sync(Arc::new(func)),
+            hooks: TargetHooks::default(),
+            options: TargetOptions::default(),
         }
     }
 
@@ -40,8 +107,104 @@ impl TargetFunction {
         TargetFunction {
             name: name.to_string(),
             func_type: TargetFunctionType::Async(Arc::new(func)),
+            hooks: TargetHooks::default(),
+            options: TargetOptions::default(),
         }
     }
+
+    /// Wraps a function over a typed value; each input is decoded with
+    /// [`structured::from_fuzz_bytes`] before the call.
+    pub fn new_typed<T, F>(name: &str, func: F) -> Self
+    where
+        T: DeserializeOwned + 'static,
+        F: Fn(T) -> Result<(), FuzzerError> + Send + Sync + 'static,
+    {
+        Self::new_sync(name, move |input: &[u8]| {
+            match structured::from_fuzz_bytes::<T>(input) {
+                Ok(value) => func(value),
+                // Inputs the type refuses to represent are skipped rather than reported.
+                Err(e) => {
+                    debug!("Input rejected by typed target: {}", e);
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    pub fn with_setup<F>(mut self, hook: F) -> Self
+    where
+        F: Fn() -> Result<(), FuzzerError> + Send + Sync + 'static,
+    {
+        self.hooks.setup = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn with_before_each<F>(mut self, hook: F) -> Self
+    where
+        F: Fn() -> Result<(), FuzzerError> + Send + Sync + 'static,
+    {
+        self.hooks.before_each = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn with_after_each<F>(mut self, hook: F) -> Self
+    where
+        F: Fn() -> Result<(), FuzzerError> + Send + Sync + 'static,
+    {
+        self.hooks.after_each = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn with_teardown<F>(mut self, hook: F) -> Self
+    where
+        F: Fn() -> Result<(), FuzzerError> + Send + Sync + 'static,
+    {
+        self.hooks.teardown = Some(Arc::new(hook));
+        self
+    }
+
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_input_format(mut self, format: InputFormat) -> Self {
+        self.options.input_format = Some(format);
+        self
+    }
+
+    pub fn with_mutator_options(mut self, options: MutatorOptions) -> Self {
+        self.options.mutator_options = Some(options);
+        self
+    }
+
+    pub const fn with_weight(mut self, weight: f64) -> Self {
+        self.options.weight = weight;
+        self
+    }
+
+    pub fn with_coverage(mut self, coverage: CoverageTracker) -> Self {
+        self.options.coverage = Some(coverage);
+        self
+    }
+
+    pub fn setup(&self) -> Result<(), FuzzerError> {
+        run_hook(self.hooks.setup.as_ref())
+    }
+
+    pub fn teardown(&self) -> Result<(), FuzzerError> {
+        run_hook(self.hooks.teardown.as_ref())
+    }
+}
+
+fn run_hook(hook: Option<&Hook>) -> Result<(), FuzzerError> {
+    match hook {
+        Some(hook) => hook().map_err(|e| match e {
+            FuzzerError::HookError(_) => e,
+            e => FuzzerError::HookError(e.to_string()),
+        }),
+        None => Ok(()),
+    }
 }
 
 #[async_trait]
@@ -51,10 +214,32 @@ pub trait Executable {
 
 #[async_trait]
 impl Executable for TargetFunction {
+    /// Runs the target between its `before_each` and `after_each` hooks. An error from the
+    /// target takes precedence over one from `after_each`. Sync targets run on a blocking
+    /// thread, so that a timeout can give up on them, and a panic is reported as an error.
     async fn execute(&self, input: &[u8]) -> Result<(), FuzzerError> {
-        match &self.func_type {
-            TargetFunctionType::Sync(func) => func(input),
+        run_hook(self.hooks.before_each.as_ref())?;
+        let result = match &self.func_type {
+            TargetFunctionType::Sync(func) => {
+                let func = Arc::clone(func);
+                let input = input.to_vec();
+                let context = ThreadContext::capture();
+                task::spawn_blocking(move || context.run(|| func(&input)))
+                    .await
+                    .unwrap_or_else(|e| {
+                        let message = match e.try_into_panic() {
+                            Ok(payload) => panic_message(payload.as_ref()),
+                            Err(e) => e.to_string(),
+                        };
+                        Err(FuzzerError::ExecutionError(format!(
+                            "panicked: {}",
+                            message
+                        )))
+                    })
+            }
             TargetFunctionType::Async(func) => (func)(input).await,
-        }
+        };
+        let after = run_hook(self.hooks.after_each.as_ref());
+        result.and(after)
     }
 }
@@ -0,0 +1,955 @@
+use crate::errors::FuzzerError;
+use crate::regions::{RegionKind, RegionMap};
+use rand::{prelude::StdRng, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+
+/// Upper bound on element counts read from the input while decoding.
+const MAX_DECODED_COUNT: usize = 1 << 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegerType {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+}
+
+impl IntegerType {
+    pub const fn width(self) -> usize {
+        match self {
+            IntegerType::U8 | IntegerType::I8 => 1,
+            IntegerType::U16 | IntegerType::I16 => 2,
+            IntegerType::U32 | IntegerType::I32 => 4,
+            IntegerType::U64 | IntegerType::I64 => 8,
+        }
+    }
+
+    pub const fn signed(self) -> bool {
+        matches!(
+            self,
+            IntegerType::I8 | IntegerType::I16 | IntegerType::I32 | IntegerType::I64
+        )
+    }
+
+    const fn mask(self) -> u64 {
+        match self.width() {
+            8 => u64::MAX,
+            width => (1 << (8 * width)) - 1,
+        }
+    }
+
+    const fn bounds(self) -> (i128, i128) {
+        let bits = 8 * self.width() as u32;
+        if self.signed() {
+            (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+        } else {
+            (0, (1 << bits) - 1)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumAlgorithm {
+    Crc32,
+    /// CRC-16/CCITT-FALSE.
+    Crc16,
+    Adler32,
+    Sum8,
+    Xor8,
+}
+
+impl ChecksumAlgorithm {
+    pub const fn integer_type(self) -> IntegerType {
+        match self {
+            ChecksumAlgorithm::Crc32 | ChecksumAlgorithm::Adler32 => IntegerType::U32,
+            ChecksumAlgorithm::Crc16 => IntegerType::U16,
+            ChecksumAlgorithm::Sum8 | ChecksumAlgorithm::Xor8 => IntegerType::U8,
+        }
+    }
+
+    pub fn compute(self, data: &[u8]) -> u64 {
+        match self {
+            ChecksumAlgorithm::Crc32 => {
+                let mut crc = 0xFFFF_FFFFu32;
+                for &byte in data {
+                    crc ^= u32::from(byte);
+                    for _ in 0..8 {
+                        crc = if crc & 1 == 1 {
+                            (crc >> 1) ^ 0xEDB8_8320
+                        } else {
+                            crc >> 1
+                        };
+                    }
+                }
+                u64::from(!crc)
+            }
+            ChecksumAlgorithm::Crc16 => {
+                let mut crc = 0xFFFFu16;
+                for &byte in data {
+                    crc ^= u16::from(byte) << 8;
+                    for _ in 0..8 {
+                        crc = if crc & 0x8000 != 0 {
+                            (crc << 1) ^ 0x1021
+                        } else {
+                            crc << 1
+                        };
+                    }
+                }
+                u64::from(crc)
+            }
+            ChecksumAlgorithm::Adler32 => {
+                let (mut a, mut b) = (1u32, 0u32);
+                for &byte in data {
+                    a = (a + u32::from(byte)) % 65521;
+                    b = (b + a) % 65521;
+                }
+                u64::from((b << 16) | a)
+            }
+            ChecksumAlgorithm::Sum8 => {
+                u64::from(data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)))
+            }
+            ChecksumAlgorithm::Xor8 => u64::from(data.iter().fold(0u8, |sum, &b| sum ^ b)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Field {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: FieldKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FieldKind {
+    /// An integer. With `length_of` it holds the encoded length of a sibling field and with
+    /// `count_of` the number of elements of a sibling repeat or bytes of a sibling blob.
+    Int {
+        int: IntegerType,
+        #[serde(default)]
+        endian: Endianness,
+        min: Option<i64>,
+        max: Option<i64>,
+        length_of: Option<String>,
+        count_of: Option<String>,
+    },
+    Enum {
+        int: IntegerType,
+        #[serde(default)]
+        endian: Endianness,
+        values: Vec<i64>,
+    },
+    Magic {
+        value: Vec<u8>,
+    },
+    /// Raw bytes, optionally preceded by their length.
+    Blob {
+        prefix: Option<IntegerType>,
+        #[serde(default)]
+        endian: Endianness,
+        #[serde(default)]
+        min_length: usize,
+        #[serde(default = "default_max_length")]
+        max_length: usize,
+    },
+    Repeat {
+        fields: Vec<Field>,
+        #[serde(default)]
+        min_count: usize,
+        #[serde(default = "default_max_count")]
+        max_count: usize,
+    },
+    /// A checksum over the named sibling fields, or over all preceding siblings if `over` is
+    /// empty.
+    Checksum {
+        algorithm: ChecksumAlgorithm,
+        #[serde(default)]
+        endian: Endianness,
+        #[serde(default)]
+        over: Vec<String>,
+    },
+}
+
+const fn default_max_length() -> usize {
+    64
+}
+
+const fn default_max_count() -> usize {
+    4
+}
+
+impl FieldKind {
+    /// Fields whose value is computed from other fields when encoding.
+    const fn is_derived(&self) -> bool {
+        matches!(
+            self,
+            FieldKind::Int {
+                length_of: Some(_),
+                ..
+            } | FieldKind::Int {
+                count_of: Some(_),
+                ..
+            } | FieldKind::Checksum { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldValue {
+    Int(u64),
+    Bytes(Vec<u8>),
+    Repeat(Vec<Vec<FieldValue>>),
+}
+
+/// A declarative binary layout, similar to a Peach pit or an 010 Editor template.
+///
+/// Instances are generated valid, and mutated field by field with lengths, counts and
+/// checksums recomputed on every encode.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BinaryTemplate {
+    #[serde(default)]
+    pub name: String,
+    pub fields: Vec<Field>,
+}
+
+impl BinaryTemplate {
+    pub fn new(name: &str, fields: Vec<Field>) -> Result<Self, FuzzerError> {
+        let template = BinaryTemplate {
+            name: name.to_string(),
+            fields,
+        };
+        validate_scope(&template.fields)?;
+        Ok(template)
+    }
+
+    /// Loads a template from a JSON file.
+    pub fn load_from_file(filename: &str) -> Result<Self, FuzzerError> {
+        let file = File::open(filename).map_err(|e| {
+            FuzzerError::InputGenerationError(format!(
+                "Failed to open template {}: {}",
+                filename, e
+            ))
+        })?;
+        let template: BinaryTemplate = serde_json::from_reader(file).map_err(|e| {
+            FuzzerError::InputGenerationError(format!("Invalid template {}: {}", filename, e))
+        })?;
+        validate_scope(&template.fields)?;
+        Ok(template)
+    }
+
+    pub fn generate(&self, rng: &mut StdRng) -> Vec<u8> {
+        let values = self.generate_values(rng);
+        self.encode(&values)
+    }
+
+    pub fn generate_values(&self, rng: &mut StdRng) -> Vec<FieldValue> {
+        generate_scope(&self.fields, rng)
+    }
+
+    pub fn encode(&self, values: &[FieldValue]) -> Vec<u8> {
+        encode_scope(&self.fields, values)
+    }
+
+    /// Parses data laid out by this template. Trailing bytes are ignored.
+    pub fn decode(&self, data: &[u8]) -> Option<Vec<FieldValue>> {
+        decode_scope(&self.fields, data).map(|(values, _)| values)
+    }
+
+    /// Region map of data laid out by this template: magic values, lengths, counts,
+    /// checksums and blob prefixes are frozen, other integers numeric and blobs free.
+    pub fn regions(&self, data: &[u8]) -> Option<RegionMap> {
+        let values = self.decode(data)?;
+        let mut regions = RegionMap::new();
+        mark_scope(&self.fields, &values, 0, &mut regions);
+        Some(regions)
+    }
+
+    /// Mutates one field that is not derived from others.
+    pub fn mutate(&self, rng: &mut StdRng, values: &mut [FieldValue]) {
+        let mut leaves = Vec::new();
+        collect_leaves(&self.fields, values, &mut Vec::new(), &mut leaves);
+        if leaves.is_empty() {
+            return;
+        }
+        let path = &leaves[rng.gen_range(0..leaves.len())];
+        if let Some((scope, field, value)) = resolve(&self.fields, values, path) {
+            mutate_field(scope, field, value, rng);
+        }
+    }
+}
+
+fn validate_scope(fields: &[Field]) -> Result<(), FuzzerError> {
+    let invalid = |name: &str, reason: &str| {
+        FuzzerError::InputGenerationError(format!("Invalid template field {}: {}", name, reason))
+    };
+    let mut names = HashSet::new();
+    for field in fields {
+        if !names.insert(field.name.as_str()) {
+            return Err(invalid(&field.name, "duplicate name"));
+        }
+    }
+    let find = |name: &str| fields.iter().find(|f| f.name == name);
+
+    for field in fields {
+        match &field.kind {
+            FieldKind::Int {
+                min,
+                max,
+                length_of,
+                count_of,
+                ..
+            } => {
+                if length_of.is_some() && count_of.is_some() {
+                    return Err(invalid(&field.name, "both length_of and count_of are set"));
+                }
+                if let (Some(min), Some(max)) = (min, max) {
+                    if min > max {
+                        return Err(invalid(&field.name, "min is greater than max"));
+                    }
+                }
+                if let Some(target) = length_of {
+                    if target == &field.name || find(target).is_none() {
+                        return Err(invalid(&field.name, "length_of names no sibling field"));
+                    }
+                }
+                if let Some(target) = count_of {
+                    match find(target).map(|f| &f.kind) {
+                        Some(FieldKind::Repeat { .. }) | Some(FieldKind::Blob { .. }) => {}
+                        _ => {
+                            return Err(invalid(
+                                &field.name,
+                                "count_of must name a sibling repeat or blob",
+                            ))
+                        }
+                    }
+                }
+            }
+            FieldKind::Enum { values, .. } if values.is_empty() => {
+                return Err(invalid(&field.name, "enum has no values"));
+            }
+            FieldKind::Blob {
+                min_length,
+                max_length,
+                ..
+            } if min_length > max_length => {
+                return Err(invalid(
+                    &field.name,
+                    "min_length is greater than max_length",
+                ));
+            }
+            FieldKind::Repeat {
+                fields: sub,
+                min_count,
+                max_count,
+            } => {
+                if min_count > max_count {
+                    return Err(invalid(&field.name, "min_count is greater than max_count"));
+                }
+                validate_scope(sub)?;
+            }
+            FieldKind::Checksum { over, .. } => {
+                if let Some(missing) = over.iter().find(|name| find(name).is_none()) {
+                    return Err(invalid(
+                        &field.name,
+                        &format!("checksum covers unknown field {}", missing),
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn generate_scope(fields: &[Field], rng: &mut StdRng) -> Vec<FieldValue> {
+    fields
+        .iter()
+        .map(|field| generate_field(fields, field, rng))
+        .collect()
+}
+
+fn generate_field(scope: &[Field], field: &Field, rng: &mut StdRng) -> FieldValue {
+    match &field.kind {
+        _ if field.kind.is_derived() => FieldValue::Int(0),
+        FieldKind::Int { int, min, max, .. } => {
+            if min.is_none() && max.is_none() && rng.gen_bool(0.1) {
+                FieldValue::Int(interesting_int(*int, rng))
+            } else {
+                FieldValue::Int(random_int(*int, *min, *max, rng))
+            }
+        }
+        FieldKind::Enum { int, values, .. } => {
+            let value = values[rng.gen_range(0..values.len())];
+            FieldValue::Int(value as u64 & int.mask())
+        }
+        FieldKind::Magic { value } => FieldValue::Bytes(value.clone()),
+        FieldKind::Blob {
+            min_length,
+            max_length,
+            ..
+        } => {
+            let max = (*max_length).min(max_blob_length(scope, field));
+            let len = rng.gen_range((*min_length).min(max)..=max);
+            FieldValue::Bytes((0..len).map(|_| rng.gen()).collect())
+        }
+        FieldKind::Repeat {
+            fields: sub,
+            min_count,
+            max_count,
+        } => {
+            let count = rng.gen_range(*min_count..=*max_count);
+            FieldValue::Repeat((0..count).map(|_| generate_scope(sub, rng)).collect())
+        }
+        FieldKind::Checksum { .. } => FieldValue::Int(0),
+    }
+}
+
+fn encode_scope(fields: &[Field], values: &[FieldValue]) -> Vec<u8> {
+    let mut chunks: Vec<Vec<u8>> = fields
+        .iter()
+        .zip(values)
+        .map(|(field, value)| encode_field(field, value))
+        .collect();
+    let index_of = |name: &str| fields.iter().position(|f| f.name == name);
+
+    for (i, field) in fields.iter().enumerate() {
+        if let FieldKind::Int {
+            int,
+            endian,
+            length_of,
+            count_of,
+            ..
+        } = &field.kind
+        {
+            let derived = if let Some(target) = length_of {
+                index_of(target)
+                    .and_then(|j| chunks.get(j))
+                    .map(|c| c.len() as u64)
+            } else if let Some(target) = count_of {
+                index_of(target)
+                    .and_then(|j| values.get(j))
+                    .map(|value| match value {
+                        FieldValue::Repeat(elements) => elements.len() as u64,
+                        FieldValue::Bytes(bytes) => bytes.len() as u64,
+                        FieldValue::Int(_) => 0,
+                    })
+            } else {
+                None
+            };
+            if let (Some(value), Some(chunk)) = (derived, chunks.get_mut(i)) {
+                *chunk = int_bytes(value, *int, *endian);
+            }
+        }
+    }
+
+    // Checksums go last and in order, so they may cover lengths and earlier checksums.
+    for (i, field) in fields.iter().enumerate().take(chunks.len()) {
+        if let FieldKind::Checksum {
+            algorithm,
+            endian,
+            over,
+        } = &field.kind
+        {
+            let covered: Vec<u8> = if over.is_empty() {
+                chunks[..i].concat()
+            } else {
+                fields
+                    .iter()
+                    .zip(&chunks)
+                    .filter(|(f, _)| over.contains(&f.name))
+                    .flat_map(|(_, chunk)| chunk.iter().copied())
+                    .collect()
+            };
+            chunks[i] = int_bytes(
+                algorithm.compute(&covered),
+                algorithm.integer_type(),
+                *endian,
+            );
+        }
+    }
+    chunks.concat()
+}
+
+fn encode_field(field: &Field, value: &FieldValue) -> Vec<u8> {
+    match (&field.kind, value) {
+        (FieldKind::Int { int, endian, .. }, FieldValue::Int(v))
+        | (FieldKind::Enum { int, endian, .. }, FieldValue::Int(v)) => int_bytes(*v, *int, *endian),
+        (
+            FieldKind::Checksum {
+                algorithm, endian, ..
+            },
+            FieldValue::Int(v),
+        ) => int_bytes(*v, algorithm.integer_type(), *endian),
+        (FieldKind::Magic { .. }, FieldValue::Bytes(bytes)) => bytes.clone(),
+        (FieldKind::Blob { prefix, endian, .. }, FieldValue::Bytes(bytes)) => {
+            let mut out = match prefix {
+                Some(prefix) => int_bytes(bytes.len() as u64, *prefix, *endian),
+                None => Vec::new(),
+            };
+            out.extend_from_slice(bytes);
+            out
+        }
+        (FieldKind::Repeat { fields: sub, .. }, FieldValue::Repeat(elements)) => elements
+            .iter()
+            .flat_map(|element| encode_scope(sub, element))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn decode_scope(fields: &[Field], data: &[u8]) -> Option<(Vec<FieldValue>, usize)> {
+    let mut values: Vec<FieldValue> = Vec::with_capacity(fields.len());
+    let mut pos = 0;
+    for field in fields {
+        let rest = &data[pos..];
+        let length = related(fields, &values, &field.name, true);
+        let (value, used) = match &field.kind {
+            FieldKind::Int { int, endian, .. } | FieldKind::Enum { int, endian, .. } => {
+                (FieldValue::Int(read_int(rest, *int, *endian)?), int.width())
+            }
+            FieldKind::Checksum {
+                algorithm, endian, ..
+            } => {
+                let int = algorithm.integer_type();
+                (FieldValue::Int(read_int(rest, int, *endian)?), int.width())
+            }
+            FieldKind::Magic { value } => (
+                FieldValue::Bytes(rest.get(..value.len())?.to_vec()),
+                value.len(),
+            ),
+            FieldKind::Blob {
+                prefix,
+                endian,
+                min_length,
+                max_length,
+            } => {
+                let count = related(fields, &values, &field.name, false);
+                let (len, header) = match (prefix, length.or(count)) {
+                    (Some(prefix), _) => {
+                        (read_int(rest, *prefix, *endian)? as usize, prefix.width())
+                    }
+                    (None, Some(len)) => (len, 0),
+                    (None, None) if min_length == max_length => (*min_length, 0),
+                    (None, None) => (rest.len(), 0),
+                };
+                let end = header.checked_add(len)?;
+                (FieldValue::Bytes(rest.get(header..end)?.to_vec()), end)
+            }
+            FieldKind::Repeat {
+                fields: sub,
+                min_count,
+                max_count,
+            } => {
+                let rest = match length {
+                    Some(len) => rest.get(..len)?,
+                    None => rest,
+                };
+                let count = related(fields, &values, &field.name, false)
+                    .or((min_count == max_count).then_some(*min_count));
+                let mut elements = Vec::new();
+                let mut used = 0;
+                match count {
+                    Some(count) => {
+                        for _ in 0..count.min(MAX_DECODED_COUNT) {
+                            let (element, n) = decode_scope(sub, &rest[used..])?;
+                            elements.push(element);
+                            used += n;
+                        }
+                    }
+                    None => {
+                        while used < rest.len() {
+                            match decode_scope(sub, &rest[used..]) {
+                                Some((element, n)) if n > 0 => {
+                                    elements.push(element);
+                                    used += n;
+                                }
+                                _ => break,
+                            }
+                        }
+                    }
+                }
+                (FieldValue::Repeat(elements), length.unwrap_or(used))
+            }
+        };
+        values.push(value);
+        pos += used;
+    }
+    Some((values, pos))
+}
+
+/// Marks the fields of a decoded scope starting at `offset` and returns its length.
+fn mark_scope(
+    fields: &[Field],
+    values: &[FieldValue],
+    offset: usize,
+    regions: &mut RegionMap,
+) -> usize {
+    let mut pos = offset;
+    for (field, value) in fields.iter().zip(values) {
+        let used = match (&field.kind, value) {
+            (FieldKind::Magic { .. }, FieldValue::Bytes(bytes)) => {
+                regions.mark(pos..pos + bytes.len(), RegionKind::Frozen);
+                bytes.len()
+            }
+            (FieldKind::Checksum { algorithm, .. }, _) => {
+                let width = algorithm.integer_type().width();
+                regions.mark(pos..pos + width, RegionKind::Frozen);
+                width
+            }
+            (FieldKind::Int { int, .. } | FieldKind::Enum { int, .. }, _) => {
+                let kind = if field.kind.is_derived() {
+                    RegionKind::Frozen
+                } else {
+                    RegionKind::Numeric
+                };
+                regions.mark(pos..pos + int.width(), kind);
+                int.width()
+            }
+            (FieldKind::Blob { prefix, .. }, FieldValue::Bytes(bytes)) => {
+                let header = prefix.map_or(0, IntegerType::width);
+                regions.mark(pos..pos + header, RegionKind::Frozen);
+                header + bytes.len()
+            }
+            (FieldKind::Repeat { fields: sub, .. }, FieldValue::Repeat(elements)) => {
+                let used = elements.iter().fold(0, |used, element| {
+                    used + mark_scope(sub, element, pos + used, regions)
+                });
+                related(fields, values, &field.name, true).unwrap_or(used)
+            }
+            _ => 0,
+        };
+        pos += used;
+    }
+    pos - offset
+}
+
+/// Value of an already decoded sibling holding the length (or count) of `name`.
+fn related(fields: &[Field], values: &[FieldValue], name: &str, length: bool) -> Option<usize> {
+    fields.iter().zip(values).find_map(|(field, value)| {
+        let FieldKind::Int {
+            length_of,
+            count_of,
+            ..
+        } = &field.kind
+        else {
+            return None;
+        };
+        let target = if length { length_of } else { count_of };
+        match value {
+            FieldValue::Int(v) if target.as_deref() == Some(name) => {
+                Some(usize::try_from(*v).unwrap_or(usize::MAX))
+            }
+            _ => None,
+        }
+    })
+}
+
+fn collect_leaves(
+    fields: &[Field],
+    values: &[FieldValue],
+    prefix: &mut Vec<usize>,
+    leaves: &mut Vec<Vec<usize>>,
+) {
+    for (i, (field, value)) in fields.iter().zip(values).enumerate() {
+        if field.kind.is_derived() || matches!(field.kind, FieldKind::Magic { .. }) {
+            continue;
+        }
+        prefix.push(i);
+        leaves.push(prefix.clone());
+        if let (FieldKind::Repeat { fields: sub, .. }, FieldValue::Repeat(elements)) =
+            (&field.kind, value)
+        {
+            for (j, element) in elements.iter().enumerate() {
+                prefix.push(j);
+                collect_leaves(sub, element, prefix, leaves);
+                prefix.pop();
+            }
+        }
+        prefix.pop();
+    }
+}
+
+/// Longest blob whose length fits its prefix and every sibling holding its length or count.
+fn max_blob_length(scope: &[Field], blob: &Field) -> usize {
+    let FieldKind::Blob { prefix, .. } = &blob.kind else {
+        return usize::MAX;
+    };
+    let header = prefix.map_or(0, IntegerType::width) as u64;
+    let mut max = prefix.map_or(u64::MAX, IntegerType::mask);
+    for field in scope {
+        if let FieldKind::Int {
+            int,
+            length_of,
+            count_of,
+            ..
+        } = &field.kind
+        {
+            // A length covers the prefix as well as the bytes.
+            if length_of.as_deref() == Some(blob.name.as_str()) {
+                max = max.min(int.mask().saturating_sub(header));
+            }
+            if count_of.as_deref() == Some(blob.name.as_str()) {
+                max = max.min(int.mask());
+            }
+        }
+    }
+    usize::try_from(max).unwrap_or(usize::MAX)
+}
+
+/// Follows a path of alternating field and element indices to a field, its value and the
+/// fields of its scope.
+fn resolve<'a>(
+    fields: &'a [Field],
+    values: &'a mut [FieldValue],
+    path: &[usize],
+) -> Option<(&'a [Field], &'a Field, &'a mut FieldValue)> {
+    let field = fields.get(path[0])?;
+    let value = values.get_mut(path[0])?;
+    if path.len() == 1 {
+        return Some((fields, field, value));
+    }
+    match (&field.kind, value) {
+        (FieldKind::Repeat { fields: sub, .. }, FieldValue::Repeat(elements)) => {
+            resolve(sub, elements.get_mut(path[1])?, &path[2..])
+        }
+        _ => None,
+    }
+}
+
+fn mutate_field(scope: &[Field], field: &Field, value: &mut FieldValue, rng: &mut StdRng) {
+    match (&field.kind, value) {
+        (FieldKind::Int { int, min, max, .. }, FieldValue::Int(v)) => {
+            *v = match rng.gen_range(0..4) {
+                0 => interesting_int(*int, rng),
+                1 => {
+                    let delta = rng.gen_range(1..=16u64);
+                    if rng.gen_bool(0.5) {
+                        v.wrapping_add(delta) & int.mask()
+                    } else {
+                        v.wrapping_sub(delta) & int.mask()
+                    }
+                }
+                2 => random_int(*int, *min, *max, rng),
+                _ => (*v ^ (1 << rng.gen_range(0..8 * int.width()))) & int.mask(),
+            };
+        }
+        (FieldKind::Enum { int, values, .. }, FieldValue::Int(v)) => {
+            *v = if rng.gen_bool(0.7) {
+                values[rng.gen_range(0..values.len())] as u64 & int.mask()
+            } else {
+                interesting_int(*int, rng)
+            };
+        }
+        (FieldKind::Blob { max_length, .. }, FieldValue::Bytes(bytes)) => {
+            match rng.gen_range(0..6) {
+                0 if !bytes.is_empty() => {
+                    let index = rng.gen_range(0..bytes.len());
+                    bytes[index] ^= 1 << rng.gen_range(0..8);
+                }
+                1 => {
+                    let at = rng.gen_range(0..=bytes.len());
+                    let extra: Vec<u8> = (0..rng.gen_range(1..=16)).map(|_| rng.gen()).collect();
+                    bytes.splice(at..at, extra);
+                }
+                2 if !bytes.is_empty() => {
+                    let start = rng.gen_range(0..bytes.len());
+                    let end = rng.gen_range(start..=bytes.len());
+                    bytes.drain(start..end);
+                }
+                3 => bytes.clear(),
+                4 => bytes.resize(max_length + rng.gen_range(1..=64), 0x41),
+                _ => bytes.fill([0x00, 0xFF][rng.gen_range(0..2)]),
+            }
+            // Keep the length representable in the prefix and in any length or count field.
+            bytes.truncate(max_blob_length(scope, field));
+        }
+        (
+            FieldKind::Repeat {
+                fields: sub,
+                max_count,
+                ..
+            },
+            FieldValue::Repeat(elements),
+        ) => match rng.gen_range(0..4) {
+            0 if !elements.is_empty() => {
+                let index = rng.gen_range(0..elements.len());
+                let copies = if rng.gen_bool(0.8) { 1 } else { max_count + 1 };
+                for _ in 0..copies {
+                    elements.insert(index, elements[index].clone());
+                }
+            }
+            1 if !elements.is_empty() => {
+                elements.remove(rng.gen_range(0..elements.len()));
+            }
+            2 if elements.len() > 1 => {
+                let a = rng.gen_range(0..elements.len());
+                let b = rng.gen_range(0..elements.len());
+                elements.swap(a, b);
+            }
+            _ => {
+                let at = rng.gen_range(0..=elements.len());
+                elements.insert(at, generate_scope(sub, rng));
+            }
+        },
+        _ => {}
+    }
+}
+
+fn random_int(int: IntegerType, min: Option<i64>, max: Option<i64>, rng: &mut StdRng) -> u64 {
+    let (low, high) = int.bounds();
+    let low = min.map_or(low, i128::from).max(low);
+    let high = max.map_or(high, i128::from).min(high).max(low);
+    rng.gen_range(low..=high) as u64 & int.mask()
+}
+
+fn interesting_int(int: IntegerType, rng: &mut StdRng) -> u64 {
+    let mask = int.mask();
+    let values = [
+        0,
+        1,
+        mask,
+        mask >> 1,
+        (mask >> 1) + 1,
+        0x7F,
+        0x80,
+        0xFF,
+        0x100,
+        0x7FFF,
+        0x8000,
+        0xFFFF,
+        0x1_0000,
+    ];
+    values[rng.gen_range(0..values.len())] & mask
+}
+
+fn int_bytes(value: u64, int: IntegerType, endian: Endianness) -> Vec<u8> {
+    let width = int.width();
+    match endian {
+        Endianness::Little => value.to_le_bytes()[..width].to_vec(),
+        Endianness::Big => value.to_be_bytes()[8 - width..].to_vec(),
+    }
+}
+
+fn read_int(data: &[u8], int: IntegerType, endian: Endianness) -> Option<u64> {
+    let bytes = data.get(..int.width())?;
+    let mut buf = [0; 8];
+    Some(match endian {
+        Endianness::Little => {
+            buf[..bytes.len()].copy_from_slice(bytes);
+            u64::from_le_bytes(buf)
+        }
+        Endianness::Big => {
+            buf[8 - bytes.len()..].copy_from_slice(bytes);
+            u64::from_be_bytes(buf)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn packet() -> BinaryTemplate {
+        let template: BinaryTemplate = serde_json::from_str(
+            r#"{
+                "name": "packet",
+                "fields": [
+                    {"name": "magic", "type": "magic", "value": [80, 75]},
+                    {"name": "kind", "type": "enum", "int": "u16", "endian": "big", "values": [1, 2, 7]},
+                    {"name": "len", "type": "int", "int": "u8", "length_of": "payload"},
+                    {"name": "payload", "type": "blob", "prefix": "u16", "max_length": 1000},
+                    {"name": "count", "type": "int", "int": "u8", "count_of": "records"},
+                    {"name": "records", "type": "repeat", "max_count": 4, "fields": [
+                        {"name": "id", "type": "int", "int": "i32", "min": 0, "max": 1000},
+                        {"name": "size", "type": "int", "int": "u8", "count_of": "data"},
+                        {"name": "data", "type": "blob", "max_length": 300}
+                    ]},
+                    {"name": "crc", "type": "checksum", "algorithm": "crc32", "endian": "big"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        BinaryTemplate::new(&template.name, template.fields).unwrap()
+    }
+
+    #[test]
+    fn generated_instances_round_trip() {
+        let template = packet();
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let data = template.generate(&mut rng);
+            let values = template.decode(&data).unwrap();
+            assert_eq!(template.encode(&values), data);
+            let crc = ChecksumAlgorithm::Crc32.compute(&data[..data.len() - 4]);
+            assert_eq!(data[data.len() - 4..], (crc as u32).to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn blob_lengths_fit_narrow_length_fields() {
+        let template = packet();
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut values = template.generate_values(&mut rng);
+        for _ in 0..2000 {
+            template.mutate(&mut rng, &mut values);
+            let data = template.encode(&values);
+            let decoded = template.decode(&data).expect("mutated instance decodes");
+            assert_eq!(template.encode(&decoded), data);
+            // The u8 length covers the u16 prefix as well.
+            let FieldValue::Bytes(ref payload) = decoded[3] else {
+                unreachable!()
+            };
+            assert!(payload.len() <= 255 - 2);
+        }
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        let field = |name: &str, kind: FieldKind| Field {
+            name: name.to_string(),
+            kind,
+        };
+        let length = FieldKind::Int {
+            int: IntegerType::U8,
+            endian: Endianness::Little,
+            min: None,
+            max: None,
+            length_of: Some("missing".to_string()),
+            count_of: None,
+        };
+        assert!(BinaryTemplate::new("t", vec![field("len", length)]).is_err());
+        let magic = FieldKind::Magic { value: vec![1] };
+        assert!(
+            BinaryTemplate::new("t", vec![field("a", magic.clone()), field("a", magic)]).is_err()
+        );
+    }
+
+    #[test]
+    fn regions_freeze_derived_fields() {
+        let template = packet();
+        let data = template.generate(&mut StdRng::seed_from_u64(3));
+        let regions = template.regions(&data).unwrap();
+        assert_eq!(regions.kind_at(0), RegionKind::Frozen);
+        assert_eq!(regions.kind_at(2), RegionKind::Numeric);
+        assert_eq!(regions.kind_at(4), RegionKind::Frozen);
+        assert_eq!(regions.kind_at(data.len() - 1), RegionKind::Frozen);
+    }
+}
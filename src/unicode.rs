@@ -0,0 +1,252 @@
+use crate::input::SizeLimits;
+use rand::{prelude::StdRng, Rng};
+
+const BIDI_CONTROLS: [char; 12] = [
+    '\u{200E}', '\u{200F}', '\u{061C}', '\u{202A}', '\u{202B}', '\u{202C}', '\u{202D}', '\u{202E}',
+    '\u{2066}', '\u{2067}', '\u{2068}', '\u{2069}',
+];
+
+const ZERO_WIDTH: [char; 6] = [
+    '\u{200B}', '\u{200C}', '\u{200D}', '\u{2060}', '\u{FEFF}', '\u{180E}',
+];
+
+const NONCHARACTERS: [char; 5] = [
+    '\u{FFFE}',
+    '\u{FFFF}',
+    '\u{FDD0}',
+    '\u{1FFFE}',
+    '\u{10FFFF}',
+];
+
+/// Pairs of strings that are canonically or compatibly equivalent but differ byte-wise.
+const NORMALIZATION_PAIRS: [(&str, &str); 10] = [
+    ("\u{00E9}", "e\u{0301}"),
+    ("\u{00C5}", "\u{212B}"),
+    ("K", "\u{212A}"),
+    ("\u{1E9B}\u{0323}", "\u{017F}\u{0323}\u{0307}"),
+    ("\u{AC00}", "\u{1100}\u{1161}"),
+    ("fi", "\u{FB01}"),
+    ("\u{0390}", "\u{03B9}\u{0308}\u{0301}"),
+    ("ss", "\u{00DF}"),
+    ("\u{01C4}", "D\u{017D}"),
+    ("\u{2126}", "\u{03A9}"),
+];
+
+const EMOJI_SEQUENCES: [&str; 5] = [
+    "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}\u{200D}\u{1F466}",
+    "\u{1F3F3}\u{FE0F}\u{200D}\u{1F308}",
+    "\u{1F44B}\u{1F3FD}",
+    "\u{1F1FA}\u{1F1F8}",
+    "\u{0023}\u{FE0F}\u{20E3}",
+];
+
+/// Generates text built from Unicode edge cases: invalid and overlong UTF-8, WTF-8 surrogates,
+/// combining marks, bidi and zero-width controls, normalization-sensitive sequences and very
+/// long grapheme clusters.
+pub fn generate(rng: &mut StdRng, limits: &SizeLimits) -> Vec<u8> {
+    let size = limits.sample(rng);
+    let mut out = Vec::with_capacity(size + 16);
+    while out.len() < size {
+        push_segment(rng, &mut out);
+    }
+    out.truncate(size.max(limits.min));
+    out
+}
+
+/// Applies one Unicode-aware mutation, keeping the rest of the text intact.
+pub fn mutate(rng: &mut StdRng, data: &mut Vec<u8>) {
+    let at = char_boundary(data, rng.gen_range(0..=data.len()));
+    match rng.gen_range(0..7) {
+        0 => {
+            let mut segment = Vec::new();
+            push_segment(rng, &mut segment);
+            data.splice(at..at, segment);
+        }
+        1 => {
+            // Re-encode an ASCII character as an overlong sequence.
+            if let Some(pos) = find_ascii(data, at) {
+                let overlong = overlong(data[pos], rng.gen_range(2..=4));
+                data.splice(pos..pos + 1, overlong);
+            }
+        }
+        2 => {
+            // Cut a multi-byte sequence short.
+            if let Some(pos) = (at..data.len()).find(|&i| data[i] >= 0xC0) {
+                let len = sequence_len(data[pos]).min(data.len() - pos);
+                if len > 1 {
+                    data.remove(pos + rng.gen_range(1..len));
+                }
+            }
+        }
+        3 => {
+            let (composed, decomposed) =
+                NORMALIZATION_PAIRS[rng.gen_range(0..NORMALIZATION_PAIRS.len())];
+            let replacement = if rng.gen_bool(0.5) {
+                composed
+            } else {
+                decomposed
+            };
+            let end = char_boundary(data, (at + 1).min(data.len()));
+            data.splice(at..end, replacement.bytes());
+        }
+        4 => {
+            let mark = char::from_u32(rng.gen_range(0x300..0x370)).unwrap_or('\u{0301}');
+            let count = rng.gen_range(1..64);
+            let marks: String = std::iter::repeat_n(mark, count).collect();
+            data.splice(at..at, marks.into_bytes());
+        }
+        5 => {
+            let control = if rng.gen_bool(0.5) {
+                BIDI_CONTROLS[rng.gen_range(0..BIDI_CONTROLS.len())]
+            } else {
+                ZERO_WIDTH[rng.gen_range(0..ZERO_WIDTH.len())]
+            };
+            let mut buf = [0; 4];
+            data.splice(at..at, control.encode_utf8(&mut buf).bytes());
+        }
+        _ => {
+            // Split a supplementary character into a WTF-8 surrogate pair.
+            if let Some((pos, c)) = std::str::from_utf8(&data[at..])
+                .ok()
+                .and_then(|text| text.char_indices().find(|(_, c)| c.len_utf8() == 4))
+            {
+                let start = at + pos;
+                let mut units = [0; 2];
+                let mut encoded = Vec::with_capacity(6);
+                for unit in c.encode_utf16(&mut units) {
+                    encoded.extend_from_slice(&surrogate(*unit));
+                }
+                data.splice(start..start + 4, encoded);
+            } else {
+                data.splice(at..at, surrogate(rng.gen_range(0xD800..=0xDFFF)));
+            }
+        }
+    }
+}
+
+fn push_segment(rng: &mut StdRng, out: &mut Vec<u8>) {
+    let mut buf = [0; 4];
+    match rng.gen_range(0..12) {
+        0 => {
+            // Invalid bytes: stray continuations, truncated sequences and impossible leads.
+            match rng.gen_range(0..3) {
+                0 => out.push(rng.gen_range(0x80..=0xBF)),
+                1 => {
+                    let lead = [0xC3, 0xE2, 0xF0][rng.gen_range(0..3)];
+                    out.push(lead);
+                    if lead != 0xC3 {
+                        out.push(0x82);
+                    }
+                }
+                _ => out.push([0xC0, 0xC1, 0xF5, 0xF8, 0xFC, 0xFE, 0xFF][rng.gen_range(0..7)]),
+            }
+        }
+        1 => {
+            let c = [b'/', b'.', b'\\', b'<', b'\'', 0][rng.gen_range(0..6)];
+            out.extend(overlong(c, rng.gen_range(2..=4)));
+        }
+        2 => out.extend_from_slice(&surrogate(rng.gen_range(0xD800..=0xDFFF))),
+        3 => {
+            let base = rng.gen_range(b'a'..=b'z');
+            out.push(base);
+            for _ in 0..rng.gen_range(1..8) {
+                let mark = char::from_u32(rng.gen_range(0x300..0x370)).unwrap_or('\u{0301}');
+                out.extend_from_slice(mark.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+        4 => {
+            let c = BIDI_CONTROLS[rng.gen_range(0..BIDI_CONTROLS.len())];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+        5 => {
+            let c = ZERO_WIDTH[rng.gen_range(0..ZERO_WIDTH.len())];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+        6 => {
+            let (composed, decomposed) =
+                NORMALIZATION_PAIRS[rng.gen_range(0..NORMALIZATION_PAIRS.len())];
+            out.extend_from_slice(if rng.gen_bool(0.5) {
+                composed.as_bytes()
+            } else {
+                decomposed.as_bytes()
+            });
+        }
+        7 => {
+            // A single grapheme cluster hundreds of code points long.
+            out.push(rng.gen_range(b'A'..=b'Z'));
+            for _ in 0..rng.gen_range(64..512) {
+                let mark = char::from_u32(rng.gen_range(0x300..0x370)).unwrap_or('\u{0301}');
+                out.extend_from_slice(mark.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+        8 => {
+            let sequence = EMOJI_SEQUENCES[rng.gen_range(0..EMOJI_SEQUENCES.len())];
+            for _ in 0..rng.gen_range(1..4) {
+                out.extend_from_slice(sequence.as_bytes());
+            }
+            if rng.gen_bool(0.3) {
+                // Emoji joined by ZWJ into one long cluster.
+                for _ in 0..rng.gen_range(8..64) {
+                    out.extend_from_slice("\u{200D}\u{1F468}".as_bytes());
+                }
+            }
+        }
+        9 => {
+            let c = NONCHARACTERS[rng.gen_range(0..NONCHARACTERS.len())];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+        _ => {
+            for _ in 0..rng.gen_range(1..16) {
+                let c = match rng.gen_range(0..4) {
+                    0 => rng.gen_range(0x20u32..0x7F),
+                    1 => rng.gen_range(0x400..0x500),
+                    2 => rng.gen_range(0x4E00..0x9FFF),
+                    _ => rng.gen_range(0x1F300..0x1F600),
+                };
+                let c = char::from_u32(c).unwrap_or('?');
+                out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+}
+
+/// Encodes an ASCII byte in `width` bytes, which strict decoders must reject.
+fn overlong(byte: u8, width: usize) -> Vec<u8> {
+    let c = u32::from(byte & 0x7F);
+    match width {
+        2 => vec![0xC0 | (c >> 6) as u8, 0x80 | (c & 0x3F) as u8],
+        3 => vec![0xE0, 0x80 | (c >> 6) as u8, 0x80 | (c & 0x3F) as u8],
+        _ => vec![0xF0, 0x80, 0x80 | (c >> 6) as u8, 0x80 | (c & 0x3F) as u8],
+    }
+}
+
+/// Generalized UTF-8 encoding of a surrogate code unit, as used by WTF-8 and CESU-8.
+const fn surrogate(unit: u16) -> [u8; 3] {
+    [
+        0xE0 | (unit >> 12) as u8,
+        0x80 | ((unit >> 6) & 0x3F) as u8,
+        0x80 | (unit & 0x3F) as u8,
+    ]
+}
+
+const fn sequence_len(lead: u8) -> usize {
+    match lead {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    }
+}
+
+fn char_boundary(data: &[u8], mut index: usize) -> usize {
+    while index < data.len() && (data[index] & 0xC0) == 0x80 {
+        index += 1;
+    }
+    index
+}
+
+fn find_ascii(data: &[u8], from: usize) -> Option<usize> {
+    (from..data.len())
+        .chain(0..from)
+        .find(|&i| data[i].is_ascii())
+}
@@ -1,4 +1,7 @@
+use crate::allocator::AllocationStats;
+use rand::{prelude::StdRng, Rng};
 use serde::{Deserialize, Serialize};
+use std::any::Any;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum InputFormat {
@@ -10,15 +13,15 @@ pub enum InputFormat {
     Protobuf,
     Custom(String),
 
-    Format1,
-    Format2,
-    Format3,
-    Format4,
-    Format5,
-    Format6,
-    Format7,
-    Format8,
-    Format9,
+    Unicode,
+    Http,
+    Sql,
+    Regex(String),
+    Template,
+    MessagePack,
+    Cbor,
+    Bincode,
+    NGram,
     Format10,
     Format11,
     Format12,
@@ -38,8 +41,8 @@ pub enum FuzzMode {
     Hybrid,
     Custom(String),
 
-    Mode1,
-    Mode2,
+    Sequence,
+    Protocol,
     Mode3,
     Mode4,
     Mode5,
@@ -58,16 +61,53 @@ pub enum FuzzMode {
     Mode18,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SizeDistribution {
+    Uniform,
+    LogUniform,
+    BoundaryBiased,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextAlphabet {
+    PrintableAscii,
+    Ascii,
+    Alphanumeric,
+    Unicode,
+    Custom(Vec<char>),
+}
+
+impl TextAlphabet {
+    /// True for a custom alphabet without characters, which no text can be drawn from.
+    pub fn is_empty(&self) -> bool {
+        matches!(self, TextAlphabet::Custom(chars) if chars.is_empty())
+    }
+}
+
+/// The outcome of running a target on an input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Execution {
+    pub result: ExecutionResult,
+    /// Heap usage during the execution, with the `tracking-allocator` feature.
+    pub allocations: Option<AllocationStats>,
+}
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ExecutionResult {
     Success,
     Crash(String),
     Timeout,
     Error(String),
-    Result1,
-    Result2,
-    Result3,
-    Result4,
+    /// Implementations of a differential target disagreed; holds the target's name.
+    Divergence(String),
+    /// A property of a property target did not hold; holds the property's name.
+    PropertyViolation(String),
+    /// An allocation was refused because it would have exceeded a memory limit; holds its
+    /// size in bytes.
+    OutOfMemory(usize),
+    /// Allocations made by the target were still live after two runs on the input; holds the
+    /// bytes leaked by the second.
+    Leak(usize),
     Result5,
     Result6,
     Result7,
@@ -83,3 +123,68 @@ pub enum ExecutionResult {
     Result17,
     Result18,
 }
+
+/// Mostly printable ASCII with some multi-byte characters, at most `len` bytes long.
+pub(crate) fn random_text(rng: &mut StdRng, len: usize) -> Vec<u8> {
+    let mut text = String::with_capacity(len);
+    while text.len() < len {
+        let c = if rng.gen_bool(0.9) {
+            rng.gen_range(0x20u8..0x7F) as char
+        } else {
+            char::from_u32(rng.gen_range(0x80..0x3000)).unwrap_or('?')
+        };
+        if text.len() + c.len_utf8() > len {
+            break;
+        }
+        text.push(c);
+    }
+    text.into_bytes()
+}
+
+/// The message a panic was raised with, if it has one.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "unknown panic payload".to_string(),
+        },
+    }
+}
+
+/// An error message with every word that contains a digit masked, so that addresses, counters
+/// and ids do not make the same failure look different.
+pub(crate) fn error_signature(error: &str) -> String {
+    let mut signature = String::with_capacity(error.len());
+    let mut word = String::new();
+    for c in error.chars().chain([' ']) {
+        if c.is_alphanumeric() {
+            word.push(c);
+            continue;
+        }
+        if word.chars().any(|c| c.is_ascii_digit()) {
+            signature.push('#');
+        } else {
+            signature.push_str(&word);
+        }
+        word.clear();
+        signature.push(c);
+    }
+    signature.pop();
+    signature
+}
+
+pub(crate) fn special_float(rng: &mut StdRng) -> f64 {
+    [
+        0.0,
+        -0.0,
+        f64::NAN,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::MIN_POSITIVE,
+        f64::EPSILON,
+        f64::MAX,
+        1e-310,
+        rng.gen_range(-1e6..1e6),
+    ][rng.gen_range(0..10)]
+}
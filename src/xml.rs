@@ -0,0 +1,338 @@
+use rand::{prelude::StdRng, Rng};
+
+const PREDEFINED_ENTITIES: [&str; 5] = ["amp", "lt", "gt", "quot", "apos"];
+const ENCODINGS: [&str; 5] = ["UTF-8", "utf-8", "ISO-8859-1", "US-ASCII", "UTF-16"];
+
+#[derive(Debug, Clone)]
+pub struct XmlOptions {
+    pub max_depth: usize,
+    pub max_children: usize,
+    pub max_attributes: usize,
+    pub max_text_length: usize,
+    pub namespace_rate: f64,
+    pub cdata_rate: f64,
+    pub comment_rate: f64,
+    pub processing_instruction_rate: f64,
+    pub reference_rate: f64,
+    pub doctype_rate: f64,
+    pub encoding_declaration_rate: f64,
+    /// Upper bound on the fully expanded size of the internal entities declared in a DOCTYPE.
+    pub max_entity_expansion: usize,
+}
+
+impl Default for XmlOptions {
+    fn default() -> Self {
+        XmlOptions {
+            max_depth: 6,
+            max_children: 4,
+            max_attributes: 3,
+            max_text_length: 32,
+            namespace_rate: 0.2,
+            cdata_rate: 0.1,
+            comment_rate: 0.1,
+            processing_instruction_rate: 0.05,
+            reference_rate: 0.1,
+            doctype_rate: 0.2,
+            encoding_declaration_rate: 0.5,
+            max_entity_expansion: 64 * 1024,
+        }
+    }
+}
+
+pub struct XmlGenerator<'a> {
+    options: &'a XmlOptions,
+    rng: &'a mut StdRng,
+    entities: Vec<String>,
+    namespaces: Vec<String>,
+    out: String,
+}
+
+impl<'a> XmlGenerator<'a> {
+    pub fn new(options: &'a XmlOptions, rng: &'a mut StdRng) -> Self {
+        XmlGenerator {
+            options,
+            rng,
+            entities: Vec::new(),
+            namespaces: Vec::new(),
+            out: String::new(),
+        }
+    }
+
+    pub fn generate(mut self) -> Vec<u8> {
+        let encoding = if self.rng.gen_bool(self.options.encoding_declaration_rate) {
+            let encoding = ENCODINGS[self.rng.gen_range(0..ENCODINGS.len())];
+            self.out.push_str(&format!(
+                "<?xml version=\"1.0\" encoding=\"{}\"?>\n",
+                encoding
+            ));
+            Some(encoding)
+        } else {
+            None
+        };
+
+        let root = self.name();
+        if self.rng.gen_bool(self.options.doctype_rate) {
+            self.doctype(&root);
+        }
+        self.misc();
+        self.element(&root, 0);
+        self.misc();
+
+        match encoding {
+            // Occasionally leave a UTF-16 declaration on a UTF-8 body to exercise mismatches.
+            Some("UTF-16") if self.rng.gen_bool(0.9) => {
+                let mut bytes = vec![0xff, 0xfe];
+                for unit in self.out.encode_utf16() {
+                    bytes.extend_from_slice(&unit.to_le_bytes());
+                }
+                bytes
+            }
+            _ => self.out.into_bytes(),
+        }
+    }
+
+    fn doctype(&mut self, root: &str) {
+        self.out.push_str(&format!("<!DOCTYPE {} [\n", root));
+        self.out.push_str(&format!("  <!ELEMENT {} ANY>\n", root));
+
+        let plain = self.name();
+        let value = self.text(self.options.max_text_length);
+        self.out.push_str(&format!(
+            "  <!ENTITY {} \"{}\">\n",
+            plain,
+            escape_attribute(&value)
+        ));
+        self.entities.push(plain);
+
+        // Nested entities in the billion-laughs style: each level references the previous
+        // one `fan_out` times, with the depth chosen so the full expansion stays under the cap.
+        let fan_out = self.rng.gen_range(2..=10usize);
+        let mut expanded = 3usize;
+        let mut previous = "lol0".to_string();
+        self.out.push_str("  <!ENTITY lol0 \"lol\">\n");
+        let mut level = 1;
+        while expanded.saturating_mul(fan_out) <= self.options.max_entity_expansion
+            && self.rng.gen_bool(0.8)
+        {
+            let name = format!("lol{}", level);
+            let body = format!("&{};", previous).repeat(fan_out);
+            self.out
+                .push_str(&format!("  <!ENTITY {} \"{}\">\n", name, body));
+            expanded *= fan_out;
+            previous = name;
+            level += 1;
+        }
+        self.entities.push(previous);
+        self.out.push_str("]>\n");
+    }
+
+    fn misc(&mut self) {
+        if self.rng.gen_bool(self.options.comment_rate) {
+            self.comment();
+        }
+        if self.rng.gen_bool(self.options.processing_instruction_rate) {
+            self.processing_instruction();
+        }
+    }
+
+    fn element(&mut self, name: &str, depth: usize) {
+        let declared = if self.rng.gen_bool(self.options.namespace_rate) {
+            let prefix = self.name();
+            let uri = format!("http://example.com/ns/{}", self.rng.gen::<u16>());
+            self.namespaces.push(prefix.clone());
+            Some((prefix, uri))
+        } else {
+            None
+        };
+
+        let qualified = match declared {
+            Some((ref prefix, _)) => format!("{}:{}", prefix, name),
+            None => self.qualified(name.to_string()),
+        };
+
+        self.out.push('<');
+        self.out.push_str(&qualified);
+        if let Some((ref prefix, ref uri)) = declared {
+            self.out.push_str(&format!(" xmlns:{}=\"{}\"", prefix, uri));
+        } else if self.rng.gen_bool(self.options.namespace_rate / 2.0) {
+            self.out.push_str(" xmlns=\"http://example.com/default\"");
+        }
+
+        let attributes = self.rng.gen_range(0..=self.options.max_attributes);
+        let mut used = Vec::with_capacity(attributes);
+        for _ in 0..attributes {
+            let attribute = self.name();
+            let attribute = self.qualified(attribute);
+            if used.contains(&attribute) {
+                continue;
+            }
+            let value = self.text(self.options.max_text_length);
+            self.out.push(' ');
+            self.out.push_str(&attribute);
+            self.out.push_str("=\"");
+            self.out.push_str(&escape_attribute(&value));
+            self.reference();
+            self.out.push('"');
+            used.push(attribute);
+        }
+
+        let children = if depth >= self.options.max_depth {
+            0
+        } else {
+            self.rng.gen_range(0..=self.options.max_children)
+        };
+        if children == 0 && self.rng.gen_bool(0.3) {
+            self.out.push_str("/>");
+        } else {
+            self.out.push('>');
+            for _ in 0..children {
+                self.content(depth);
+            }
+            if children == 0 {
+                let text = self.text(self.options.max_text_length);
+                self.out.push_str(&escape_text(&text));
+            }
+            self.out.push_str("</");
+            self.out.push_str(&qualified);
+            self.out.push('>');
+        }
+
+        if declared.is_some() {
+            self.namespaces.pop();
+        }
+    }
+
+    fn content(&mut self, depth: usize) {
+        let roll: f64 = self.rng.gen();
+        let cdata = self.options.cdata_rate;
+        let comment = cdata + self.options.comment_rate;
+        let instruction = comment + self.options.processing_instruction_rate;
+        let reference = instruction + self.options.reference_rate;
+        if roll < cdata {
+            let text = self.text(self.options.max_text_length);
+            // `]]>` cannot appear inside CDATA, so split it across two sections.
+            self.out.push_str(&format!(
+                "<![CDATA[{}]]>",
+                text.replace("]]>", "]]]]><![CDATA[>")
+            ));
+        } else if roll < comment {
+            self.comment();
+        } else if roll < instruction {
+            self.processing_instruction();
+        } else if roll < reference {
+            self.reference();
+        } else if self.rng.gen_bool(0.6) {
+            let child = self.name();
+            self.element(&child, depth + 1);
+        } else {
+            let text = self.text(self.options.max_text_length);
+            self.out.push_str(&escape_text(&text));
+        }
+    }
+
+    fn comment(&mut self) {
+        let mut text = self.text(self.options.max_text_length);
+        while text.contains("--") {
+            text = text.replace("--", "- -");
+        }
+        self.out
+            .push_str(&format!("<!--{}-->", text.trim_end_matches('-')));
+    }
+
+    fn processing_instruction(&mut self) {
+        let mut target = self.name();
+        if target.eq_ignore_ascii_case("xml") {
+            target.push('-');
+        }
+        let data = self.text(self.options.max_text_length);
+        self.out
+            .push_str(&format!("<?{} {}?>", target, data.replace("?>", "? >")));
+    }
+
+    fn reference(&mut self) {
+        if !self.rng.gen_bool(self.options.reference_rate) {
+            return;
+        }
+        match self.rng.gen_range(0..4) {
+            0 => {
+                let entity = PREDEFINED_ENTITIES[self.rng.gen_range(0..PREDEFINED_ENTITIES.len())];
+                self.out.push_str(&format!("&{};", entity));
+            }
+            1 => {
+                let code = self.char_code();
+                self.out.push_str(&format!("&#{};", code));
+            }
+            2 => {
+                let code = self.char_code();
+                self.out.push_str(&format!("&#x{:X};", code));
+            }
+            _ => match self.entities.len() {
+                0 => self.out.push_str("&amp;"),
+                n => {
+                    let entity = self.entities[self.rng.gen_range(0..n)].clone();
+                    self.out.push_str(&format!("&{};", entity));
+                }
+            },
+        }
+    }
+
+    fn char_code(&mut self) -> u32 {
+        match self.rng.gen_range(0..4) {
+            0 => self.rng.gen_range(0x20..0x7f),
+            1 => self.rng.gen_range(0xa0..0xd800),
+            2 => self.rng.gen_range(0x10000..0x110000),
+            // Mostly invalid: C0 controls, surrogates and non-characters.
+            _ => [0x0, 0x1, 0x8, 0xb, 0xd800, 0xdfff, 0xfffe, 0xffff, 0x110000]
+                [self.rng.gen_range(0..9)],
+        }
+    }
+
+    fn qualified(&mut self, name: String) -> String {
+        if !self.namespaces.is_empty() && self.rng.gen_bool(0.5) {
+            let prefix = &self.namespaces[self.rng.gen_range(0..self.namespaces.len())];
+            format!("{}:{}", prefix, name)
+        } else {
+            name
+        }
+    }
+
+    fn name(&mut self) -> String {
+        const START: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_";
+        const REST: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_.-";
+        let len = self.rng.gen_range(0..8);
+        let mut name = String::with_capacity(len + 1);
+        name.push(START[self.rng.gen_range(0..START.len())] as char);
+        for _ in 0..len {
+            name.push(REST[self.rng.gen_range(0..REST.len())] as char);
+        }
+        if self.rng.gen_bool(0.05) {
+            name.push(['é', 'ß', 'λ', 'ж', '中'][self.rng.gen_range(0..5)]);
+        }
+        name
+    }
+
+    fn text(&mut self, max_len: usize) -> String {
+        let len = self.rng.gen_range(0..=max_len);
+        (0..len)
+            .map(|_| {
+                if self.rng.gen_bool(0.9) {
+                    self.rng.gen_range(0x20u8..0x7f) as char
+                } else {
+                    self.rng.gen::<char>()
+                }
+            })
+            .collect()
+    }
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn escape_attribute(text: &str) -> String {
+    escape_text(text)
+        .replace('"', "&quot;")
+        .replace('%', "&#37;")
+}
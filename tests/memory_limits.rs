@@ -0,0 +1,85 @@
+use fuzzer::allocator::MemoryOptions;
+use fuzzer::errors::FuzzerError;
+use fuzzer::target::TargetFunction;
+use fuzzer::utils::{FuzzMode, InputFormat};
+use fuzzer::{Fuzzer, FuzzerConfig};
+use std::time::Duration;
+
+/// An allocation refused while another target runs concurrently is reported against the
+/// target that made it.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn out_of_memory_is_attributed_to_the_allocating_target() {
+    let innocent = TargetFunction::new_async("innocent", |_input: &[u8]| {
+        Box::pin(async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(())
+        })
+    });
+    // Finishes after "innocent", which must not take the refusal.
+    let hog = TargetFunction::new_async("hog", |_input: &[u8]| {
+        Box::pin(async {
+            let mut buffer: Vec<u8> = Vec::new();
+            let reserved = buffer.try_reserve(512 << 20);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            reserved.map_err(|e| FuzzerError::ExecutionError(e.to_string()))
+        })
+    });
+    let config = FuzzerConfig::builder()
+        .input_format(InputFormat::Binary)
+        .fuzz_mode(FuzzMode::Generation)
+        .timeout(Duration::from_secs(2))
+        .max_iterations(3)
+        .seed(1)
+        .stats_interval(1000)
+        .save_crashes(false)
+        .memory_options(MemoryOptions {
+            max_allocation_bytes: Some(64 << 20),
+            max_heap_bytes: None,
+        })
+        .build();
+    let mut fuzzer = Fuzzer::new(config);
+    fuzzer.add_target(innocent);
+    fuzzer.add_target(hog);
+    fuzzer.run().await.unwrap();
+
+    let targets: Vec<&str> = fuzzer
+        .out_of_memory()
+        .iter()
+        .map(|oom| oom.target.as_str())
+        .collect();
+    assert_eq!(targets, ["hog", "hog", "hog"]);
+    let stats = fuzzer.stats();
+    assert_eq!(stats.targets["innocent"].out_of_memory, 0);
+    assert_eq!(stats.targets["innocent"].successful_runs, 3);
+    assert_eq!(stats.targets["hog"].out_of_memory, 3);
+    assert_eq!(stats.targets["hog"].crashes, 0);
+}
+
+/// Sync targets run on a blocking thread, under the limits of the execution that started them.
+#[tokio::test]
+async fn limits_apply_to_sync_targets() {
+    let hog = TargetFunction::new_sync("hog", |_input: &[u8]| {
+        let mut buffer: Vec<u8> = Vec::new();
+        buffer
+            .try_reserve(512 << 20)
+            .map_err(|e| FuzzerError::ExecutionError(e.to_string()))
+    });
+    let config = FuzzerConfig::builder()
+        .input_format(InputFormat::Binary)
+        .fuzz_mode(FuzzMode::Generation)
+        .max_iterations(2)
+        .seed(1)
+        .stats_interval(1000)
+        .save_crashes(false)
+        .memory_options(MemoryOptions {
+            max_allocation_bytes: Some(64 << 20),
+            max_heap_bytes: None,
+        })
+        .build();
+    let mut fuzzer = Fuzzer::new(config);
+    fuzzer.add_target(hog);
+    fuzzer.run().await.unwrap();
+
+    assert_eq!(fuzzer.out_of_memory().len(), 2);
+    assert_eq!(fuzzer.stats().targets["hog"].crashes, 0);
+}
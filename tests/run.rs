@@ -0,0 +1,188 @@
+use fuzzer::errors::FuzzerError;
+use fuzzer::input::SizeLimits;
+use fuzzer::target::TargetFunction;
+use fuzzer::utils::{FuzzMode, InputFormat, TextAlphabet};
+use fuzzer::{Fuzzer, FuzzerConfig};
+use rand::rngs::StdRng;
+use std::time::Duration;
+
+fn config(format: InputFormat) -> FuzzerConfig {
+    FuzzerConfig::builder()
+        .input_format(format)
+        .fuzz_mode(FuzzMode::Generation)
+        .max_iterations(1000)
+        .seed(1)
+        .stats_interval(1_000_000)
+        .build()
+}
+
+fn fuzzer(config: FuzzerConfig) -> Fuzzer {
+    let mut fuzzer = Fuzzer::new(config);
+    fuzzer.add_target(TargetFunction::new_sync("noop", |_input: &[u8]| Ok(())));
+    fuzzer
+}
+
+/// Runs the fuzzer, failing the test if `run` does not return.
+async fn run(fuzzer: &mut Fuzzer) -> Result<(), FuzzerError> {
+    tokio::time::timeout(Duration::from_secs(10), fuzzer.run())
+        .await
+        .expect("run did not return")
+}
+
+#[tokio::test]
+async fn failing_generator_aborts_the_run() {
+    let mut fuzzer = fuzzer(config(InputFormat::Custom("broken".to_string())));
+    fuzzer.register_generator("broken", |_rng: &mut StdRng, _limits: &SizeLimits| {
+        Err(FuzzerError::InputGenerationError("broken".to_string()))
+    });
+    let result = run(&mut fuzzer).await;
+    assert!(matches!(result, Err(FuzzerError::InputGenerationError(ref e)) if e == "broken"));
+    assert_eq!(fuzzer.stats().total_runs, 0);
+}
+
+#[tokio::test]
+async fn occasional_generation_failures_are_skipped() {
+    let mut fuzzer = fuzzer(config(InputFormat::Custom("flaky".to_string())));
+    fuzzer.register_generator("flaky", |rng: &mut StdRng, _limits: &SizeLimits| {
+        use rand::Rng;
+        if rng.gen_bool(0.5) {
+            Ok(vec![0])
+        } else {
+            Err(FuzzerError::InputGenerationError("flaky".to_string()))
+        }
+    });
+    run(&mut fuzzer).await.unwrap();
+    let stats = fuzzer.stats();
+    assert!(stats.total_runs > 0 && stats.total_runs < 1000);
+}
+
+#[tokio::test]
+async fn protobuf_without_schema_is_rejected() {
+    let mut fuzzer = fuzzer(config(InputFormat::Protobuf));
+    assert!(run(&mut fuzzer).await.is_err());
+}
+
+#[tokio::test]
+async fn empty_text_alphabet_is_rejected() {
+    let mut config = config(InputFormat::Text);
+    config.text_alphabet = TextAlphabet::Custom(Vec::new());
+    let mut fuzzer = fuzzer(config);
+    assert!(run(&mut fuzzer).await.is_err());
+    assert_eq!(fuzzer.stats().total_runs, 0);
+}
+
+#[tokio::test]
+async fn template_format_without_template_is_rejected() {
+    let mut fuzzer = fuzzer(config(InputFormat::Template));
+    assert!(run(&mut fuzzer).await.is_err());
+}
+
+#[tokio::test]
+async fn corpus_entries_are_mutated_within_their_regions() {
+    use fuzzer::regions::{RegionKind, RegionMap};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let mut config = config(InputFormat::Binary);
+    config.fuzz_mode = FuzzMode::Mutation;
+    config.use_corpus = true;
+    config.corpus_sampling_rate = 1.0;
+    config.max_iterations = 200;
+    let mut fuzzer = Fuzzer::new(config);
+    let mut entry = b"FUZZ".to_vec();
+    entry.extend(0..32);
+    fuzzer
+        .mutator
+        .add_to_corpus_with_regions(entry, RegionMap::new().with(0..4, RegionKind::Frozen));
+    let seen = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&seen);
+    fuzzer.add_target(TargetFunction::new_sync("header", move |input: &[u8]| {
+        counter.fetch_add(1, Ordering::Relaxed);
+        if input.starts_with(b"FUZZ") {
+            Ok(())
+        } else {
+            Err(FuzzerError::ExecutionError("header changed".to_string()))
+        }
+    }));
+    run(&mut fuzzer).await.unwrap();
+    assert_eq!(seen.load(Ordering::Relaxed), 200);
+    assert_eq!(fuzzer.stats().total_crashes, 0);
+}
+
+#[tokio::test]
+async fn hooks_run_in_sequence_mode() {
+    use fuzzer::sequence::SequenceTarget;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let mut config = config(InputFormat::Binary);
+    config.fuzz_mode = FuzzMode::Sequence;
+    config.max_iterations = 10;
+    let mut fuzzer = Fuzzer::new(config);
+    let calls = Arc::new(AtomicUsize::new(0));
+    let (setup, teardown) = (Arc::clone(&calls), Arc::clone(&calls));
+    fuzzer.add_target(
+        TargetFunction::new_sync("noop", |_input: &[u8]| Ok(()))
+            .with_setup(move || {
+                setup.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+            .with_teardown(move || {
+                teardown.fetch_add(10, Ordering::Relaxed);
+                Ok(())
+            }),
+    );
+    fuzzer.add_sequence_target(SequenceTarget::new("counter", || 0u32).operation(
+        "add",
+        |n: &mut u32, _: &[u8]| {
+            *n += 1;
+            Ok(())
+        },
+    ));
+    run(&mut fuzzer).await.unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 11);
+}
+
+#[tokio::test]
+async fn failed_setup_tears_down_earlier_targets() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let mut fuzzer = Fuzzer::new(config(InputFormat::Binary));
+    let torn_down = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&torn_down);
+    fuzzer.add_target(
+        TargetFunction::new_sync("first", |_input: &[u8]| Ok(())).with_teardown(move || {
+            flag.store(true, Ordering::Relaxed);
+            Ok(())
+        }),
+    );
+    fuzzer.add_target(
+        TargetFunction::new_sync("second", |_input: &[u8]| Ok(()))
+            .with_setup(|| Err(FuzzerError::HookError("no fixtures".to_string())))
+            .with_teardown(|| panic!("torn down without setup")),
+    );
+    assert!(matches!(
+        run(&mut fuzzer).await,
+        Err(FuzzerError::HookError(_))
+    ));
+    assert!(torn_down.load(Ordering::Relaxed));
+    assert_eq!(fuzzer.stats().total_runs, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hanging_sync_targets_time_out() {
+    let mut config = config(InputFormat::Binary);
+    config.max_iterations = 2;
+    config.timeout = Duration::from_millis(50);
+    let mut fuzzer = Fuzzer::new(config);
+    fuzzer.add_target(TargetFunction::new_sync("hang", |_input: &[u8]| {
+        std::thread::sleep(Duration::from_secs(1));
+        Ok(())
+    }));
+    tokio::time::timeout(Duration::from_millis(500), fuzzer.run())
+        .await
+        .expect("timeout did not interrupt the target")
+        .unwrap();
+    assert_eq!(fuzzer.stats().timeouts, 2);
+}